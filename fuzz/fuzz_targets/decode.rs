@@ -0,0 +1,53 @@
+#![no_main]
+#![allow(dead_code)]
+
+// rustitor has no library, so the whole editor is compiled in from the source, with main.rs as the
+// crate root its modules refer to
+include!("../../src/main.rs");
+
+use libfuzzer_sys::fuzz_target;
+
+const TEXT: &str = "fn main() {\n\tlet s = \"caf\u{e9}\";\n    // \u{4f60}\u{597d} \u{1f600}\n}\n";
+
+// Keys which write files, run commands or open the files around, which the fuzzer must not do
+fn leaves_editor(seq: &InputSeq) -> bool {
+    matches!(
+        seq,
+        InputSeq::Key(b's' | b'o' | b'x' | b'b', true)
+            | InputSeq::AltKey(b's' | b'S' | b':')
+            | InputSeq::CtrlAltKey(b'f')
+            // A count makes the next key run as many times as it says
+            | InputSeq::AltKey(b'0'..=b'9')
+            | InputSeq::Key(b'u', true)
+    )
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut keys = InputSequences::new(data);
+    let mut decoded = vec![];
+    // Each key takes at least one byte, and once the data runs out every read is Unidentified
+    for _ in 0..=data.len() {
+        match keys.next() {
+            Some(Ok(seq)) => decoded.push(seq),
+            other => panic!("decoding {:?} gave {:?}", data, other),
+        }
+    }
+
+    // The keys are run the way Editor::run does, with prompts reading the keys after theirs
+    let mut editor = Editor::new_from_string(TEXT, (80, 24));
+    editor.settings.clipboard_provider = ClipboardProvider::Internal;
+    editor.input = decoded.into_iter().filter(|seq| !leaves_editor(seq)).map(Ok).collect::<Vec<_>>().into_iter();
+    while let Some(Ok(seq)) = editor.next_seq() {
+        if seq == InputSeq::Unidentified {
+            continue;
+        }
+        match editor.process_keypress(seq) {
+            Ok(AfterKeyPress::Continue) => {}
+            _ => break,
+        }
+        editor.setup_scroll();
+        if editor.refresh_screen().is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,200 @@
+use crate::settings::Settings;
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "\
+Usage: rustitor [OPTIONS] [FILE[:LINE[:COL]]]...
+
+Opens each FILE in its own buffer. A line and column after the name put the
+cursor there, unless a file by that whole name exists. Otherwise the cursor
+goes back to where it was when the file was last closed, as remembered in
+$XDG_DATA_HOME/rustitor/positions.toml. Without a FILE, text piped to stdin
+is opened in an unnamed buffer and keys are read from the terminal.
+
+Options:
+  -R, --readonly        Refuse to change or save the files
+      --line N          Open the next file at line N
+      --tab-stop N      Show tabs N columns wide (default 8)
+      --expand-tabs     Insert spaces when Tab is pressed
+      --ruler N         Mark the text past column N, 0 for no ruler
+      --scrolloff N     Keep N rows visible above and below the cursor
+      --sidescrolloff N Keep N columns visible left and right of the cursor
+      --no-mouse        Leave mouse clicks and scrolling to the terminal
+      --no-title        Don't show the file name in the terminal title
+      --config PATH     Read the TOML config from PATH instead of
+                        $XDG_CONFIG_HOME/rustitor/config.toml
+      --session PATH    Reopen the buffers saved in the session file PATH
+      --save-session PATH
+                        Save the open buffers to PATH when quitting
+      --diff A B        Show the files A and B side by side, marking the
+                        lines that differ
+      --headless        Edit FILE with the commands of a script instead of
+                        the terminal: goto LINE [COL], insert \"TEXT\",
+                        delete [N], save and quit, one on each line
+      --pipe            Like --headless, editing the text read from stdin
+                        and writing the result to stdout
+      --script PATH     Read the script for --headless or --pipe from PATH
+      --check-syntax    Look for strings and comments left open in each
+                        FILE, printing them and exiting with status 1
+      --benchmark       Time opening FILE, drawing 1000 screens of it,
+                        10,000 insertions and deleting half of a 10,000
+                        character line, without using the terminal
+      --benchmark-csv   Like --benchmark, printing the times as CSV
+  -h, --help            Print this help and exit
+      --version         Print the version and exit
+";
+
+// Each file comes with the line and column to open it at, if one was given
+pub type FileArg = (String, Option<(usize, usize)>);
+
+// The command line, applied over the settings from the config files
+#[derive(Clone, Default, Debug)]
+pub struct Args {
+    pub help: bool,
+    pub version: bool,
+    pub config: Option<PathBuf>,
+    pub session: Option<PathBuf>,
+    pub save_session: Option<PathBuf>,
+    pub diff: bool,
+    pub headless: bool,
+    pub pipe: bool,
+    pub script: Option<PathBuf>,
+    pub check_syntax: bool,
+    pub benchmark: bool,
+    pub benchmark_csv: bool,
+    pub files: Vec<FileArg>,
+    tab_stop: Option<usize>,
+    expand_tabs: bool,
+    scroll_off: Option<usize>,
+    side_scroll_off: Option<usize>,
+    no_title: bool,
+    no_mouse: bool,
+    read_only: bool,
+    ruler: Option<usize>,
+}
+
+fn parse_number<A: Iterator<Item = String>>(flag: &str, args: &mut A) -> Result<usize, String> {
+    let value = args.next().ok_or(format!("{} needs a value", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", flag, value))
+}
+
+fn parse_path<A: Iterator<Item = String>>(flag: &str, args: &mut A) -> Result<PathBuf, String> {
+    args.next().map(PathBuf::from).ok_or(format!("{} needs a value", flag))
+}
+
+// Splits `file:line` or `file:line:col`. A file whose name really ends like that is left alone
+fn split_position(arg: String) -> FileArg {
+    if Path::new(&arg).exists() {
+        return (arg, None);
+    }
+    let (rest, last) = match arg.rsplit_once(':') {
+        Some((rest, last)) if !rest.is_empty() => (rest, last),
+        _ => return (arg, None),
+    };
+    let last = match last.parse::<usize>() {
+        Ok(n) => n,
+        Err(_) => return (arg, None),
+    };
+    if let Some((file, line)) = rest.rsplit_once(':') {
+        if let (false, Ok(line)) = (file.is_empty(), line.parse::<usize>()) {
+            return (file.to_string(), Some((line, last)));
+        }
+    }
+    (rest.to_string(), Some((last, 1)))
+}
+
+impl Args {
+    pub fn parse() -> Result<Args, String> {
+        Args::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from<A: Iterator<Item = String>>(mut args: A) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut line = None;
+        // After --, names starting with - are files too
+        let mut options = true;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" if options => options = false,
+                "-h" | "--help" if options => parsed.help = true,
+                "--version" if options => parsed.version = true,
+                "--tab-stop" if options => {
+                    let tab_stop = parse_number(&arg, &mut args)?;
+                    if tab_stop == 0 {
+                        return Err("invalid tab stop: 0".to_string());
+                    }
+                    parsed.tab_stop = Some(tab_stop);
+                }
+                "--expand-tabs" if options => parsed.expand_tabs = true,
+                "--scrolloff" if options => parsed.scroll_off = Some(parse_number(&arg, &mut args)?),
+                "--sidescrolloff" if options => parsed.side_scroll_off = Some(parse_number(&arg, &mut args)?),
+                "--no-title" if options => parsed.no_title = true,
+                "--no-mouse" if options => parsed.no_mouse = true,
+                "--readonly" | "-R" if options => parsed.read_only = true,
+                "--ruler" if options => parsed.ruler = Some(parse_number(&arg, &mut args)?),
+                "--line" if options => line = Some(parse_number(&arg, &mut args)?),
+                "--config" if options => parsed.config = Some(parse_path(&arg, &mut args)?),
+                "--session" if options => parsed.session = Some(parse_path(&arg, &mut args)?),
+                "--save-session" if options => parsed.save_session = Some(parse_path(&arg, &mut args)?),
+                "--diff" if options => parsed.diff = true,
+                "--headless" if options => parsed.headless = true,
+                "--pipe" if options => parsed.pipe = true,
+                "--script" if options => parsed.script = Some(parse_path(&arg, &mut args)?),
+                "--check-syntax" if options => parsed.check_syntax = true,
+                "--benchmark" if options => parsed.benchmark = true,
+                "--benchmark-csv" if options => {
+                    parsed.benchmark = true;
+                    parsed.benchmark_csv = true;
+                }
+                // A lone - is left to be a file name
+                flag if options && flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(format!("unknown option '{}'", flag));
+                }
+                _ => {
+                    let (file, position) = split_position(arg);
+                    // --line applies to the file after it
+                    parsed.files.push((file, line.take().map(|line| (line, 1)).or(position)));
+                }
+            }
+        }
+        if line.is_some() {
+            return Err("--line needs a file to open".to_string());
+        }
+        if parsed.diff && parsed.files.len() != 2 {
+            return Err("--diff needs two files".to_string());
+        }
+        if parsed.headless && (parsed.script.is_none() || parsed.files.len() != 1) {
+            return Err("--headless needs a --script and one file".to_string());
+        }
+        if parsed.pipe && (parsed.script.is_none() || !parsed.files.is_empty()) {
+            return Err("--pipe needs a --script and no files".to_string());
+        }
+        if parsed.check_syntax && parsed.files.is_empty() {
+            return Err("--check-syntax needs a file".to_string());
+        }
+        if parsed.benchmark && parsed.files.len() != 1 {
+            return Err("--benchmark needs one file".to_string());
+        }
+        Ok(parsed)
+    }
+
+    pub fn apply(&self, settings: &mut Settings) {
+        if let Some(tab_stop) = self.tab_stop {
+            settings.tab_stop = tab_stop;
+        }
+        if let Some(scroll_off) = self.scroll_off {
+            settings.scroll_off = scroll_off;
+        }
+        if let Some(side_scroll_off) = self.side_scroll_off {
+            settings.side_scroll_off = side_scroll_off;
+        }
+        if let Some(ruler) = self.ruler {
+            settings.ruler_column = Some(ruler).filter(|&n| n > 0);
+        }
+        settings.expand_tabs |= self.expand_tabs;
+        settings.set_title &= !self.no_title;
+        settings.mouse_support &= !self.no_mouse;
+        settings.read_only |= self.read_only;
+    }
+}
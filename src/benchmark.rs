@@ -0,0 +1,123 @@
+use crate::args::Args;
+use crate::row::Row;
+use crate::settings::Settings;
+use crate::Editor;
+use std::io;
+use std::iter;
+use std::time::{Duration, Instant};
+
+const FRAMES: usize = 1000;
+const INSERTS: usize = 10_000;
+const DELETE_ROW: usize = 10_000;
+
+// What --benchmark measures, for comparing one build against another
+pub struct Results {
+    pub open: Duration,
+    pub frames_per_sec: f64,
+    pub mb_per_sec: f64,
+    pub inserts: Duration,
+    // The middle half of a long row deleted a grapheme at a time, and in one step
+    pub delete_chars: Duration,
+    pub delete_range: Duration,
+}
+
+// xorshift, seeded the same on every run so that runs insert at the same places
+struct Positions(u64);
+
+impl Positions {
+    fn next(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+fn secs(d: Duration) -> f64 {
+    d.as_secs_f64().max(1e-9)
+}
+
+// Opens `file` on an 80x24 screen which is never written to the terminal
+pub fn run(file: &str, args: &Args, settings: Settings) -> io::Result<Results> {
+    let mut editor = Editor::new(Some((80, 24)), iter::empty(), settings);
+    editor.command_line = args.clone();
+    let start = Instant::now();
+    editor.open_file(file)?;
+    let open = start.elapsed();
+    editor.buffer_mut().load_all()?;
+
+    // A page further down for each frame, every row of it drawn as if the screen had been cleared
+    let mut buf = vec![];
+    let mut bytes = 0;
+    let start = Instant::now();
+    for i in 0..FRAMES {
+        let rows = editor.buffer().rows.len();
+        editor.buffer_mut().cy = (i * editor.screen_rows) % rows.max(1);
+        editor.setup_scroll();
+        editor.renderer.invalidate();
+        editor.draw_frame(&mut buf)?;
+        bytes += buf.len();
+        buf.clear();
+    }
+    let render = secs(start.elapsed());
+
+    let mut positions = Positions(0x2545_f491_4f6c_dd1d);
+    let start = Instant::now();
+    for _ in 0..INSERTS {
+        let buffer = editor.buffer_mut();
+        buffer.cy = positions.next(buffer.rows.len().max(1));
+        buffer.cx = positions.next(buffer.rows.get(buffer.cy).map_or(0, |row| row.grapheme_count()) + 1);
+        let ch = (b'a' + positions.next(26) as u8) as char;
+        buffer.insert_char(ch);
+    }
+    let inserts = start.elapsed();
+
+    let line: String = (0..DELETE_ROW).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+    let (from, to) = (DELETE_ROW / 4, DELETE_ROW * 3 / 4);
+    let mut row = Row::new(line.as_str());
+    let start = Instant::now();
+    for _ in from..to {
+        row.delete_char(from);
+    }
+    let delete_chars = start.elapsed();
+    let mut row = Row::new(line.as_str());
+    let start = Instant::now();
+    row.delete_range(from, to);
+    let delete_range = start.elapsed();
+
+    Ok(Results {
+        open,
+        frames_per_sec: FRAMES as f64 / render,
+        mb_per_sec: bytes as f64 / 1_000_000.0 / render,
+        inserts,
+        delete_chars,
+        delete_range,
+    })
+}
+
+impl Results {
+    pub fn print(&self, csv: bool) {
+        let open_ms = self.open.as_secs_f64() * 1000.0;
+        let inserts_ms = self.inserts.as_secs_f64() * 1000.0;
+        let (chars_ms, range_ms) = (self.delete_chars.as_secs_f64() * 1000.0, self.delete_range.as_secs_f64() * 1000.0);
+        if csv {
+            println!("open_ms,frames_per_sec,mb_per_sec,inserts_ms,delete_chars_ms,delete_range_ms");
+            println!(
+                "{:.3},{:.1},{:.2},{:.3},{:.3},{:.3}",
+                open_ms, self.frames_per_sec, self.mb_per_sec, inserts_ms, chars_ms, range_ms
+            );
+        } else {
+            println!(
+                "open: {:.2}ms, render: {:.0} frames/sec, {:.2} MB/s throughput, {} inserts: {:.2}ms",
+                open_ms, self.frames_per_sec, self.mb_per_sec, INSERTS, inserts_ms
+            );
+            println!(
+                "deleting {} of {} characters: {:.2}ms one at a time, {:.3}ms as a range",
+                DELETE_ROW / 2,
+                DELETE_ROW,
+                chars_ms,
+                range_ms
+            );
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::cmp;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use unicode_width::UnicodeWidthChar;
+
+pub const SIDEBAR_WIDTH: usize = 30;
+
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+}
+
+// Paths which start with `input`, directories ending in /. Dot files are only listed when
+// the last part of `input` starts with a dot
+pub fn complete_path(input: &str) -> Vec<String> {
+    let (dir, prefix) = match input.rfind('/') {
+        Some(i) => (&input[..=i], &input[i + 1..]),
+        None => ("", input),
+    };
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut paths: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+pub fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let end = a
+        .char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map(|((i, _), _)| i)
+        .unwrap_or_else(|| cmp::min(a.len(), b.len()));
+    &a[..end]
+}
+
+// The directory listing shown in the sidebar
+pub struct FileBrowser {
+    pub entries: Vec<DirEntry>,
+    pub selected: usize,
+    pub open: bool,
+    pub path: PathBuf,
+    // First entry on screen
+    offset: usize,
+}
+
+impl FileBrowser {
+    pub fn new(path: PathBuf) -> FileBrowser {
+        FileBrowser { entries: vec![], selected: 0, open: false, path, offset: 0 }
+    }
+
+    // Lists `path` with ../ first, then directories, then files
+    pub fn read_dir(&mut self) -> io::Result<()> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let is_dir = entry.path().is_dir();
+            entries.push(DirEntry { name: entry.file_name().to_string_lossy().into_owned(), is_dir });
+        }
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        entries.insert(0, DirEntry { name: "..".to_string(), is_dir: true });
+        self.entries = entries;
+        self.selected = 0;
+        self.offset = 0;
+        Ok(())
+    }
+
+    pub fn move_selection(&mut self, up: bool, by: usize) {
+        let last = self.entries.len().saturating_sub(1);
+        self.selected = if up { self.selected.saturating_sub(by) } else { cmp::min(self.selected + by, last) };
+    }
+
+    // Selects the entry drawn on screen row `y`
+    pub fn click(&mut self, y: usize) {
+        if self.offset + y < self.entries.len() {
+            self.selected = self.offset + y;
+        }
+    }
+
+    // Goes into the selected directory, or returns the selected file
+    pub fn enter(&mut self) -> io::Result<Option<PathBuf>> {
+        let entry = match self.entries.get(self.selected) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let path = self.path.join(&entry.name);
+        if !entry.is_dir {
+            // Named like a file given on the command line when it's under the working directory
+            let cwd = std::env::current_dir()?;
+            return Ok(Some(path.strip_prefix(&cwd).map(Path::to_path_buf).unwrap_or(path)));
+        }
+        let previous = std::mem::replace(&mut self.path, fs::canonicalize(&path).unwrap_or(path));
+        if let Err(err) = self.read_dir() {
+            self.path = previous;
+            return Err(err);
+        }
+        Ok(None)
+    }
+
+    // Keeps the selected entry within `rows` screen rows
+    pub fn scroll(&mut self, rows: usize) {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if rows > 0 && self.selected >= self.offset + rows {
+            self.offset = self.selected + 1 - rows;
+        }
+    }
+
+    // Screen row of the selected entry
+    pub fn selected_row(&self) -> usize {
+        self.selected - self.offset
+    }
+
+    // Screen row `y` of the sidebar, `width` columns wide including the border
+    pub fn draw_row<W: Write>(&self, mut buf: W, y: usize, width: usize, focused: bool) -> io::Result<()> {
+        let text_width = width.saturating_sub(1);
+        let mut text = String::new();
+        let mut used = 0;
+        if let Some(entry) = self.entries.get(self.offset + y) {
+            let suffix = if entry.is_dir { "/" } else { "" };
+            for c in entry.name.chars().chain(suffix.chars()) {
+                let c = if c.is_control() { '?' } else { c };
+                let w = c.width().unwrap_or(0);
+                if used + w > text_width {
+                    break;
+                }
+                used += w;
+                text.push(c);
+            }
+            if self.offset + y == self.selected {
+                let color: &[u8] = if focused { b"\x1b[7m" } else { b"\x1b[48;5;238m" };
+                buf.write_all(color)?;
+            }
+        }
+        text.push_str(&" ".repeat(text_width - used));
+        buf.write_all(text.as_bytes())?;
+        buf.write_all(b"\x1b[m")?;
+        if width > 0 {
+            buf.write_all("│".as_bytes())?;
+        }
+        Ok(())
+    }
+}
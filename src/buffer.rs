@@ -0,0 +1,2165 @@
+use crate::diff::{self, DiffMark};
+use crate::encoding::Encoding;
+use crate::highlight::{self, Highlight, Highlighter, LineState, PlainHighlighter};
+use crate::lockfile::LockFile;
+use crate::rope::Rope;
+use crate::row::{self, Row};
+use crate::settings::Settings;
+use std::cmp;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use unicode_segmentation::UnicodeSegmentation;
+
+pub struct FilePath {
+    pub path: PathBuf,
+    pub display: String,
+}
+
+impl FilePath {
+    pub fn from<P: AsRef<Path>>(path: P) -> FilePath {
+        let path = path.as_ref();
+        FilePath {
+            path: PathBuf::from(path),
+            display: path.to_string_lossy().to_string(),
+        }
+    }
+}
+
+// Letters, digits and underscores make up the words offered for completion
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+// Lines read from a file at a time. Only the part of a file scrolled into view is read
+const LOAD_LINES: usize = 1000;
+
+// Lines as bytes, which are decoded in the buffer's encoding
+type LazyLoader = io::Split<io::BufReader<fs::File>>;
+
+// Whether most of the lines in the start of the file end in CR LF. None when there are no line breaks
+fn detect_crlf(path: &Path) -> Option<bool> {
+    let mut head = Vec::with_capacity(1024);
+    fs::File::open(path).ok()?.take(1024).read_to_end(&mut head).ok()?;
+    let lf = head.iter().filter(|&&b| b == b'\n').count();
+    let crlf = head.windows(2).filter(|w| w == b"\r\n").count();
+    if lf == 0 {
+        None
+    } else {
+        Some(crlf * 2 > lf)
+    }
+}
+
+// Only the start of the file is looked at, like for the line endings
+fn detect_encoding(path: &Path) -> Encoding {
+    let mut head = Vec::with_capacity(64 * 1024);
+    match fs::File::open(path).and_then(|f| f.take(64 * 1024).read_to_end(&mut head)) {
+        Ok(_) => Encoding::detect(&head),
+        Err(_) => Encoding::Utf8,
+    }
+}
+
+fn open_lines(path: &Path) -> io::Result<LazyLoader> {
+    Ok(io::BufReader::new(fs::File::open(path)?).split(b'\n'))
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub struct WriteResult {
+    pub bytes: usize,
+    // The temporary file could not be renamed over the original and was copied instead
+    pub copied: bool,
+    // Characters the encoding had no byte for, written as ?
+    pub replaced: usize,
+}
+
+// A new file in `dir` with a name nobody could have known beforehand, ending in `suffix`. It is
+// never a file or link that was already there
+pub fn create_temp_file(dir: &Path, suffix: &str) -> io::Result<(PathBuf, fs::File)> {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+
+    let mut last_err = None;
+    for i in 0..16u32 {
+        let n = seed.wrapping_add(std::process::id()).wrapping_add(i) % 0x100_0000;
+        let tmp_path = dir.join(format!(".rustitor_{:06x}{}", n, suffix));
+        match fs::OpenOptions::new().write(true).create_new(true).open(&tmp_path) {
+            Ok(file) => return Ok((tmp_path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => last_err = Some(err),
+            Err(err) => return Err(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::AlreadyExists, "no temporary file name available")))
+}
+
+pub enum UndoRecord {
+    // `byte` is where `ch` went into the row. A combining mark joins the grapheme before it, so
+    // `col` can't find it again
+    InsertChar { row: usize, col: usize, byte: usize, ch: char },
+    DeleteChar { row: usize, col: usize, grapheme: String },
+    InsertLine { at: usize },
+    // `indent` is prepended to the new line
+    SplitLine { row: usize, col: usize, indent: String },
+    MergeLine { row: usize, col: usize },
+    Replace { row: usize, col: usize, old: String, new: String },
+    // Appends the next row to `row`, separated by a space when both are non-empty.
+    // `split_at` is the length of `row` before joining
+    JoinLine { row: usize, split_at: usize },
+    // `prefix` is inserted at or removed from `col`
+    ToggleComment { row: usize, col: usize, prefix: String, added: bool },
+    // Applied in order and reverted in reverse order as a single step
+    Group(Vec<UndoRecord>),
+    // Swaps `row` with the row below it
+    SwapLines { row: usize },
+    // Swaps the graphemes at `at - 1` and `at`, leaving the cursor after them
+    Transpose { row: usize, at: usize },
+    // `text` holds one entry per affected line, starting at (row, col)
+    InsertRegion { row: usize, col: usize, text: Vec<String> },
+    DeleteRegion { row: usize, col: usize, text: Vec<String> },
+}
+
+fn region_end(row: usize, col: usize, text: &[String]) -> (usize, usize) {
+    match text.len() {
+        0 => (row, col),
+        1 => (row, col + text[0].graphemes(true).count()),
+        n => (row + n - 1, text[n - 1].graphemes(true).count()),
+    }
+}
+
+// Byte ranges of non-overlapping occurrences of `query` in `text`
+pub fn match_ranges(text: &str, query: &str, ignore_case: bool) -> Vec<(usize, usize)> {
+    if query.is_empty() {
+        return vec![];
+    }
+    if !ignore_case {
+        let (mut ranges, mut from) = (vec![], 0);
+        while let Some(idx) = row::find_from(text, query, from) {
+            from = idx + query.len();
+            ranges.push((idx, from));
+        }
+        return ranges;
+    }
+
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    let mut ranges = vec![];
+    let mut next_start = 0;
+    for (start, _) in text.char_indices() {
+        if start < next_start {
+            continue;
+        }
+        let mut lower = Vec::with_capacity(query.len());
+        let mut end = start;
+        for (i, c) in text[start..].char_indices() {
+            if lower.len() >= query.len() {
+                break;
+            }
+            lower.extend(c.to_lowercase());
+            end = start + i + c.len_utf8();
+        }
+        if lower == query {
+            ranges.push((start, end));
+            next_start = end;
+        }
+    }
+    ranges
+}
+
+#[derive(Clone, Copy)]
+pub enum SearchDir {
+    Forward,
+    Backward,
+}
+
+pub enum CursorDir {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordLeft,
+    WordRight,
+    // Runs of word characters or of punctuation, each taken as a stop
+    TokenLeft,
+    TokenRight,
+    // Grow or shrink the column selection
+    ColumnLeft,
+    ColumnRight,
+    ColumnUp,
+    ColumnDown,
+}
+
+// A rectangle from the row and column it was started at to the cursor's. Columns count
+// characters, so its sides are only straight on rows without tabs or wide characters
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ColumnSelection {
+    pub anchor_row: usize,
+    pub anchor_col: usize,
+    pub end_row: usize,
+    pub end_col: usize,
+}
+
+impl ColumnSelection {
+    // Top and bottom row, and the columns from the left side up to the right one
+    pub fn rect(&self) -> (usize, usize, usize, usize) {
+        (
+            cmp::min(self.anchor_row, self.end_row),
+            cmp::max(self.anchor_row, self.end_row),
+            cmp::min(self.anchor_col, self.end_col),
+            cmp::max(self.anchor_col, self.end_col),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct BufferStats {
+    pub lines: usize,
+    pub words: usize,
+    pub chars: usize,
+    pub bytes: usize,
+}
+
+pub struct EditorBuffer {
+    pub file: Option<FilePath>,
+    // Shown instead of [No Name] for a buffer the editor filled without a file
+    pub label: Option<String>,
+
+    pub cx: usize,
+    pub cy: usize,
+
+    pub rx: usize,
+
+    pub rows: Rope,
+    // Reads the rest of the file until it's exhausted
+    loader: Option<LazyLoader>,
+    pub rowoff: usize,
+    pub coloff: usize,
+    // Screen rows of row `rowoff` scrolled above the view when lines wrap
+    pub wrapoff: usize,
+
+    // Counts every change to the text, so that others can tell whether it changed
+    pub changes: u64,
+
+    pub settings: Settings,
+    // Which the file is read in and written back in
+    pub encoding: Encoding,
+
+    // Anchor (row, col) of the selection, the other end is the cursor
+    pub selection: Option<(usize, usize)>,
+    // Block selected with Alt+Shift+arrows, which is kept apart from `selection`
+    pub column_selection: Option<ColumnSelection>,
+
+    highlighter: Box<dyn Highlighter>,
+
+    undo_stack: Vec<UndoRecord>,
+    redo_stack: Vec<UndoRecord>,
+    // Length of the undo stack when the file was last read or saved, None once that state can't be
+    // reached by undoing or redoing
+    clean_index: Option<usize>,
+
+    // Modification time of the file when it was last read or written
+    last_mtime: Option<SystemTime>,
+
+    // Lines which differ from the last commit, as of the last read or write
+    pub diff: Option<HashMap<usize, DiffMark>>,
+    // Rows marked with Ctrl-F2, which move along as rows are inserted or deleted above them
+    pub bookmarks: BTreeSet<usize>,
+    // Held while the file is open for editing, and removed with the buffer
+    pub lock: Option<LockFile>,
+}
+
+impl Default for EditorBuffer {
+    fn default() -> EditorBuffer {
+        EditorBuffer::new(Settings::default())
+    }
+}
+
+impl EditorBuffer {
+    pub fn new(settings: Settings) -> EditorBuffer {
+        EditorBuffer {
+            file: None,
+            label: None,
+            cx: 0,
+            cy: 0,
+            rx: 0,
+            rows: Rope::new(),
+            loader: None,
+            rowoff: 0,
+            coloff: 0,
+            wrapoff: 0,
+            encoding: Encoding::Utf8,
+            changes: 0,
+            settings,
+            selection: None,
+            column_selection: None,
+            highlighter: Box::new(PlainHighlighter),
+            undo_stack: vec![],
+            redo_stack: vec![],
+            clean_index: Some(0),
+            last_mtime: None,
+            diff: None,
+            bookmarks: BTreeSet::new(),
+            lock: None,
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, settings: Settings) -> io::Result<EditorBuffer> {
+        let mut buffer = EditorBuffer::new(settings);
+        buffer.loader = Some(open_lines(path.as_ref())?);
+        // Existing files keep their line endings, whatever the settings say
+        if let Some(crlf) = detect_crlf(path.as_ref()) {
+            buffer.settings.crlf = crlf;
+        }
+        buffer.encoding = detect_encoding(path.as_ref());
+        buffer.set_file(path.as_ref());
+        buffer.load_rows(LOAD_LINES)?;
+        buffer.last_mtime = modified_time(path.as_ref());
+        buffer.update_diff();
+        Ok(buffer)
+    }
+
+    // A buffer without a file, holding `lines`
+    pub fn scratch(label: &str, lines: Vec<String>, settings: Settings) -> EditorBuffer {
+        let mut buffer = EditorBuffer::new(settings);
+        buffer.label = Some(label.to_string());
+        buffer.rows = lines.into_iter().map(Row::new).collect();
+        buffer.update_rows(0, buffer.rows.len());
+        buffer
+    }
+
+    // A buffer without a file, holding `text` split into lines the way a file is read
+    pub fn from_text(text: &str, settings: Settings) -> EditorBuffer {
+        let mut buffer = EditorBuffer::new(settings);
+        buffer.rows = text.lines().map(Row::new).collect();
+        buffer.update_rows(0, buffer.rows.len());
+        buffer.settings.crlf = text.contains("\r\n");
+        buffer
+    }
+
+    // Discards the rows and the undo history and reads the file again
+    pub fn reload(&mut self) -> io::Result<()> {
+        let path = match self.file {
+            Some(ref file) => file.path.clone(),
+            None => return Ok(()),
+        };
+        let loader = open_lines(&path)?;
+        if let Some(crlf) = detect_crlf(&path) {
+            self.settings.crlf = crlf;
+        }
+        self.encoding = detect_encoding(&path);
+        self.rows = Rope::new();
+        self.changes += 1;
+        self.loader = Some(loader);
+        self.load_rows(LOAD_LINES)?;
+        self.last_mtime = modified_time(&path);
+        self.update_diff();
+        self.cx = 0;
+        self.cy = 0;
+        self.rowoff = 0;
+        self.coloff = 0;
+        self.wrapoff = 0;
+        self.selection = None;
+        self.column_selection = None;
+        self.bookmarks.clear();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.clean_index = Some(0);
+        Ok(())
+    }
+
+    // Reads up to `n` more lines of the file
+    pub fn load_rows(&mut self, n: usize) -> io::Result<()> {
+        let lines = match self.loader {
+            Some(ref mut lines) => lines,
+            None => return Ok(()),
+        };
+        let start = self.rows.len();
+        let mut result = Ok(());
+        let mut exhausted = false;
+        for _ in 0..n {
+            match lines.next() {
+                Some(Ok(mut line)) => {
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    // Past the part of the file the encoding was detected from, a line which isn't
+                    // UTF-8 makes it and the rest of the file Latin-1
+                    let text = match self.encoding.decode(line) {
+                        Ok(text) => text,
+                        Err(line) => {
+                            self.encoding = Encoding::Latin1;
+                            self.encoding.decode(line).unwrap_or_default()
+                        }
+                    };
+                    self.rows.push(Row::new(text));
+                }
+                Some(Err(err)) => {
+                    result = Err(err);
+                    break;
+                }
+                None => {
+                    exhausted = true;
+                    break;
+                }
+            }
+        }
+        if exhausted {
+            self.loader = None;
+        }
+        self.update_rows(start, self.rows.len());
+        result
+    }
+
+    pub fn load_all(&mut self) -> io::Result<()> {
+        self.load_rows(usize::MAX)
+    }
+
+    pub fn fully_loaded(&self) -> bool {
+        self.loader.is_none()
+    }
+
+    // Like the line ending, takes effect when the file is next saved
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+        self.mark_modified();
+    }
+
+    // Takes effect when the file is next saved, so the buffer stays modified until then
+    pub fn toggle_line_ending(&mut self) {
+        self.settings.crlf = !self.settings.crlf;
+        self.mark_modified();
+    }
+
+    // Modified until saved, whatever is undone
+    pub fn mark_modified(&mut self) {
+        self.clean_index = None;
+    }
+
+    // Undoing or redoing back to the saved text makes the buffer unmodified again
+    pub fn is_dirty(&self) -> bool {
+        self.clean_index != Some(self.undo_stack.len())
+    }
+
+    pub fn update_diff(&mut self) {
+        self.diff = self.file.as_ref().and_then(|file| diff::git_diff(&file.path));
+    }
+
+    // True when the file on disk is newer than the one last read or written
+    pub fn changed_on_disk(&self) -> bool {
+        let path = match self.file {
+            Some(ref file) => &file.path,
+            None => return false,
+        };
+        matches!((self.last_mtime, modified_time(path)), (Some(last), Some(now)) if now > last)
+    }
+
+    // Stops reporting the current version of the file as changed
+    pub fn ignore_disk_change(&mut self) {
+        if let Some(ref file) = self.file {
+            self.last_mtime = modified_time(&file.path);
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        if let Some(ref f) = self.file {
+            f.display.as_str()
+        } else {
+            self.label.as_deref().unwrap_or("[No Name]")
+        }
+    }
+
+    // An unnamed buffer which was never touched can be replaced by an opened file
+    pub fn is_pristine(&self) -> bool {
+        self.file.is_none() && self.label.is_none() && self.rows.is_empty() && !self.is_dirty()
+    }
+
+    pub fn set_file<P: AsRef<Path>>(&mut self, path: P) {
+        self.file = Some(FilePath::from(&path));
+        self.highlighter = highlight::for_path(path);
+        self.update_rows(0, self.rows.len());
+    }
+
+    // Writes rows to the file and returns the number of bytes written
+    pub fn write_file(&mut self) -> io::Result<WriteResult> {
+        let path = if let Some(ref file) = self.file {
+            file.path.clone()
+        } else {
+            return Ok(WriteResult { bytes: 0, copied: false, replaced: 0 });
+        };
+        if self.lock.as_ref().is_some_and(|lock| !lock.is_ours()) {
+            return Err(io::Error::other("the lock file was taken over by another editor"));
+        }
+        self.load_all()?;
+        if self.settings.trim_trailing_whitespace {
+            self.trim_trailing_whitespace();
+        }
+        let written = self.write_to(&path)?;
+        self.last_mtime = modified_time(&path);
+        self.update_diff();
+        self.clean_index = Some(self.undo_stack.len());
+        Ok(written)
+    }
+
+    // Writes rows to another file and makes it the buffer's file
+    pub fn write_file_as<P: AsRef<Path>>(&mut self, path: P) -> io::Result<WriteResult> {
+        self.load_all()?;
+        if self.settings.trim_trailing_whitespace {
+            self.trim_trailing_whitespace();
+        }
+        let written = self.write_to(path.as_ref())?;
+        self.last_mtime = modified_time(path.as_ref());
+        if self.lock.is_some() {
+            self.lock = LockFile::acquire(path.as_ref()).ok();
+        }
+        self.set_file(path);
+        self.update_diff();
+        self.clean_index = Some(self.undo_stack.len());
+        Ok(written)
+    }
+
+    // Writes rows to `path` without touching the buffer's file or dirty state
+    pub fn write_to(&mut self, path: &Path) -> io::Result<WriteResult> {
+        self.load_all()?;
+        // Write everything to a sibling file first so that the original is never left truncated
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let (tmp_path, tmp) = create_temp_file(dir, "")?;
+        let (bytes, replaced) = match self.write_rows(tmp, path) {
+            Ok(written) => written,
+            Err(err) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(err);
+            }
+        };
+
+        let copied = match fs::rename(&tmp_path, path) {
+            Ok(()) => false,
+            Err(ref err) if err.raw_os_error() == Some(libc::EXDEV) => {
+                let copy = fs::copy(&tmp_path, path);
+                let _ = fs::remove_file(&tmp_path);
+                copy?;
+                true
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(err);
+            }
+        };
+        Ok(WriteResult { bytes, copied, replaced })
+    }
+
+    // Returns the bytes written and the characters replaced by ?
+    fn write_rows(&self, file: fs::File, original: &Path) -> io::Result<(usize, usize)> {
+        if let Ok(metadata) = fs::metadata(original) {
+            file.set_permissions(metadata.permissions())?;
+        }
+
+        let mut f = io::BufWriter::new(file);
+        let written = self.encode_rows(&mut f)?;
+        let file = f.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        Ok(written)
+    }
+
+    // The rows as they are saved, in the file's encoding and line ending. Returns the bytes written
+    // and the characters replaced by ?
+    pub fn encode_rows<W: Write>(&self, mut w: W) -> io::Result<(usize, usize)> {
+        let newline: &[u8] = if self.settings.crlf { b"\r\n" } else { b"\n" };
+        let (mut bytes, mut replaced) = (0, 0);
+        for (y, line) in self.rows.iter().enumerate() {
+            let b = self.encoding.encode(&line.buf, &mut replaced);
+            w.write_all(&b)?;
+            bytes += b.len();
+            if y + 1 < self.rows.len() || self.settings.insert_final_newline {
+                w.write_all(newline)?;
+                bytes += newline.len();
+            }
+        }
+        Ok((bytes, replaced))
+    }
+
+    // Counts of the rows read so far, each with its line ending
+    pub fn stats(&self) -> BufferStats {
+        let ending = if self.settings.crlf { 2 } else { 1 };
+        let mut stats = BufferStats { lines: self.rows.len(), ..BufferStats::default() };
+        for row in self.rows.iter() {
+            stats.words += row.buf.split_whitespace().count();
+            stats.chars += row.buf.chars().count() + ending;
+            stats.bytes += row.buf.len() + ending;
+        }
+        stats
+    }
+
+    // Characters other than whitespace in each of `regions` runs of rows, for the minimap
+    pub fn non_blank_counts(&self, regions: usize) -> Vec<usize> {
+        let mut counts = vec![0; regions];
+        let len = self.rows.len();
+        if regions == 0 {
+            return counts;
+        }
+        for (y, row) in self.rows.iter().enumerate() {
+            counts[y * regions / len] += row.buf.chars().filter(|c| !c.is_whitespace()).count();
+        }
+        counts
+    }
+
+    // Every line followed by a newline, whatever the line ending setting
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for row in self.rows.iter() {
+            text.push_str(&row.buf);
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn setup_scroll(&mut self, screen_rows: usize, screen_cols: usize) {
+        if self.rowoff + screen_rows * 2 > self.rows.len() {
+            // A read error is reported when the file is saved, which needs every line
+            let _ = self.load_rows(cmp::max(LOAD_LINES, screen_rows * 10));
+        }
+        if self.cy < self.rows.len() {
+            self.rx = self.rows[self.cy].rx_from_cx(self.cx, self.settings.tab_stop);
+        } else {
+            self.rx = 0;
+        }
+
+        if self.settings.word_wrap {
+            self.coloff = 0;
+            self.wrapoff = cmp::min(self.wrapoff, self.row_height(self.rowoff, screen_cols) - 1);
+            let margin = self.scroll_margin(screen_rows);
+            let cursor = (self.cy, self.cursor_segment(screen_cols).0);
+            let top = self.step_rows(cursor, margin, true, screen_cols);
+            if (self.rowoff, self.wrapoff) > top {
+                (self.rowoff, self.wrapoff) = top;
+            }
+            let bottom = self.step_rows(cursor, screen_rows.saturating_sub(margin + 1), true, screen_cols);
+            if (self.rowoff, self.wrapoff) < bottom {
+                (self.rowoff, self.wrapoff) = cmp::min(bottom, self.max_view(screen_rows, screen_cols));
+            }
+            return;
+        }
+        self.wrapoff = 0;
+
+        let margin = self.scroll_margin(screen_rows);
+        if self.cy < self.rowoff + margin {
+            self.rowoff = self.cy.saturating_sub(margin);
+        }
+        if self.cy + margin >= self.rowoff + screen_rows {
+            // Do not scroll past the virtual line after the end of the file
+            let max_rowoff = (self.rows.len() + 1).saturating_sub(screen_rows);
+            self.rowoff = cmp::min((self.cy + margin + 1).saturating_sub(screen_rows), max_rowoff);
+        }
+
+        // Margins shrink on small screens so that the cursor can still reach every column
+        let margin = cmp::min(self.settings.side_scroll_off, screen_cols.saturating_sub(1) / 2);
+        if self.rx < self.coloff + margin {
+            self.coloff = self.rx.saturating_sub(margin);
+        }
+        if self.rx + margin >= self.coloff + screen_cols {
+            self.coloff = (self.rx + margin + 1).saturating_sub(screen_cols);
+        }
+    }
+
+    // Margins shrink on small screens so that the cursor can still reach every row
+    fn scroll_margin(&self, screen_rows: usize) -> usize {
+        cmp::min(self.settings.scroll_off, screen_rows.saturating_sub(1) / 2)
+    }
+
+    // Render columns at which the screen rows of row `y` start, a single 0 unless lines wrap
+    pub fn wrap_starts(&self, y: usize, width: usize) -> Vec<usize> {
+        match self.rows.get(y) {
+            Some(row) if self.settings.word_wrap => row.wrap_starts(width),
+            _ => vec![0],
+        }
+    }
+
+    fn row_height(&self, y: usize, width: usize) -> usize {
+        self.wrap_starts(y, width).len()
+    }
+
+    // Moves a position of (row, screen row within it) by `n` screen rows, stopping at the top
+    // and at the line after the end of the file
+    fn step_rows(&self, (mut y, mut seg): (usize, usize), n: usize, up: bool, width: usize) -> (usize, usize) {
+        for _ in 0..n {
+            if up {
+                if seg > 0 {
+                    seg -= 1;
+                } else if y > 0 {
+                    y -= 1;
+                    seg = self.row_height(y, width) - 1;
+                } else {
+                    break;
+                }
+            } else if seg + 1 < self.row_height(y, width) {
+                seg += 1;
+            } else if y < self.rows.len() {
+                y += 1;
+                seg = 0;
+            } else {
+                break;
+            }
+        }
+        (y, seg)
+    }
+
+    // The furthest the view scrolls down when lines wrap, with the line after the end at the bottom
+    fn max_view(&self, screen_rows: usize, width: usize) -> (usize, usize) {
+        self.step_rows((self.rows.len(), 0), screen_rows.saturating_sub(1), true, width)
+    }
+
+    // The screen row of row `cy` which holds the cursor, and the cursor's column in it
+    fn cursor_segment(&self, width: usize) -> (usize, usize) {
+        let starts = self.wrap_starts(self.cy, width);
+        let seg = starts.iter().rposition(|&start| start <= self.rx).unwrap_or(0);
+        (seg, self.rx - starts[seg])
+    }
+
+    // Screen row and column of the cursor within the text area
+    pub fn screen_cursor(&self, width: usize) -> (usize, usize) {
+        if !self.settings.word_wrap {
+            return (self.cy - self.rowoff, self.rx - self.coloff);
+        }
+        let (seg, col) = self.cursor_segment(width);
+        let above: usize = (self.rowoff..self.cy).map(|y| self.row_height(y, width)).sum();
+        (above + seg - self.wrapoff, col)
+    }
+
+    // Puts the cursor at column `col` of a screen row of row `y`
+    fn set_cursor_on_screen_row(&mut self, (y, seg): (usize, usize), col: usize, width: usize) {
+        self.cy = cmp::min(y, self.rows.len());
+        let starts = self.wrap_starts(self.cy, width);
+        self.cx = match self.rows.get(self.cy) {
+            Some(row) => {
+                let start = starts.get(seg).copied().unwrap_or(0);
+                // Past the end of a screen row the cursor stays on it instead of the next one
+                let rx = match starts.get(seg + 1) {
+                    Some(&next) => cmp::min(start + col, next - 1),
+                    None => start + col,
+                };
+                row.cx_from_rx(rx, self.settings.tab_stop)
+            }
+            None => 0,
+        };
+    }
+
+    // Moves the view without moving the cursor, unless the cursor would leave the view
+    pub fn scroll(&mut self, up: bool, lines: usize, screen_rows: usize, screen_cols: usize) {
+        if self.settings.word_wrap {
+            let max_view = self.max_view(screen_rows, screen_cols);
+            let view = cmp::min(self.step_rows((self.rowoff, self.wrapoff), lines, up, screen_cols), max_view);
+            (self.rowoff, self.wrapoff) = view;
+
+            let margin = self.scroll_margin(screen_rows);
+            let top = if view == (0, 0) { view } else { self.step_rows(view, margin, false, screen_cols) };
+            let bottom = if view == max_view {
+                (self.rows.len(), 0)
+            } else {
+                self.step_rows(view, screen_rows.saturating_sub(margin + 1), false, screen_cols)
+            };
+            let (seg, col) = self.cursor_segment(screen_cols);
+            if (self.cy, seg) < top {
+                self.set_cursor_on_screen_row(top, col, screen_cols);
+            } else if (self.cy, seg) > bottom {
+                self.set_cursor_on_screen_row(bottom, col, screen_cols);
+            }
+            return;
+        }
+
+        let max_rowoff = (self.rows.len() + 1).saturating_sub(screen_rows);
+        self.rowoff = if up {
+            self.rowoff.saturating_sub(lines)
+        } else {
+            cmp::min(self.rowoff + lines, max_rowoff)
+        };
+
+        let margin = self.scroll_margin(screen_rows);
+        let top = if self.rowoff == 0 { 0 } else { self.rowoff + margin };
+        let bottom = if self.rowoff == max_rowoff {
+            self.rows.len()
+        } else {
+            (self.rowoff + screen_rows).saturating_sub(margin + 1)
+        };
+        self.cy = cmp::max(cmp::min(self.cy, bottom), top);
+        self.clamp_cx();
+    }
+
+    fn update_row(&mut self, y: usize) {
+        self.update_rows(y, y + 1);
+    }
+
+    // Renders and highlights rows `start..end`, then the rows below them which were highlighted as
+    // starting in or out of a string or comment that has since been closed or opened
+    fn update_rows(&mut self, start: usize, end: usize) {
+        let mut state = match start.checked_sub(1).and_then(|y| self.rows.get(y)) {
+            Some(row) => row.end_state,
+            None => LineState::Code,
+        };
+        for y in start..self.rows.len() {
+            let row = &mut self.rows[y];
+            if y >= end && row.start_state == state {
+                break;
+            }
+            if y < end {
+                row.update_render(self.settings.tab_stop);
+            }
+            row.start_state = state;
+            row.highlight = self.highlighter.highlight_line(&row.render, &mut state);
+            row.end_state = state;
+        }
+    }
+
+    fn update_edited_rows(&mut self) {
+        // Apart from regions, which update their own rows, every undo record only touches the
+        // cursor row and the row just above it
+        if self.cy > 0 {
+            self.update_row(self.cy - 1);
+        }
+        self.update_row(self.cy);
+    }
+
+    fn apply_record(&mut self, record: &UndoRecord) {
+        self.changes += 1;
+        match *record {
+            UndoRecord::InsertChar { row, col, byte, ch } => {
+                self.rows[row].insert_char(col, ch);
+                self.cx = self.rows[row].grapheme_index_of_byte(byte + ch.len_utf8());
+                self.cy = row;
+            }
+            UndoRecord::DeleteChar { row, col, .. } => {
+                self.rows[row].delete_char(col);
+                self.cx = col;
+                self.cy = row;
+            }
+            UndoRecord::InsertLine { at } => {
+                self.rows.insert(at, Row::empty());
+                self.rows_inserted(at, 1);
+                self.cx = 0;
+                self.cy = at;
+            }
+            UndoRecord::SplitLine { row, col, ref indent } => {
+                let (head, tail) = self.rows[row].split_at_grapheme(col);
+                self.rows[row].buf = head;
+                self.rows.insert(row + 1, Row::new(format!("{}{}", indent, tail)));
+                // Splitting at the start moves the whole line down, and its bookmark with it
+                let at = if col == 0 { row } else { row + 1 };
+                self.rows_inserted(at, 1);
+                self.cx = indent.graphemes(true).count();
+                self.cy = row + 1;
+            }
+            UndoRecord::MergeLine { row, col } => {
+                let merged = self.rows.remove(row);
+                self.rows_removed(row, row + 1);
+                self.rows[row - 1].append(merged.buf);
+                self.cx = col;
+                self.cy = row - 1;
+            }
+            UndoRecord::Replace { row, col, ref old, ref new } => {
+                self.rows[row].replace_str(col, old, new);
+                self.cx = col + new.graphemes(true).count();
+                self.cy = row;
+            }
+            UndoRecord::JoinLine { row, split_at } => {
+                let next = self.rows.remove(row + 1);
+                self.rows_removed(row + 1, row + 2);
+                if split_at > 0 && !next.buf.is_empty() {
+                    self.rows[row].append(" ");
+                }
+                self.rows[row].append(next.buf);
+                self.cx = split_at;
+                self.cy = row;
+            }
+            UndoRecord::ToggleComment { row, col, ref prefix, added } => {
+                if added {
+                    self.rows[row].insert_str(col, prefix);
+                } else {
+                    self.rows[row].replace_str(col, prefix, "");
+                }
+                self.cx = col;
+                self.cy = row;
+            }
+            UndoRecord::Group(ref records) => {
+                for record in records {
+                    self.apply_record(record);
+                }
+            }
+            UndoRecord::SwapLines { row } => {
+                self.rows.swap(row, row + 1);
+                self.swap_bookmarks(row);
+                self.cy = row;
+            }
+            UndoRecord::Transpose { row, at } => {
+                self.rows[row].transpose_at(at);
+                self.cx = at + 1;
+                self.cy = row;
+            }
+            UndoRecord::InsertRegion { row, col, ref text } => self.insert_region(row, col, text),
+            UndoRecord::DeleteRegion { row, col, ref text } => self.delete_region(row, col, text),
+        }
+        self.update_edited_rows();
+    }
+
+    fn revert_record(&mut self, record: &UndoRecord) {
+        self.changes += 1;
+        match *record {
+            UndoRecord::InsertChar { row, col, byte, .. } => {
+                self.rows[row].remove_char(byte);
+                self.cx = col;
+                self.cy = row;
+            }
+            UndoRecord::DeleteChar { row, col, ref grapheme } => {
+                self.rows[row].insert_str(col, grapheme);
+                self.cx = col + 1;
+                self.cy = row;
+            }
+            UndoRecord::InsertLine { at } => {
+                self.rows.remove(at);
+                self.rows_removed(at, at + 1);
+                self.cx = 0;
+                self.cy = at;
+            }
+            UndoRecord::SplitLine { row, col, ref indent } => {
+                let merged = self.rows.remove(row + 1);
+                let at = if col == 0 { row } else { row + 1 };
+                self.rows_removed(at, at + 1);
+                self.rows[row].append(&merged.buf[indent.len()..]);
+                self.cx = col;
+                self.cy = row;
+            }
+            UndoRecord::MergeLine { row, col } => {
+                let (head, tail) = self.rows[row - 1].split_at_grapheme(col);
+                self.rows[row - 1].buf = head;
+                self.rows.insert(row, Row::new(tail));
+                self.rows_inserted(row, 1);
+                self.cx = 0;
+                self.cy = row;
+            }
+            UndoRecord::Replace { row, col, ref old, ref new } => {
+                self.rows[row].replace_str(col, new, old);
+                self.cx = col;
+                self.cy = row;
+            }
+            UndoRecord::JoinLine { row, split_at } => {
+                let idx = self.rows[row].byte_offset_of_grapheme(split_at);
+                let mut rest = &self.rows[row].buf[idx..];
+                if split_at > 0 && !rest.is_empty() {
+                    rest = &rest[1..];
+                }
+                let next = Row::new(rest);
+                self.rows[row].truncate(split_at);
+                self.rows.insert(row + 1, next);
+                self.rows_inserted(row + 1, 1);
+                self.update_row(row + 1);
+                self.cx = split_at;
+                self.cy = row;
+            }
+            UndoRecord::ToggleComment { row, col, ref prefix, added } => {
+                if added {
+                    self.rows[row].replace_str(col, prefix, "");
+                } else {
+                    self.rows[row].insert_str(col, prefix);
+                }
+                self.cx = col;
+                self.cy = row;
+            }
+            UndoRecord::Group(ref records) => {
+                for record in records.iter().rev() {
+                    self.revert_record(record);
+                }
+            }
+            UndoRecord::SwapLines { row } => {
+                self.rows.swap(row, row + 1);
+                self.swap_bookmarks(row);
+                self.cy = row;
+            }
+            UndoRecord::Transpose { row, at } => {
+                self.rows[row].transpose_at(at);
+                self.cx = at + 1;
+                self.cy = row;
+            }
+            UndoRecord::InsertRegion { row, col, ref text } => {
+                self.delete_region(row, col, text);
+            }
+            UndoRecord::DeleteRegion { row, col, ref text } => {
+                self.insert_region(row, col, text);
+                self.cx = col;
+                self.cy = row;
+            }
+        }
+        self.update_edited_rows();
+    }
+
+    // `n` rows were inserted at `at`, so the bookmarks from there on move down
+    fn rows_inserted(&mut self, at: usize, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let moved = self.bookmarks.split_off(&at);
+        self.bookmarks.extend(moved.into_iter().map(|y| y + n));
+    }
+
+    // Rows `start..end` were removed along with their bookmarks, and the later ones move up
+    fn rows_removed(&mut self, start: usize, end: usize) {
+        let moved = self.bookmarks.split_off(&start);
+        self.bookmarks.extend(moved.into_iter().filter(|&y| y >= end).map(|y| y - (end - start)));
+    }
+
+    // A bookmark goes with its row when the row moves
+    fn swap_bookmarks(&mut self, row: usize) {
+        let (a, b) = (self.bookmarks.remove(&row), self.bookmarks.remove(&(row + 1)));
+        if a {
+            self.bookmarks.insert(row + 1);
+        }
+        if b {
+            self.bookmarks.insert(row);
+        }
+    }
+
+    // Returns whether the row is bookmarked now
+    pub fn toggle_bookmark(&mut self, row: usize) -> bool {
+        if self.bookmarks.remove(&row) {
+            false
+        } else {
+            self.bookmarks.insert(row);
+            true
+        }
+    }
+
+    // The next bookmarked row after `row`, or before it going back, wrapping around the ends
+    pub fn next_bookmark(&self, row: usize, forward: bool) -> Option<usize> {
+        if forward {
+            self.bookmarks.range(row + 1..).next().or(self.bookmarks.iter().next()).copied()
+        } else {
+            self.bookmarks.range(..row).next_back().or(self.bookmarks.iter().next_back()).copied()
+        }
+    }
+
+    // The graphemes of row `y` which are not in a string or comment, with their grapheme index
+    fn code_graphemes(&self, y: usize) -> Vec<(usize, &str)> {
+        let row = &self.rows[y];
+        let (mut col, mut idx) = (0, 0);
+        let mut code = vec![];
+        for (x, g) in row.buf.graphemes(true).enumerate() {
+            if !matches!(row.highlight.get(idx), Some(Highlight::String | Highlight::Comment)) {
+                code.push((x, g));
+            }
+            let width = row::advance(g, col, self.settings.tab_stop);
+            col += width;
+            idx += if g == "\t" { width } else { g.chars().count() };
+        }
+        code
+    }
+
+    // (row, col) of the bracket matching the one under the cursor, None when the cursor is not on
+    // a bracket or it has no match. Brackets in strings and comments are not counted
+    pub fn matching_bracket(&self) -> Option<(usize, usize)> {
+        let (cx, cy) = (self.cx, self.cy);
+        if cy >= self.rows.len() {
+            return None;
+        }
+        let (_, bracket) = self.code_graphemes(cy).into_iter().find(|&(x, _)| x == cx)?;
+        let (partner, forward) = match bracket {
+            "(" => (")", true),
+            "[" => ("]", true),
+            "{" => ("}", true),
+            "<" => (">", true),
+            ")" => ("(", false),
+            "]" => ("[", false),
+            "}" => ("{", false),
+            ">" => ("<", false),
+            _ => return None,
+        };
+        let rows: Box<dyn Iterator<Item = usize>> = if forward { Box::new(cy..self.rows.len()) } else { Box::new((0..=cy).rev()) };
+        let mut depth = 0;
+        for y in rows {
+            let mut code = self.code_graphemes(y);
+            if !forward {
+                code.reverse();
+            }
+            for (x, g) in code {
+                if y == cy && (if forward { x < cx } else { x > cx }) {
+                    continue;
+                }
+                if g == bracket {
+                    depth += 1;
+                } else if g == partner {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((y, x));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // Leaves the cursor after the inserted text
+    fn insert_region(&mut self, row: usize, col: usize, text: &[String]) {
+        let (head, tail) = self.rows[row].split_at_grapheme(col);
+        self.rows[row].buf = head;
+        for (i, line) in text.iter().enumerate() {
+            if i == 0 {
+                self.rows[row].append(line);
+            } else {
+                self.rows.insert(row + i, Row::new(line.as_str()));
+            }
+        }
+        let (end_row, end_col) = region_end(row, col, text);
+        self.rows_inserted(row + 1, end_row - row);
+        self.rows[end_row].append(tail);
+        for y in row..=end_row {
+            self.update_row(y);
+        }
+        self.cx = end_col;
+        self.cy = end_row;
+    }
+
+    fn delete_region(&mut self, row: usize, col: usize, text: &[String]) {
+        let (end_row, end_col) = region_end(row, col, text);
+        if end_row == row {
+            self.rows[row].delete_range(col, end_col);
+        } else {
+            let (_, tail) = self.rows[end_row].split_at_grapheme(end_col);
+            self.rows.remove_range(row + 1, end_row + 1);
+            self.rows_removed(row + 1, end_row + 1);
+            let len = self.rows[row].grapheme_count();
+            self.rows[row].replace_range(col, len, &tail);
+        }
+        self.update_row(row);
+        self.cx = col;
+        self.cy = row;
+    }
+
+    fn edit(&mut self, record: UndoRecord) {
+        self.selection = None;
+        self.column_selection = None;
+        if let Some(idx) = self.clean_index {
+            // The saved state lived in the redo stack which is about to be discarded
+            if idx > self.undo_stack.len() {
+                self.clean_index = None;
+            }
+        }
+        self.apply_record(&record);
+        self.undo_stack.push(record);
+        self.redo_stack.clear();
+    }
+
+    // Returns false when there is nothing to undo
+    pub fn undo(&mut self) -> bool {
+        self.selection = None;
+        self.column_selection = None;
+        if let Some(record) = self.undo_stack.pop() {
+            self.revert_record(&record);
+            self.redo_stack.push(record);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Returns false when there is nothing to redo
+    pub fn redo(&mut self) -> bool {
+        self.selection = None;
+        self.column_selection = None;
+        if let Some(record) = self.redo_stack.pop() {
+            self.apply_record(&record);
+            self.undo_stack.push(record);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn insert_char(&mut self, ch: char) {
+        if self.cy == self.rows.len() {
+            self.edit(UndoRecord::InsertLine { at: self.cy });
+        }
+        self.edit(UndoRecord::InsertChar {
+            row: self.cy,
+            col: self.cx,
+            byte: self.rows[self.cy].byte_offset_of_grapheme(self.cx),
+            ch,
+        });
+    }
+
+    pub fn delete_char(&mut self) {
+        if self.cy == self.rows.len() || self.cx == 0 && self.cy == 0 {
+            return;
+        }
+        if self.cx > 0 {
+            let col = self.cx - 1;
+            if let Some(grapheme) = self.rows[self.cy].grapheme_at(col) {
+                let grapheme = grapheme.to_string();
+                self.edit(UndoRecord::DeleteChar { row: self.cy, col, grapheme });
+            }
+        } else {
+            let col = self.rows[self.cy - 1].grapheme_count();
+            self.edit(UndoRecord::MergeLine { row: self.cy, col });
+        }
+    }
+
+    // Deletes the character under the cursor, or joins the next line at the end of a line
+    pub fn delete_char_forward(&mut self) {
+        let len = match self.rows.get(self.cy) {
+            Some(row) => row.grapheme_count(),
+            None => return,
+        };
+        if self.cx < len {
+            if let Some(grapheme) = self.rows[self.cy].grapheme_at(self.cx) {
+                let grapheme = grapheme.to_string();
+                self.edit(UndoRecord::DeleteChar { row: self.cy, col: self.cx, grapheme });
+            }
+        } else if self.cy + 1 < self.rows.len() {
+            self.edit(UndoRecord::MergeLine { row: self.cy + 1, col: len });
+        }
+    }
+
+    pub fn insert_tab(&mut self) {
+        if self.selection.is_some() || (self.cx == 0 && self.cy < self.rows.len()) {
+            self.indent();
+            return;
+        }
+        if !self.settings.expand_tabs {
+            self.insert_char('\t');
+            return;
+        }
+        let tab_stop = self.settings.tab_stop;
+        let rx = self.rows.get(self.cy).map(|row| row.rx_from_cx(self.cx, tab_stop)).unwrap_or(0);
+        let spaces = " ".repeat(tab_stop - rx % tab_stop);
+        self.paste(&[spaces]);
+    }
+
+    // Leading whitespace of the row, one level deeper when the text before `col` opens a block
+    fn indent_for_split(&self, y: usize, col: usize) -> String {
+        let row = &self.rows[y];
+        let head = &row.buf[..row.byte_offset_of_grapheme(col)];
+        let mut indent: String = head.chars().take_while(|&c| c == ' ' || c == '\t').collect();
+        if head.trim_end().ends_with('{') {
+            if self.settings.expand_tabs {
+                indent.push_str(&" ".repeat(self.settings.tab_stop));
+            } else {
+                indent.push('\t');
+            }
+        }
+        indent
+    }
+
+    pub fn insert_line(&mut self) {
+        if self.cy >= self.rows.len() {
+            self.edit(UndoRecord::InsertLine { at: self.cy });
+            self.cy += 1;
+        } else {
+            let col = cmp::min(self.cx, self.rows[self.cy].grapheme_count());
+            let indent = self.indent_for_split(self.cy, col);
+            self.edit(UndoRecord::SplitLine { row: self.cy, col, indent });
+        }
+    }
+
+    pub fn duplicate_line(&mut self) {
+        if self.cy >= self.rows.len() {
+            self.edit(UndoRecord::InsertLine { at: self.cy });
+            self.cy += 1;
+            return;
+        }
+        let (cx, cy) = (self.cx, self.cy);
+        let line = self.rows[cy].buf.clone();
+        self.edit(UndoRecord::InsertRegion {
+            row: cy,
+            col: self.rows[cy].grapheme_count(),
+            text: vec![String::new(), line],
+        });
+        self.cx = cx;
+    }
+
+    // Deletes from the cursor to the end of the line, or the line break when already there
+    pub fn kill_to_end(&mut self) -> Option<String> {
+        if self.cy >= self.rows.len() {
+            return None;
+        }
+        let (row, col) = (self.cy, cmp::min(self.cx, self.rows[self.cy].grapheme_count()));
+        let line = &self.rows[row];
+        let text = if col < line.grapheme_count() {
+            vec![line.buf[line.byte_offset_of_grapheme(col)..].to_string()]
+        } else if row + 1 < self.rows.len() {
+            vec![String::new(), String::new()]
+        } else {
+            return None;
+        };
+        let killed = text.join("\n");
+        self.edit(UndoRecord::DeleteRegion { row, col, text });
+        Some(killed)
+    }
+
+    pub fn kill_to_start(&mut self) -> Option<String> {
+        if self.cy >= self.rows.len() || self.cx == 0 {
+            return None;
+        }
+        let line = &self.rows[self.cy];
+        let killed = line.buf[..line.byte_offset_of_grapheme(self.cx)].to_string();
+        self.edit(UndoRecord::DeleteRegion {
+            row: self.cy,
+            col: 0,
+            text: vec![killed.clone()],
+        });
+        Some(killed)
+    }
+
+    // Alternates between the indentation and column 0
+    pub fn smart_home(&mut self) {
+        let indent = self.rows.get(self.cy).map(Row::first_non_whitespace).unwrap_or(0);
+        self.cx = if self.cx == indent { 0 } else { indent };
+    }
+
+    pub fn line_end(&mut self) {
+        if let Some(row) = self.rows.get(self.cy) {
+            self.cx = row.grapheme_count();
+        }
+    }
+
+    // First and last row touched by the selection
+    pub fn selected_row_range(&self) -> Option<(usize, usize)> {
+        let ((start, _), (end, end_col)) = self.selection_range()?;
+        // A selection ending at column 0 does not include that row
+        let end = if end > start && end_col == 0 { end - 1 } else { end };
+        Some((start, end))
+    }
+
+    // Non-blank rows touched by the selection, or the cursor row without one
+    fn selected_rows(&self) -> Vec<usize> {
+        match self.selected_row_range() {
+            Some((start, end)) => (start..=end).filter(|&y| !self.rows[y].buf.trim().is_empty()).collect(),
+            None if self.cy < self.rows.len() => vec![self.cy],
+            None => vec![],
+        }
+    }
+
+    // Puts `lines` in place of rows `start` to `end` as one undo step, keeping the cursor where
+    // it was as far as the new rows allow. Returns false when they already held those lines
+    pub fn replace_rows(&mut self, start: usize, end: usize, lines: &[String]) -> bool {
+        let old: Vec<String> = (start..=end).map(|y| self.rows[y].buf.clone()).collect();
+        if old == lines {
+            return false;
+        }
+        let (cx, cy) = (self.cx, self.cy);
+        let mut records = vec![UndoRecord::DeleteRegion { row: start, col: 0, text: old }];
+        if !lines.is_empty() {
+            records.push(UndoRecord::InsertRegion { row: start, col: 0, text: lines.to_vec() });
+        }
+        self.edit(UndoRecord::Group(records));
+        self.cy = cmp::min(cy, self.rows.len().saturating_sub(1));
+        self.cx = cmp::min(cx, self.rows.get(self.cy).map_or(0, Row::grapheme_count));
+        true
+    }
+
+    // Applies per-row edits as one undo step, keeping the cursor on its row at column `cx`
+    fn edit_rows(&mut self, mut records: Vec<UndoRecord>, cx: usize) {
+        let (old_cx, cy, selection) = (self.cx, self.cy, self.selection);
+        let record = match records.len() {
+            0 => return,
+            1 => records.remove(0),
+            _ => UndoRecord::Group(records),
+        };
+        self.edit(record);
+        // A selection ending at column 0 excludes its last row, so do not let it collapse there
+        self.cx = if selection.is_some() && cx == 0 && old_cx > 0 {
+            cmp::min(1, self.rows[cy].grapheme_count())
+        } else {
+            cx
+        };
+        self.cy = cy;
+        self.selection = selection;
+    }
+
+    // Column of the cursor after `len` graphemes are inserted (or removed) at `col` of its row
+    fn shifted_cx(&self, y: usize, col: usize, len: usize, inserted: bool) -> Option<usize> {
+        if y != self.cy || self.cx < col {
+            None
+        } else if inserted {
+            Some(self.cx + len)
+        } else {
+            Some(col + (self.cx - col).saturating_sub(len))
+        }
+    }
+
+    // Comments or uncomments the cursor row, or every non-blank row touched by the selection
+    pub fn toggle_comment(&mut self) {
+        let prefix = self.highlighter.comment_prefix().unwrap_or("//");
+        let rows = self.selected_rows();
+        let commented = rows
+            .iter()
+            .filter(|&&y| self.rows[y].buf.trim_start().starts_with(prefix))
+            .count();
+        let add = commented * 2 <= rows.len();
+
+        let mut cx = self.cx;
+        let mut records = vec![];
+        for y in rows {
+            let row = &self.rows[y];
+            let col = row.first_non_whitespace();
+            let rest = &row.buf[row.byte_offset_of_grapheme(col)..];
+            let spaced = format!("{} ", prefix);
+            let text = if add || rest.starts_with(&spaced) {
+                spaced
+            } else if rest.starts_with(prefix) {
+                prefix.to_string()
+            } else {
+                continue;
+            };
+            if let Some(x) = self.shifted_cx(y, col, text.graphemes(true).count(), add) {
+                cx = x;
+            }
+            records.push(UndoRecord::ToggleComment { row: y, col, prefix: text, added: add });
+        }
+        self.edit_rows(records, cx);
+    }
+
+    pub fn indent(&mut self) {
+        let unit = if self.settings.expand_tabs {
+            " ".repeat(self.settings.tab_stop)
+        } else {
+            "\t".to_string()
+        };
+        let mut cx = self.cx;
+        let mut records = vec![];
+        for y in self.selected_rows() {
+            if let Some(x) = self.shifted_cx(y, 0, unit.len(), true) {
+                cx = x;
+            }
+            records.push(UndoRecord::Replace { row: y, col: 0, old: String::new(), new: unit.clone() });
+        }
+        self.edit_rows(records, cx);
+    }
+
+    // One undo step, like the block edits
+    pub fn trim_trailing_whitespace(&mut self) {
+        let markdown = self.is_markdown();
+        let mut cx = self.cx;
+        let mut records = vec![];
+        for (y, row) in self.rows.iter().enumerate() {
+            let trimmed = row.buf.trim_end();
+            if trimmed.len() == row.buf.len() {
+                continue;
+            }
+            // Two spaces at the end of a line of text are a line break in Markdown
+            if markdown && !trimmed.is_empty() && row.buf.ends_with("  ") && !row.buf[trimmed.len()..].contains('\t') {
+                continue;
+            }
+            let col = row.grapheme_index_of_byte(trimmed.len());
+            if y == self.cy {
+                cx = cmp::min(cx, col);
+            }
+            records.push(UndoRecord::Replace {
+                row: y,
+                col,
+                old: row.buf[trimmed.len()..].to_string(),
+                new: String::new(),
+            });
+        }
+        self.edit_rows(records, cx);
+    }
+
+    fn is_markdown(&self) -> bool {
+        let extension = self.file.as_ref().and_then(|file| file.path.extension()).and_then(|e| e.to_str());
+        matches!(extension, Some("md" | "markdown"))
+    }
+
+    // Removes one tab or up to a tab stop of spaces, whatever the row starts with
+    pub fn unindent(&mut self) {
+        let mut cx = self.cx;
+        let mut records = vec![];
+        for y in self.selected_rows() {
+            let buf = &self.rows[y].buf;
+            let len = if buf.starts_with('\t') {
+                1
+            } else {
+                buf.chars().take(self.settings.tab_stop).take_while(|&c| c == ' ').count()
+            };
+            if len == 0 {
+                continue;
+            }
+            if let Some(x) = self.shifted_cx(y, 0, len, false) {
+                cx = x;
+            }
+            records.push(UndoRecord::Replace { row: y, col: 0, old: buf[..len].to_string(), new: String::new() });
+        }
+        self.edit_rows(records, cx);
+    }
+
+    // Deletes `n` rows from the cursor's as one undo step. Deleting every row leaves an empty one
+    pub fn delete_lines(&mut self, n: usize) {
+        let (start, end) = (self.cy, cmp::min(self.cy.saturating_add(n), self.rows.len()));
+        if start >= end {
+            return;
+        }
+        let mut text: Vec<String> = (start..end).map(|y| self.rows[y].buf.clone()).collect();
+        let (row, col) = if end < self.rows.len() {
+            // Up to the start of the row after
+            text.push(String::new());
+            (start, 0)
+        } else if start > 0 {
+            // From the end of the row before, so that no empty row is left at the end
+            text.insert(0, String::new());
+            (start - 1, self.rows[start - 1].grapheme_count())
+        } else {
+            (0, 0)
+        };
+        self.edit(UndoRecord::DeleteRegion { row, col, text });
+        self.cy = cmp::min(start, self.rows.len().saturating_sub(1));
+        self.cx = 0;
+    }
+
+    pub fn join_lines(&mut self) {
+        if self.cy + 1 >= self.rows.len() {
+            return;
+        }
+        self.edit(UndoRecord::JoinLine {
+            row: self.cy,
+            split_at: self.rows[self.cy].grapheme_count(),
+        });
+    }
+
+    // Swaps the characters before and under the cursor and moves past them, or the last two at
+    // the end of the line. At the start of a line the last character of the line above swaps
+    // with the first one of this line
+    pub fn transpose_chars(&mut self) {
+        if self.cy >= self.rows.len() {
+            return;
+        }
+        let len = self.rows[self.cy].grapheme_count();
+        if self.cx == 0 {
+            if self.cy == 0 || len == 0 || self.rows[self.cy - 1].buf.is_empty() {
+                return;
+            }
+            let (row, above) = (self.cy, self.cy - 1);
+            let last_col = self.rows[above].grapheme_count() - 1;
+            let (_, last) = self.rows[above].split_at_grapheme(last_col);
+            let first = self.rows[row].buf.graphemes(true).next().unwrap_or("").to_string();
+            self.edit(UndoRecord::Group(vec![
+                UndoRecord::Replace { row: above, col: last_col, old: last.clone(), new: first.clone() },
+                UndoRecord::Replace { row, col: 0, old: first, new: last },
+            ]));
+            return;
+        }
+        if len < 2 {
+            return;
+        }
+        let at = cmp::min(self.cx, len - 1);
+        self.edit(UndoRecord::Transpose { row: self.cy, at });
+    }
+
+    pub fn move_line_up(&mut self) {
+        if self.cy == 0 || self.cy >= self.rows.len() {
+            return;
+        }
+        self.edit(UndoRecord::SwapLines { row: self.cy - 1 });
+        self.clamp_cx();
+    }
+
+    pub fn move_line_down(&mut self) {
+        if self.cy + 1 >= self.rows.len() {
+            return;
+        }
+        let cy = self.cy;
+        self.edit(UndoRecord::SwapLines { row: cy });
+        self.cy = cy + 1;
+        self.clamp_cx();
+    }
+
+    // Moves the cursor to a position in the text area of the screen
+    pub fn click(&mut self, screen_row: usize, screen_col: usize, screen_cols: usize) {
+        self.selection = None;
+        if self.settings.word_wrap {
+            let pos = self.step_rows((self.rowoff, self.wrapoff), screen_row, false, screen_cols);
+            self.set_cursor_on_screen_row(pos, screen_col, screen_cols);
+            return;
+        }
+        self.cy = cmp::min(self.rowoff + screen_row, self.rows.len());
+        self.cx = match self.rows.get(self.cy) {
+            Some(row) => row.cx_from_rx(self.coloff + screen_col, self.settings.tab_stop),
+            None => 0,
+        };
+    }
+
+    fn clamp_cx(&mut self) {
+        let len = self.rows.get(self.cy).map(Row::grapheme_count).unwrap_or(0);
+        if self.cx > len {
+            self.cx = len;
+        }
+    }
+
+    // Ordered (start, end) positions of the selection, clamped to the text
+    pub fn selection_range(&self) -> Option<((usize, usize), (usize, usize))> {
+        let (ax, ay) = self.selection.map(|(row, col)| (col, row))?;
+        let last = self.rows.len().checked_sub(1)?;
+        let clamp = |x: usize, y: usize| {
+            if y > last {
+                (last, self.rows[last].grapheme_count())
+            } else {
+                (y, cmp::min(x, self.rows[y].grapheme_count()))
+            }
+        };
+        let anchor = clamp(ax, ay);
+        let cursor = clamp(self.cx, self.cy);
+        if anchor == cursor {
+            None
+        } else if anchor < cursor {
+            Some((anchor, cursor))
+        } else {
+            Some((cursor, anchor))
+        }
+    }
+
+    pub fn selected_text(&self) -> Option<Vec<String>> {
+        let ((start_row, start_col), (end_row, end_col)) = self.selection_range()?;
+        let text = (start_row..=end_row)
+            .map(|y| {
+                let row = &self.rows[y];
+                let from = if y == start_row { row.byte_offset_of_grapheme(start_col) } else { 0 };
+                let to = if y == end_row { row.byte_offset_of_grapheme(end_col) } else { row.buf.len() };
+                row.buf[from..to].to_string()
+            })
+            .collect();
+        Some(text)
+    }
+
+    // Returns false when nothing is selected
+    pub fn delete_selection(&mut self) -> bool {
+        let ((row, col), _) = match self.selection_range() {
+            Some(range) => range,
+            None => return false,
+        };
+        let text = self.selected_text().unwrap_or_default();
+        self.edit(UndoRecord::DeleteRegion { row, col, text });
+        true
+    }
+
+    // Replaces the selected text as one undo step. Returns false when nothing is selected
+    pub fn replace_selection(&mut self, text: &[String]) -> bool {
+        let ((row, col), _) = match self.selection_range() {
+            Some(range) => range,
+            None => return false,
+        };
+        let old = self.selected_text().unwrap_or_default();
+        let mut records = vec![UndoRecord::DeleteRegion { row, col, text: old }];
+        if !text.is_empty() {
+            records.push(UndoRecord::InsertRegion { row, col, text: text.to_vec() });
+        }
+        self.edit(UndoRecord::Group(records));
+        true
+    }
+
+    pub fn paste(&mut self, text: &[String]) {
+        if text.is_empty() {
+            return;
+        }
+        if self.cy == self.rows.len() {
+            self.edit(UndoRecord::InsertLine { at: self.cy });
+        }
+        self.edit(UndoRecord::InsertRegion {
+            row: self.cy,
+            col: self.cx,
+            text: text.to_vec(),
+        });
+    }
+
+    // The word characters just before the cursor
+    pub fn word_before_cursor(&self) -> &str {
+        let row = match self.rows.get(self.cy) {
+            Some(row) => row,
+            None => return "",
+        };
+        let end = row.byte_offset_of_grapheme(self.cx);
+        let start = row.buf[..end].bytes().rposition(|b| !is_word_byte(b)).map_or(0, |i| i + 1);
+        &row.buf[start..end]
+    }
+
+    // Longer words of the buffer which start with `prefix`, the most frequent first
+    pub fn completions(&self, prefix: &str) -> Vec<String> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for row in self.rows.iter() {
+            for word in row.buf.split(|c: char| !c.is_ascii() || !is_word_byte(c as u8)) {
+                if word.len() > prefix.len() && word.starts_with(prefix) {
+                    *counts.entry(word).or_insert(0) += 1;
+                }
+            }
+        }
+        let mut words: Vec<(&str, usize)> = counts.into_iter().collect();
+        words.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        words.into_iter().map(|(word, _)| word.to_string()).collect()
+    }
+
+    pub fn move_cursor(&mut self, dir: CursorDir) {
+        match dir {
+            CursorDir::Up => self.cy = self.cy.saturating_sub(1),
+            CursorDir::Left => {
+                if self.cx > 0 {
+                    self.cx -= 1;
+                } else if self.cy > 0 {
+                    self.cy -= 1;
+                    self.cx = self.rows[self.cy].grapheme_count();
+                }
+            }
+            CursorDir::Down => {
+                if self.cy < self.rows.len() {
+                    self.cy += 1;
+                }
+            }
+            CursorDir::Right => {
+                if self.cy < self.rows.len() {
+                    let len = self.rows[self.cy].grapheme_count();
+                    if self.cx < len {
+                        self.cx += 1;
+                    } else if self.cx >= len {
+                        self.cy += 1;
+                        self.cx = 0;
+                    }
+                }
+            }
+            CursorDir::WordLeft => {
+                // Line breaks count as whitespace so the motion continues onto the previous line
+                loop {
+                    if self.cx == 0 {
+                        if self.cy == 0 {
+                            break;
+                        }
+                        self.cy -= 1;
+                        self.cx = self.rows.get(self.cy).map(Row::grapheme_count).unwrap_or(0);
+                    } else if self.is_space_at(self.cy, self.cx - 1) {
+                        self.cx -= 1;
+                    } else {
+                        break;
+                    }
+                }
+                while self.cx > 0 && !self.is_space_at(self.cy, self.cx - 1) {
+                    self.cx -= 1;
+                }
+            }
+            CursorDir::WordRight => {
+                loop {
+                    let len = self.rows.get(self.cy).map(Row::grapheme_count).unwrap_or(0);
+                    if self.cx >= len {
+                        if self.cy + 1 >= self.rows.len() {
+                            break;
+                        }
+                        self.cy += 1;
+                        self.cx = 0;
+                    } else if self.is_space_at(self.cy, self.cx) {
+                        self.cx += 1;
+                    } else {
+                        break;
+                    }
+                }
+                let len = self.rows.get(self.cy).map(Row::grapheme_count).unwrap_or(0);
+                while self.cx < len && !self.is_space_at(self.cy, self.cx) {
+                    self.cx += 1;
+                }
+            }
+            // At either end of a line the cursor goes over the line break, like Left and Right
+            CursorDir::TokenLeft => match self.rows.get(self.cy) {
+                Some(row) if self.cx > 0 => self.cx = row.prev_token_boundary(self.cx),
+                _ => self.move_cursor(CursorDir::Left),
+            },
+            CursorDir::TokenRight => match self.rows.get(self.cy) {
+                Some(row) if self.cx < row.grapheme_count() => self.cx = row.next_token_boundary(self.cx),
+                _ => self.move_cursor(CursorDir::Right),
+            },
+            CursorDir::ColumnLeft | CursorDir::ColumnRight | CursorDir::ColumnUp | CursorDir::ColumnDown => {
+                self.selection = None;
+                let (cy, cx) = (cmp::min(self.cy, self.rows.len().saturating_sub(1)), self.cx);
+                let mut sel = self.column_selection.unwrap_or(ColumnSelection {
+                    anchor_row: cy,
+                    anchor_col: cx,
+                    end_row: cy,
+                    end_col: cx,
+                });
+                match dir {
+                    CursorDir::ColumnLeft => sel.end_col = sel.end_col.saturating_sub(1),
+                    // May go past the end of the cursor's row, since a longer row may be in the block
+                    CursorDir::ColumnRight => sel.end_col += 1,
+                    CursorDir::ColumnUp => sel.end_row = sel.end_row.saturating_sub(1),
+                    _ => sel.end_row = cmp::min(sel.end_row + 1, self.rows.len().saturating_sub(1)),
+                }
+                self.set_column_selection(sel);
+            }
+        };
+        self.clamp_cx();
+    }
+
+    // Puts the cursor at the end the column selection was last moved at
+    fn set_column_selection(&mut self, sel: ColumnSelection) {
+        self.column_selection = Some(sel);
+        self.cy = sel.end_row;
+        self.cx = sel.end_col;
+        self.clamp_cx();
+    }
+
+    // The selected columns of each row of the column selection
+    pub fn column_text(&self) -> Option<Vec<String>> {
+        let (top, bottom, left, right) = self.column_selection?.rect();
+        let text = (top..=bottom)
+            .filter_map(|y| self.rows.get(y))
+            .map(|row| row.buf[row.byte_offset_of_grapheme(left)..row.byte_offset_of_grapheme(right)].to_string())
+            .collect();
+        Some(text)
+    }
+
+    // Replaces the selected columns of each row with the entry of `text` for it, as one undo
+    // step. Rows past the block take the rest of `text`, and rows too short to reach the left
+    // side are left alone. The selection becomes an empty column after the first entry
+    pub fn replace_columns(&mut self, text: &[String]) -> bool {
+        let sel = match self.column_selection {
+            Some(sel) => sel,
+            None => return false,
+        };
+        let (top, bottom, left, right) = sel.rect();
+        let height = cmp::max(bottom - top + 1, text.len());
+        let mut records = vec![];
+        for (i, y) in (top..top + height).enumerate() {
+            let row = match self.rows.get(y) {
+                Some(row) if row.grapheme_count() >= left => row,
+                _ => continue,
+            };
+            let old = row.buf[row.byte_offset_of_grapheme(left)..row.byte_offset_of_grapheme(right)].to_string();
+            let new = text.get(i).cloned().unwrap_or_default();
+            if !old.is_empty() || !new.is_empty() {
+                records.push(UndoRecord::Replace { row: y, col: left, old, new });
+            }
+        }
+        if records.is_empty() {
+            return false;
+        }
+        self.edit(UndoRecord::Group(records));
+        let col = left + text.first().map_or(0, |t| t.graphemes(true).count());
+        let last = cmp::min(top + height, self.rows.len()).saturating_sub(1);
+        let (anchor_row, end_row) = if sel.anchor_row <= sel.end_row { (top, last) } else { (last, top) };
+        self.set_column_selection(ColumnSelection { anchor_row, anchor_col: col, end_row, end_col: col });
+        true
+    }
+
+    // Types `ch` over the selected columns of every row
+    pub fn insert_in_columns(&mut self, ch: char) -> bool {
+        let (top, bottom, _, _) = match self.column_selection {
+            Some(sel) => sel.rect(),
+            None => return false,
+        };
+        self.replace_columns(&vec![ch.to_string(); bottom - top + 1])
+    }
+
+    // Deletes the selected columns of every row. When they are empty, the column before them is
+    // deleted instead, or the one after them with `forward`
+    pub fn delete_columns(&mut self, forward: bool) -> bool {
+        let sel = match self.column_selection {
+            Some(sel) => sel,
+            None => return false,
+        };
+        let mut wider = sel;
+        if sel.anchor_col == sel.end_col {
+            if forward {
+                wider.end_col += 1;
+            } else if sel.end_col > 0 {
+                wider.anchor_col -= 1;
+            } else {
+                return false;
+            }
+        }
+        self.column_selection = Some(wider);
+        if self.replace_columns(&[]) {
+            true
+        } else {
+            self.column_selection = Some(sel);
+            false
+        }
+    }
+
+    fn is_space_at(&self, cy: usize, cx: usize) -> bool {
+        self.rows[cy]
+            .grapheme_at(cx)
+            .is_some_and(|g| g.chars().all(char::is_whitespace))
+    }
+
+    pub fn find_match(
+        &self,
+        query: &str,
+        cx: usize,
+        cy: usize,
+        dir: SearchDir,
+        skip: bool,
+        ignore_case: bool,
+    ) -> Option<(usize, usize)> {
+        let len = self.rows.len();
+        if query.is_empty() || len == 0 {
+            return None;
+        }
+        let (cx, cy) = match dir {
+            SearchDir::Forward if cy >= len => (0, 0),
+            SearchDir::Backward if cy >= len => (usize::MAX, len - 1),
+            _ => (cx, cy),
+        };
+
+        // Visit every row once starting from the cursor row, then the cursor row again to wrap around
+        for i in 0..=len {
+            let (y, found) = match dir {
+                SearchDir::Forward => {
+                    let y = (cy + i) % len;
+                    let start = if i == 0 { cx + skip as usize } else { 0 };
+                    let row = &self.rows[y];
+                    let mut indices = match_ranges(&row.buf, query, ignore_case)
+                        .into_iter()
+                        .map(|(idx, _)| row.grapheme_index_of_byte(idx));
+                    (y, indices.find(|&x| x >= start))
+                }
+                SearchDir::Backward => {
+                    let y = (cy + len - i % len) % len;
+                    let end = if i == 0 { cx } else { usize::MAX };
+                    let row = &self.rows[y];
+                    let mut indices = match_ranges(&row.buf, query, ignore_case)
+                        .into_iter()
+                        .map(|(idx, _)| row.grapheme_index_of_byte(idx));
+                    (y, indices.rfind(|&x| x < end))
+                }
+            };
+            if let Some(x) = found {
+                return Some((x, y));
+            }
+        }
+        None
+    }
+
+    pub fn replace_next(&mut self, query: &str, replacement: &str, ignore_case: bool) -> bool {
+        let (x, y) = match self.find_match(query, self.cx, self.cy, SearchDir::Forward, false, ignore_case) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        let row = &self.rows[y];
+        let start = row.byte_offset_of_grapheme(x);
+        let old = match match_ranges(&row.buf[start..], query, ignore_case).first() {
+            Some(&(0, end)) => row.buf[start..start + end].to_string(),
+            _ => return false,
+        };
+        self.edit(UndoRecord::Replace {
+            row: y,
+            col: x,
+            old,
+            new: replacement.to_string(),
+        });
+        true
+    }
+
+    pub fn replace_all(&mut self, query: &str, replacement: &str, ignore_case: bool) -> usize {
+        let _ = self.load_all();
+        let (cx, cy) = (self.cx, self.cy);
+        let mut count = 0;
+        for y in 0..self.rows.len() {
+            let row = &self.rows[y];
+            let records: Vec<_> = match_ranges(&row.buf, query, ignore_case)
+                .into_iter()
+                .rev() // Replace from the end so that earlier positions stay valid
+                .map(|(start, end)| UndoRecord::Replace {
+                    row: y,
+                    col: row.grapheme_index_of_byte(start),
+                    old: row.buf[start..end].to_string(),
+                    new: replacement.to_string(),
+                })
+                .collect();
+            count += records.len();
+            for record in records {
+                self.edit(record);
+            }
+        }
+        self.cy = cy;
+        self.cx = cmp::min(cx, self.rows.get(cy).map(Row::grapheme_count).unwrap_or(0));
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(buffer: &EditorBuffer) -> Vec<&str> {
+        buffer.rows.iter().map(|row| row.buf.as_str()).collect()
+    }
+
+    #[test]
+    fn undo_back_to_the_saved_text_is_clean() {
+        let path = std::env::temp_dir().join(format!("rustitor-undo-{}.txt", std::process::id()));
+        let mut buffer = EditorBuffer::from_text("ab\n", Settings::default());
+        buffer.insert_char('x');
+        assert!(buffer.is_dirty());
+        buffer.write_file_as(&path).unwrap();
+        assert!(!buffer.is_dirty());
+        buffer.insert_char('y');
+        assert!(buffer.is_dirty());
+        assert!(buffer.undo());
+        assert!(!buffer.is_dirty());
+        assert!(buffer.undo());
+        assert_eq!(text(&buffer), ["ab"]);
+        assert!(buffer.is_dirty());
+        assert!(buffer.redo());
+        assert_eq!(text(&buffer), ["xab"]);
+        assert!(!buffer.is_dirty());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn undo_past_the_first_edit_does_nothing() {
+        let mut buffer = EditorBuffer::from_text("ab\n", Settings::default());
+        assert!(!buffer.undo());
+        buffer.cx = 2;
+        buffer.insert_char('c');
+        assert!(buffer.undo());
+        assert!(!buffer.undo());
+        assert_eq!(text(&buffer), ["ab"]);
+        assert_eq!((buffer.cx, buffer.cy), (2, 0));
+        assert!(!buffer.is_dirty());
+    }
+
+    #[test]
+    fn new_edit_clears_the_redo_stack() {
+        let mut buffer = EditorBuffer::from_text("ab\n", Settings::default());
+        buffer.insert_char('x');
+        assert!(buffer.undo());
+        buffer.insert_char('y');
+        assert!(!buffer.redo());
+        assert_eq!(text(&buffer), ["yab"]);
+        // Once the saved text is only in the redo stack, a new edit leaves no way back to it
+        let path = std::env::temp_dir().join(format!("rustitor-redo-{}.txt", std::process::id()));
+        buffer.write_file_as(&path).unwrap();
+        assert!(buffer.undo());
+        buffer.insert_char('z');
+        assert!(buffer.is_dirty());
+        assert!(buffer.undo());
+        assert!(buffer.is_dirty());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn enter_keeps_the_indent_and_adds_a_level_after_a_brace() {
+        // Line, whether tabs are expanded, and the line Enter at its end starts
+        let cases = [
+            ("    x", true, "    "),
+            ("    x", false, "    "),
+            ("\tx", true, "\t"),
+            ("\tx", false, "\t"),
+            ("x {", true, "    "),
+            ("x {", false, "\t"),
+            ("  if x {  ", true, "      "),
+            ("\tif x {", false, "\t\t"),
+            ("\t  if x {", true, "\t      "),
+            ("x", true, ""),
+        ];
+        for (line, expand_tabs, indent) in cases {
+            let settings = Settings { tab_stop: 4, expand_tabs, ..Default::default() };
+            let mut buffer = EditorBuffer::from_text(line, settings);
+            buffer.cx = buffer.rows[0].grapheme_count();
+            buffer.insert_line();
+            assert_eq!(text(&buffer), [line, indent], "{:?}", line);
+            assert_eq!((buffer.cx, buffer.cy), (indent.chars().count(), 1), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn enter_inside_a_line_indents_by_the_text_before_the_cursor() {
+        let mut buffer = EditorBuffer::from_text("  a {b", Settings::default());
+        buffer.cx = 1;
+        buffer.insert_line();
+        assert_eq!(text(&buffer), [" ", "  a {b"]);
+        // The text before the cursor opens a block, and the text after it keeps its own spaces
+        let mut buffer = EditorBuffer::from_text("  a { b", Settings::default());
+        buffer.cx = 5;
+        buffer.insert_line();
+        assert_eq!(text(&buffer), ["  a {", "  \t b"]);
+    }
+
+    #[test]
+    fn smart_home_toggles_between_the_indent_and_column_0() {
+        let mut buffer = EditorBuffer::from_text("  \tx y\n    \n\t \n\nabc\n", Settings::default());
+        // Mixed spaces and tabs before the text, from the end, then pressed again and again
+        buffer.cx = 6;
+        let mut seen = vec![];
+        for _ in 0..4 {
+            buffer.smart_home();
+            seen.push(buffer.cx);
+        }
+        assert_eq!(seen, [3, 0, 3, 0]);
+        // Lines of only whitespace, or nothing, have no indent to go to
+        for (y, len) in [(1, 4), (2, 2), (3, 0)] {
+            assert_eq!(buffer.rows[y].first_non_whitespace(), 0);
+            buffer.cy = y;
+            buffer.cx = len;
+            buffer.smart_home();
+            assert_eq!(buffer.cx, 0);
+            buffer.smart_home();
+            assert_eq!(buffer.cx, 0);
+        }
+        // Without an indent both presses go to column 0
+        buffer.cy = 4;
+        buffer.cx = 2;
+        buffer.smart_home();
+        assert_eq!(buffer.cx, 0);
+    }
+
+    #[test]
+    fn joining_three_lines_takes_two_rows_away() {
+        let mut buffer = EditorBuffer::from_text("one\ntwo\n\nthree\nlast\n", Settings::default());
+        let len = buffer.rows.len();
+        buffer.join_lines();
+        assert_eq!(buffer.cx, 3);
+        buffer.join_lines();
+        assert_eq!(buffer.rows.len(), len - 2);
+        // No space is added next to the empty line
+        assert_eq!(text(&buffer), ["one two", "three", "last"]);
+        assert_eq!((buffer.cx, buffer.cy), (7, 0));
+        assert!(buffer.undo() && buffer.undo());
+        assert_eq!(text(&buffer), ["one", "two", "", "three", "last"]);
+        // The last row has nothing to join
+        buffer.cy = 4;
+        buffer.join_lines();
+        assert_eq!(buffer.rows.len(), len);
+    }
+
+    #[test]
+    fn transpose_at_the_start_middle_end_and_across_lines() {
+        let mut buffer = EditorBuffer::from_text("abc\nxyz\n", Settings::default());
+        // Nothing before the cursor at the start of the file
+        buffer.transpose_chars();
+        assert_eq!((text(&buffer), buffer.cx), (vec!["abc", "xyz"], 0));
+
+        buffer.cx = 1;
+        buffer.transpose_chars();
+        assert_eq!((text(&buffer), buffer.cx), (vec!["bac", "xyz"], 2));
+
+        // At the end of the line the last two swap, and the cursor stays
+        buffer.cx = 3;
+        buffer.transpose_chars();
+        assert_eq!((text(&buffer), buffer.cx), (vec!["bca", "xyz"], 3));
+
+        // The last character of the line above trades places with the first of this one
+        buffer.cx = 0;
+        buffer.cy = 1;
+        buffer.transpose_chars();
+        assert_eq!(text(&buffer), ["bcx", "ayz"]);
+        assert!(buffer.undo());
+        assert_eq!(text(&buffer), ["bca", "xyz"]);
+        assert!(buffer.undo() && buffer.undo());
+        assert_eq!(text(&buffer), ["abc", "xyz"]);
+    }
+
+    #[test]
+    fn match_ranges_agree_for_short_and_long_queries() {
+        let text = "caf\u{e9} cafe caf\u{e9} caf\u{e9}caf\u{e9}";
+        for query in ["caf\u{e9}", "caf\u{e9} ", "caf\u{e9}caf\u{e9}", "caf", "e caf\u{e9}"] {
+            let expected: Vec<_> = text.match_indices(query).map(|(i, m)| (i, i + m.len())).collect();
+            assert_eq!(match_ranges(text, query, false), expected, "{:?}", query);
+        }
+        let buffer = EditorBuffer::from_text("x\n\tlonger query here\n", Settings::default());
+        let found = buffer.find_match("query here", 0, 0, SearchDir::Forward, false, false);
+        assert_eq!(found, Some((8, 1)));
+    }
+}
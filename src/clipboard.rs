@@ -0,0 +1,159 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClipboardProvider {
+    // OSC 52 first, then the first clipboard tool found on PATH
+    Auto,
+    Osc52,
+    Xclip,
+    Xsel,
+    Pbcopy,
+    // Never leave the editor
+    Internal,
+}
+
+impl ClipboardProvider {
+    pub fn from_name(name: &str) -> Option<ClipboardProvider> {
+        match name {
+            "auto" => Some(ClipboardProvider::Auto),
+            "osc52" => Some(ClipboardProvider::Osc52),
+            "xclip" => Some(ClipboardProvider::Xclip),
+            "xsel" => Some(ClipboardProvider::Xsel),
+            "pbcopy" => Some(ClipboardProvider::Pbcopy),
+            "internal" => Some(ClipboardProvider::Internal),
+            _ => None,
+        }
+    }
+
+    fn copy_command(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            ClipboardProvider::Xclip => Some(("xclip", &["-selection", "clipboard"])),
+            ClipboardProvider::Xsel => Some(("xsel", &["--clipboard", "--input"])),
+            ClipboardProvider::Pbcopy => Some(("pbcopy", &[])),
+            _ => None,
+        }
+    }
+
+    fn paste_command(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            ClipboardProvider::Xclip => Some(("xclip", &["-o", "-selection", "clipboard"])),
+            ClipboardProvider::Xsel => Some(("xsel", &["--clipboard", "--output"])),
+            ClipboardProvider::Pbcopy => Some(("pbpaste", &[])),
+            _ => None,
+        }
+    }
+}
+
+const TOOLS: &[ClipboardProvider] = &[
+    ClipboardProvider::Xclip,
+    ClipboardProvider::Xsel,
+    ClipboardProvider::Pbcopy,
+];
+
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+// The clipboard tool to fall back on when OSC 52 is not available
+pub fn find_tool(provider: ClipboardProvider) -> Option<ClipboardProvider> {
+    match provider {
+        ClipboardProvider::Auto => TOOLS
+            .iter()
+            .copied()
+            .find(|tool| tool.copy_command().is_some_and(|(program, _)| on_path(program))),
+        ClipboardProvider::Xclip | ClipboardProvider::Xsel | ClipboardProvider::Pbcopy => Some(provider),
+        ClipboardProvider::Osc52 | ClipboardProvider::Internal => None,
+    }
+}
+
+pub fn uses_osc52(provider: ClipboardProvider) -> bool {
+    provider == ClipboardProvider::Auto || provider == ClipboardProvider::Osc52
+}
+
+pub fn write_osc52<W: Write>(mut out: W, text: &str) -> io::Result<()> {
+    write!(out, "\x1b]52;c;{}\x07", base64_encode(text.as_bytes()))?;
+    out.flush()
+}
+
+pub fn request_osc52<W: Write>(mut out: W) -> io::Result<()> {
+    out.write_all(b"\x1b]52;c;?\x07")?;
+    out.flush()
+}
+
+pub fn copy_with_tool(tool: ClipboardProvider, text: &str) -> io::Result<()> {
+    let (program, args) = match tool.copy_command() {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    child.wait()?;
+    Ok(())
+}
+
+pub fn paste_with_tool(tool: ClipboardProvider) -> io::Result<Option<String>> {
+    let (program, args) = match tool.paste_command() {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+    if !Path::new(program).is_absolute() && !on_path(program) {
+        return Ok(None);
+    }
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// Returns None on malformed input
+pub fn base64_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() / 4 * 3);
+    let mut n = 0u32;
+    let mut bits = 0;
+    for &c in data.iter().filter(|&&c| c != b'=') {
+        let v = BASE64.iter().position(|&b| b == c)? as u32;
+        n = n << 6 | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+            n &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,523 @@
+use crate::clipboard::ClipboardProvider;
+use crate::highlight::ColorScheme;
+use crate::settings::Settings;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// A key which can be named in the [keybindings] table
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Key {
+    Ctrl(u8),
+    Alt(u8),
+    CtrlAlt(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    AltUp,
+    AltDown,
+    AltLeft,
+    AltRight,
+    CtrlLeft,
+    CtrlRight,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Delete,
+    BackTab,
+    // Function keys by number
+    F(u8),
+    ShiftF(u8),
+    CtrlF(u8),
+}
+
+fn function_key(name: &str) -> Option<u8> {
+    match name.strip_prefix('f')?.parse() {
+        Ok(n @ 1..=12) => Some(n),
+        _ => None,
+    }
+}
+
+impl Key {
+    pub fn from_name(name: &str) -> Option<Key> {
+        if let Some(n) = name.strip_prefix("shift-").and_then(function_key) {
+            return Some(Key::ShiftF(n));
+        }
+        if let Some(n) = name.strip_prefix("ctrl-").and_then(function_key) {
+            return Some(Key::CtrlF(n));
+        }
+        if let Some(n) = function_key(name) {
+            return Some(Key::F(n));
+        }
+        if let Some(c) = name.strip_prefix("ctrl-alt-") {
+            return match c.as_bytes() {
+                [c @ b'a'..=b'z'] if *c != b'm' => Some(Key::CtrlAlt(*c)),
+                _ => None,
+            };
+        }
+        match name {
+            "ctrl-left" => return Some(Key::CtrlLeft),
+            "ctrl-right" => return Some(Key::CtrlRight),
+            _ => {}
+        }
+        if let Some(c) = name.strip_prefix("ctrl-") {
+            return match c.as_bytes() {
+                [c @ (b'a'..=b'z' | b'/')] => Some(Key::Ctrl(*c)),
+                _ => None,
+            };
+        }
+        if let Some(c) = name.strip_prefix("alt-") {
+            match c {
+                "up" => return Some(Key::AltUp),
+                "down" => return Some(Key::AltDown),
+                "left" => return Some(Key::AltLeft),
+                "right" => return Some(Key::AltRight),
+                _ => {}
+            }
+            return match c.as_bytes() {
+                [c @ 0x21..=0x7e] => Some(Key::Alt(*c)),
+                _ => None,
+            };
+        }
+        match name {
+            "up" => Some(Key::Up),
+            "down" => Some(Key::Down),
+            "left" => Some(Key::Left),
+            "right" => Some(Key::Right),
+            "home" => Some(Key::Home),
+            "end" => Some(Key::End),
+            "pageup" => Some(Key::PageUp),
+            "pagedown" => Some(Key::PageDown),
+            "delete" => Some(Key::Delete),
+            "backtab" => Some(Key::BackTab),
+            _ => None,
+        }
+    }
+
+    // The name `from_name` reads back
+    pub fn name(self) -> String {
+        match self {
+            Key::Ctrl(b) => format!("ctrl-{}", b as char),
+            Key::Alt(b) => format!("alt-{}", b as char),
+            Key::CtrlAlt(b) => format!("ctrl-alt-{}", b as char),
+            Key::Up => "up".to_string(),
+            Key::Down => "down".to_string(),
+            Key::Left => "left".to_string(),
+            Key::Right => "right".to_string(),
+            Key::AltUp => "alt-up".to_string(),
+            Key::AltDown => "alt-down".to_string(),
+            Key::AltLeft => "alt-left".to_string(),
+            Key::AltRight => "alt-right".to_string(),
+            Key::CtrlLeft => "ctrl-left".to_string(),
+            Key::CtrlRight => "ctrl-right".to_string(),
+            Key::Home => "home".to_string(),
+            Key::End => "end".to_string(),
+            Key::PageUp => "pageup".to_string(),
+            Key::PageDown => "pagedown".to_string(),
+            Key::Delete => "delete".to_string(),
+            Key::BackTab => "backtab".to_string(),
+            Key::F(n) => format!("f{}", n),
+            Key::ShiftF(n) => format!("shift-f{}", n),
+            Key::CtrlF(n) => format!("ctrl-f{}", n),
+        }
+    }
+}
+
+// Commands which can be bound, with the key that runs them by default and what they do
+const COMMANDS: &[(&str, Key, &str)] = &[
+    ("help", Key::F(1), "Show this help"),
+    ("open", Key::Ctrl(b'o'), "Open a file, tab completes the path"),
+    ("save", Key::Ctrl(b's'), "Save the file"),
+    ("save_as", Key::Alt(b's'), "Save under another name"),
+    ("save_copy", Key::Alt(b'S'), "Save a copy under another name"),
+    ("quit", Key::Ctrl(b'q'), "Quit"),
+    ("undo", Key::Ctrl(b'z'), "Undo"),
+    ("redo", Key::Ctrl(b'y'), "Redo"),
+    ("find", Key::Ctrl(b'f'), "Search forward"),
+    ("find_backward", Key::Ctrl(b'r'), "Search backward"),
+    ("find_next", Key::F(3), "Next match of the last search"),
+    ("find_previous", Key::ShiftF(3), "Previous match of the last search"),
+    ("toggle_bookmark", Key::CtrlF(2), "Set or clear a bookmark on the line"),
+    ("next_bookmark", Key::F(2), "Go to the next bookmark"),
+    ("previous_bookmark", Key::ShiftF(2), "Go to the previous bookmark"),
+    ("replace", Key::Alt(b'r'), "Search and replace"),
+    ("command_line", Key::Alt(b':'), "Run a command, !cmd for the shell"),
+    ("format", Key::CtrlAlt(b'f'), "Format the file or the selection"),
+    ("go_to_line", Key::Ctrl(b'g'), "Go to a line"),
+    ("copy", Key::Ctrl(b'c'), "Copy the selection"),
+    ("cut", Key::Ctrl(b'x'), "Cut, or start a ctrl-x command"),
+    ("paste", Key::Ctrl(b'v'), "Paste"),
+    ("yank", Key::Alt(b'y'), "Paste what was cut, again to cycle"),
+    ("kill_line", Key::Ctrl(b'k'), "Cut to the end of the line"),
+    ("kill_to_line_start", Key::CtrlAlt(b'u'), "Cut to the start of the line"),
+    ("universal_argument", Key::Ctrl(b'u'), "Count for the next command, 4 unless digits follow"),
+    ("duplicate_line", Key::Ctrl(b'd'), "Duplicate the line"),
+    ("join_lines", Key::Ctrl(b'j'), "Join the next line to this one"),
+    ("transpose", Key::Alt(b't'), "Swap the characters around the cursor"),
+    ("toggle_comment", Key::Ctrl(b'/'), "Comment or uncomment the lines"),
+    ("toggle_line_numbers", Key::Ctrl(b'n'), "Show or hide line numbers"),
+    ("toggle_stats", Key::CtrlAlt(b'i'), "Count lines, words and characters"),
+    ("toggle_selection", Key::Alt(b'v'), "Start or drop a selection"),
+    ("new_buffer", Key::Ctrl(b't'), "Open an empty buffer"),
+    ("close_buffer", Key::Ctrl(b'w'), "Close the buffer"),
+    ("next_buffer", Key::Alt(b'n'), "Switch to the next buffer"),
+    ("previous_buffer", Key::Alt(b'p'), "Switch to the previous buffer"),
+    ("toggle_sidebar", Key::Ctrl(b'b'), "Show or focus the file browser"),
+    ("line_start", Key::Home, "Go to the start of the line"),
+    ("line_end", Key::End, "Go to the end of the line"),
+    ("page_up", Key::PageUp, "Scroll up a page"),
+    ("page_down", Key::PageDown, "Scroll down a page"),
+    ("word_left", Key::AltLeft, "Go to the previous word"),
+    ("word_right", Key::AltRight, "Go to the next word"),
+    ("token_left", Key::CtrlLeft, "Go to the previous word or run of symbols"),
+    ("token_right", Key::CtrlRight, "Go to the next word or run of symbols"),
+    ("move_line_up", Key::AltUp, "Move the line up"),
+    ("move_line_down", Key::AltDown, "Move the line down"),
+    ("delete_forward", Key::Delete, "Delete the character under the cursor"),
+    ("unindent", Key::BackTab, "Unindent the line"),
+];
+
+// Keys wired into the editor, which can't be bound to anything else
+const FIXED_KEYS: &[(&str, &str)] = &[
+    ("shift-arrows", "Select text"),
+    ("alt-shift-arrows", "Select a block of columns"),
+    ("tab", "Insert a tab, or indent"),
+    ("enter", "Split the line"),
+    ("backspace", "Delete the character before the cursor"),
+    ("esc", "Drop the selection"),
+    ("ctrl-space", "Complete the word before the cursor"),
+    ("alt-\"", "Name a register for the next copy, cut or paste"),
+    ("alt-digits", "Repeat the next command that many times"),
+    ("ctrl-g ctrl-g", "Go to the top of the file"),
+    ("ctrl-g ctrl-e", "Go to the end of the file"),
+    ("ctrl-x (", "Start recording a macro"),
+    ("ctrl-x )", "Stop recording the macro"),
+    ("ctrl-x e", "Replay the macro"),
+    ("ctrl-x enter", "Switch between LF and CRLF line endings"),
+    ("ctrl-x ctrl-z", "Suspend to the shell, fg comes back"),
+    ("ctrl-x ctrl-f", "Open a file, like ctrl-o"),
+    ("ctrl-x ctrl-s", "Save the file, like ctrl-s"),
+    ("ctrl-x ctrl-c", "Quit, like ctrl-q"),
+    ("ctrl-x b", "Switch to a buffer by name"),
+    ("ctrl-x k", "Close the buffer, like ctrl-w"),
+];
+
+// Every command with the keys that run it, the ones from the config file included
+pub struct KeyBindingTable {
+    pub entries: Vec<(Vec<Key>, &'static str, &'static str)>,
+}
+
+impl KeyBindingTable {
+    // `keybindings` holds each key from the config with the default key of its command
+    pub fn new(keybindings: &[(Key, Key)]) -> KeyBindingTable {
+        let entries = COMMANDS
+            .iter()
+            .map(|&(command, key, description)| {
+                let mut keys = vec![key];
+                keys.extend(keybindings.iter().filter(|(_, to)| *to == key).map(|&(from, _)| from));
+                (keys, command, description)
+            })
+            .collect();
+        KeyBindingTable { entries }
+    }
+
+    // The text of the help buffer
+    pub fn help_lines(&self) -> Vec<String> {
+        let mut lines = vec!["Keys".to_string(), String::new()];
+        for (keys, command, description) in &self.entries {
+            let names: Vec<String> = keys.iter().map(|key| key.name()).collect();
+            lines.push(format!("{:<16} {:<20} {}", names.join(", "), command, description));
+        }
+        lines.push(String::new());
+        lines.push("Keys which can't be bound".to_string());
+        lines.push(String::new());
+        for (key, description) in FIXED_KEYS {
+            lines.push(format!("{:<16} {}", key, description));
+        }
+        lines
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ConfigError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum Value {
+    Bool(bool),
+    Integer(i64),
+    String(String),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "a boolean",
+            Value::Integer(_) => "an integer",
+            Value::String(_) => "a string",
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Config {
+    pub settings: Settings,
+    // Keys of the settings the config gave a value, by their names in ~/.rustitorrc
+    pub keys: Vec<String>,
+    // Each key is bound to the default key of the command it runs
+    pub keybindings: Vec<(Key, Key)>,
+    // Formatter command for each file type, from keys such as rust_formatter
+    pub formatters: Vec<(String, String)>,
+    // Auto-save writes into this directory instead of over the files themselves
+    pub auto_save_path: Option<PathBuf>,
+}
+
+// Removes a comment, leaving any # inside a string alone
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+pub fn parse_string(s: &str) -> Option<String> {
+    if let Some(inner) = s.strip_prefix('\'') {
+        let inner = inner.strip_suffix('\'')?;
+        return if inner.contains('\'') { None } else { Some(inner.to_string()) };
+    }
+    let inner = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                '"' => '"',
+                '\\' => '\\',
+                _ => return None,
+            }),
+            '"' => return None,
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+fn parse_value(s: &str) -> Option<Value> {
+    match s {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => {}
+    }
+    if s.starts_with('"') || s.starts_with('\'') {
+        return parse_string(s).map(Value::String);
+    }
+    let digits = s.strip_prefix('+').unwrap_or(s);
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return None;
+    }
+    digits.replace('_', "").parse().ok().map(Value::Integer)
+}
+
+fn parse_key(s: &str) -> Option<String> {
+    if s.starts_with('"') || s.starts_with('\'') {
+        return parse_string(s);
+    }
+    let bare = !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+    if bare {
+        Some(s.to_string())
+    } else {
+        None
+    }
+}
+
+fn expect_bool(value: &Value) -> Result<bool, String> {
+    match *value {
+        Value::Bool(b) => Ok(b),
+        ref value => Err(format!("expected a boolean, found {}", value.type_name())),
+    }
+}
+
+fn expect_count(value: &Value) -> Result<usize, String> {
+    match *value {
+        Value::Integer(n) if n >= 0 => Ok(n as usize),
+        Value::Integer(n) => Err(format!("expected a non-negative integer, found {}", n)),
+        ref value => Err(format!("expected an integer, found {}", value.type_name())),
+    }
+}
+
+fn expect_name<T, F: FnOnce(&str) -> Option<T>>(value: &Value, from_name: F) -> Result<T, String> {
+    match value {
+        Value::String(s) => from_name(s).ok_or_else(|| format!("unknown value: {}", s)),
+        value => Err(format!("expected a string, found {}", value.type_name())),
+    }
+}
+
+impl Config {
+    // Reads the subset of TOML the config needs: tables, bare or quoted keys, and boolean,
+    // integer and string values
+    pub fn from_toml_str(s: &str) -> Result<Config, Vec<ConfigError>> {
+        let mut config = Config {
+            settings: Settings::default(),
+            keys: vec![],
+            keybindings: vec![],
+            formatters: vec![],
+            auto_save_path: None,
+        };
+        let mut errors = vec![];
+        let mut section = String::new();
+        let mut seen: Vec<(String, String)> = vec![];
+
+        for (i, line) in s.lines().enumerate() {
+            let line_no = i + 1;
+            let mut error = |message: String| errors.push(ConfigError { line: line_no, message });
+
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                match name.strip_suffix(']').map(str::trim) {
+                    Some(name @ ("editor" | "display" | "keybindings")) => section = name.to_string(),
+                    Some(name) => {
+                        error(format!("unknown table: [{}]", name));
+                        section = String::new();
+                    }
+                    None => error("expected ] at the end of the table header".to_string()),
+                }
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => {
+                    error("expected key = value".to_string());
+                    continue;
+                }
+            };
+            let key = match parse_key(key) {
+                Some(key) => key,
+                None => {
+                    error(format!("invalid key: {}", key));
+                    continue;
+                }
+            };
+            let value = match parse_value(value) {
+                Some(value) => value,
+                None => {
+                    error(format!("invalid value for {}: {}", key, value));
+                    continue;
+                }
+            };
+            if section.is_empty() {
+                error(format!("{} must be inside a table", key));
+                continue;
+            }
+            if seen.contains(&(section.clone(), key.clone())) {
+                error(format!("{} is defined twice", key));
+                continue;
+            }
+            seen.push((section.clone(), key.clone()));
+
+            if let Err(message) = config.set(&section, &key, &value) {
+                error(message);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: &Value) -> Result<(), String> {
+        let settings = &mut self.settings;
+        match (section, key) {
+            ("editor", "tab_stop") => {
+                settings.tab_stop = expect_count(value)?;
+                if settings.tab_stop == 0 {
+                    return Err("tab_stop must be at least 1".to_string());
+                }
+            }
+            ("editor", "expand_tabs") => settings.expand_tabs = expect_bool(value)?,
+            ("editor", "scroll_off") => settings.scroll_off = expect_count(value)?,
+            ("editor", "side_scroll_off") => settings.side_scroll_off = expect_count(value)?,
+            ("editor", "mouse_support") => settings.mouse_support = expect_bool(value)?,
+            ("editor", "mouse_scroll_lines") => settings.mouse_scroll_lines = expect_count(value)?,
+            ("editor", "crlf") => settings.crlf = expect_bool(value)?,
+            ("editor", "trim_trailing_whitespace") => settings.trim_trailing_whitespace = expect_bool(value)?,
+            ("editor", "insert_final_newline") => settings.insert_final_newline = expect_bool(value)?,
+            ("editor", "clipboard") => settings.clipboard_provider = expect_name(value, ClipboardProvider::from_name)?,
+            ("editor", "lsp") => settings.lsp = expect_bool(value)?,
+            ("editor", "auto_save") => {
+                let seconds = expect_count(value)? as u64;
+                settings.auto_save_interval = Some(Duration::from_secs(seconds)).filter(|_| seconds > 0);
+            }
+            ("editor", "auto_save_path") => match value {
+                Value::String(path) => self.auto_save_path = Some(PathBuf::from(path)),
+                value => return Err(format!("expected a string, found {}", value.type_name())),
+            },
+            ("editor", key) if key.ends_with("_formatter") => {
+                let command = match value {
+                    Value::String(command) => command.clone(),
+                    value => return Err(format!("expected a string, found {}", value.type_name())),
+                };
+                let file_type = key.trim_end_matches("_formatter").to_string();
+                self.formatters.push((file_type, command));
+            }
+            ("display", "show_line_numbers") => settings.show_line_numbers = expect_bool(value)?,
+            ("display", "color_scheme") => settings.color_scheme = expect_name(value, ColorScheme::from_name)?,
+            ("display", "highlight_current_line") => settings.highlight_current_line = expect_bool(value)?,
+            ("display", "show_stats") => settings.show_stats = expect_bool(value)?,
+            ("display", "show_scrollbar") => settings.show_scrollbar = expect_bool(value)?,
+            ("display", "show_minimap") => settings.show_minimap = expect_bool(value)?,
+            ("display", "current_line_color") => {
+                settings.current_line_color = match expect_count(value)? {
+                    n @ 0..=255 => n as u8,
+                    n => return Err(format!("current_line_color must be a color from 0 to 255, found {}", n)),
+                }
+            }
+            ("display", "ruler") => settings.ruler_column = Some(expect_count(value)?).filter(|&n| n > 0),
+            ("display", "show_trailing_whitespace") => settings.show_trailing_whitespace = expect_bool(value)?,
+            ("display", "word_wrap") => settings.word_wrap = expect_bool(value)?,
+            ("display", "set_title") => settings.set_title = expect_bool(value)?,
+            ("display", "show_welcome") => settings.show_welcome = expect_bool(value)?,
+            ("keybindings", _) => {
+                let from = Key::from_name(key).ok_or_else(|| format!("unknown key name: {}", key))?;
+                let to = expect_name(value, |name| {
+                    COMMANDS.iter().find(|(command, ..)| *command == name).map(|&(_, key, _)| key)
+                })?;
+                self.keybindings.push((from, to));
+            }
+            (section, key) => return Err(format!("unknown setting in [{}]: {}", section, key)),
+        }
+        if section != "keybindings" {
+            self.keys.push(key.to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DiffMark {
+    Added,
+    Changed,
+    // Lines were removed just above this one
+    Removed,
+}
+
+impl DiffMark {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            DiffMark::Added => "\x1b[32m+\x1b[m",
+            DiffMark::Changed => "\x1b[33m~\x1b[m",
+            DiffMark::Removed => "\x1b[31m-\x1b[m",
+        }
+    }
+}
+
+// Parses `start,count` from a hunk header. The count is 1 when omitted
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let mut parts = range.splitn(2, ',');
+    let start = parts.next()?.parse().ok()?;
+    let count = match parts.next() {
+        Some(count) => count.parse().ok()?,
+        None => 1,
+    };
+    Some((start, count))
+}
+
+// Marks by row index from a diff made with no context lines
+fn parse_diff(diff: &str) -> HashMap<usize, DiffMark> {
+    let mut marks = HashMap::new();
+    for line in diff.lines() {
+        let mut words = match line.strip_prefix("@@ ") {
+            Some(rest) => rest.split(' '),
+            None => continue,
+        };
+        let old = words.next().and_then(|w| w.strip_prefix('-')).and_then(parse_range);
+        let new = words.next().and_then(|w| w.strip_prefix('+')).and_then(parse_range);
+        let ((_, old_count), (new_start, new_count)) = match (old, new) {
+            (Some(old), Some(new)) => (old, new),
+            _ => continue,
+        };
+
+        if new_count == 0 {
+            // With nothing added, new_start is the line before the removed ones
+            marks.insert(new_start, DiffMark::Removed);
+            continue;
+        }
+        for i in 0..new_count {
+            let mark = if i < old_count { DiffMark::Changed } else { DiffMark::Added };
+            marks.insert(new_start - 1 + i, mark);
+        }
+    }
+    marks
+}
+
+// None when git isn't available or the file isn't in a repository
+pub fn git_diff(path: &Path) -> Option<HashMap<usize, DiffMark>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["diff", "--no-color", "--no-ext-diff", "-U0", "HEAD", "--"])
+        .arg(path.file_name()?)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(parse_diff(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// Differences beyond this many edits are not worked out line by line
+const MAX_EDITS: usize = 1000;
+
+// Flags the lines of `a` and of `b` which are not common to both, using Myers' algorithm for a
+// shortest edit script
+pub fn line_diff<T: PartialEq>(a: &[T], b: &[T]) -> (Vec<bool>, Vec<bool>) {
+    let mut removed = vec![false; a.len()];
+    let mut added = vec![false; b.len()];
+    // Common lines at the start and end don't take part in the search
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let (n, m) = (a_mid.len() as isize, b_mid.len() as isize);
+
+    let max = cmp::min(a_mid.len() + b_mid.len(), MAX_EDITS) as isize;
+    let offset = max + 1;
+    // v[k + offset] is the furthest x reached on diagonal k
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace = vec![];
+    let mut found = false;
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) { v[i + 1] } else { v[i - 1] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && a_mid[x as usize] == b_mid[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                found = true;
+                break 'search;
+            }
+        }
+    }
+    if !found {
+        removed[prefix..a.len() - suffix].fill(true);
+        added[prefix..b.len() - suffix].fill(true);
+        return (removed, added);
+    }
+
+    // Walk back from the end, marking every step that isn't along a diagonal
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let i = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[i - 1] < v[i + 1]) { k + 1 } else { k - 1 };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+        }
+        if d == 0 {
+            break;
+        }
+        if x > prev_x {
+            removed[prefix + prev_x as usize] = true;
+        } else {
+            added[prefix + prev_y as usize] = true;
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    (removed, added)
+}
@@ -0,0 +1,186 @@
+use crate::args::Args;
+use crate::diff::{self, DiffMark};
+use crate::settings::Settings;
+use crate::render::AnsiRenderer;
+use crate::{Editor, InputSeq, ScreenBuffer};
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::iter;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+type Pane = Editor<iter::Empty<io::Result<InputSeq>>>;
+
+const REMOVED_COLOR: u8 = 52;
+const ADDED_COLOR: u8 = 22;
+
+// Two files side by side, read-only, with the lines only in the left one in red and the lines
+// only in the right one in green. Each side is an editor of its own which never reads input;
+// keys are read here and only the ones that move around are passed on
+pub struct DiffView<I: Iterator<Item = io::Result<InputSeq>>> {
+    input: I,
+    panes: [Pane; 2],
+    // Pane which gets the keys
+    active: usize,
+    screen_rows: usize,
+    screen_cols: usize,
+    // Lines which differ on each side
+    changes: (usize, usize),
+    // Draws the rows and the status bar of each pane
+    renderer: AnsiRenderer,
+    screen: ScreenBuffer,
+    resized: Arc<AtomicBool>,
+}
+
+// Keys which only move the cursor or scroll
+fn is_movement(seq: &InputSeq) -> bool {
+    use InputSeq::*;
+    matches!(
+        seq,
+        UpKey | DownKey | LeftKey | RightKey | PageUpKey | PageDownKey | HomeKey | EndKey | AltLeft | AltRight
+            | CtrlLeft | CtrlRight | ScrollUp | ScrollDown
+    ) || matches!(seq, Key(b'a' | b'e' | b'p', true))
+}
+
+impl<I: Iterator<Item = io::Result<InputSeq>>> DiffView<I> {
+    pub fn new(window_size: Option<(usize, usize)>, input: I, args: &Args, settings: Settings) -> DiffView<I> {
+        let (w, h) = window_size.unwrap_or((80, 24));
+        let mut settings = settings;
+        settings.read_only = true;
+        // Lines have to stay lined up with the other side
+        settings.word_wrap = false;
+        // Drawn at the right edge of the screen, which only the right pane reaches
+        settings.show_scrollbar = false;
+        settings.show_minimap = false;
+        let pane = || {
+            let mut pane = Editor::new(None, iter::empty(), settings);
+            pane.command_line = args.clone();
+            pane
+        };
+        let mut view = DiffView {
+            input,
+            panes: [pane(), pane()],
+            active: 0,
+            screen_rows: 0,
+            screen_cols: 0,
+            changes: (0, 0),
+            renderer: AnsiRenderer::default(),
+            screen: ScreenBuffer::default(),
+            resized: Arc::new(AtomicBool::new(false)),
+        };
+        view.resize(w, h);
+        view
+    }
+
+    // The left pane takes the odd column when the width doesn't split evenly
+    fn resize(&mut self, w: usize, h: usize) {
+        self.screen_cols = w;
+        self.screen_rows = h.saturating_sub(2);
+        let left = w.saturating_sub(1) / 2;
+        let widths = [left, w.saturating_sub(left + 1)];
+        for (pane, width) in self.panes.iter_mut().zip(widths) {
+            pane.screen_cols = width;
+            pane.screen_rows = self.screen_rows;
+        }
+        self.screen.invalidate();
+    }
+
+    pub fn open<P: AsRef<Path>>(&mut self, left: P, right: P) -> io::Result<()> {
+        for (pane, path) in self.panes.iter_mut().zip([left, right]) {
+            pane.open_file(path)?;
+            pane.buffer_mut().load_all()?;
+        }
+        let [a, b] = &self.panes;
+        let (a_lines, b_lines): (Vec<_>, Vec<_>) = (
+            a.buffer().rows.iter().map(|row| row.buf.as_str()).collect(),
+            b.buffer().rows.iter().map(|row| row.buf.as_str()).collect(),
+        );
+        let (removed, added) = diff::line_diff(&a_lines, &b_lines);
+        self.changes = (removed.iter().filter(|&&r| r).count(), added.iter().filter(|&&a| a).count());
+        for (pane, (only_here, mark, color)) in self.panes.iter_mut().zip([
+            (removed, DiffMark::Removed, REMOVED_COLOR),
+            (added, DiffMark::Added, ADDED_COLOR),
+        ]) {
+            let lines: Vec<usize> = only_here.iter().enumerate().filter(|(_, &d)| d).map(|(y, _)| y).collect();
+            pane.line_backgrounds = lines.iter().map(|&y| (y, color)).collect();
+            pane.buffer_mut().diff = Some(lines.iter().map(|&y| (y, mark)).collect::<HashMap<_, _>>());
+        }
+        Ok(())
+    }
+
+    // The other pane shows the same rows as the active one, as far as it has them
+    fn sync_scroll(&mut self) {
+        self.panes[self.active].setup_scroll();
+        let rowoff = self.panes[self.active].buffer().rowoff;
+        let other = self.panes[1 - self.active].buffer_mut();
+        other.rowoff = cmp::min(rowoff, other.rows.len().saturating_sub(1));
+    }
+
+    fn refresh_screen(&mut self) -> io::Result<()> {
+        let mut buf = vec![];
+        buf.write_all(b"\x1b[?25l")?;
+        for (i, pane) in self.panes.iter_mut().enumerate() {
+            pane.settings.highlight_current_line = i == self.active;
+        }
+        let left_width = self.panes[0].screen_cols;
+        let left = self.renderer.draw_rows(&self.panes[0])?;
+        let right = self.renderer.draw_rows(&self.panes[1])?;
+        let mut rows = vec![];
+        for (y, (mut row, right)) in left.into_iter().zip(right).enumerate() {
+            // The left row may clear to the end of the line, so the right one is written after it
+            write!(row, "\x1b[{};{}H│", y + 1, left_width + 1)?;
+            row.extend(right);
+            rows.push(row);
+        }
+        let mut status_bar = vec![];
+        self.renderer.draw_status_bar(&self.panes[0], &mut status_bar)?;
+        write!(status_bar, "\x1b[{};{}H\x1b[7m \x1b[m", self.screen_rows + 1, left_width + 1)?;
+        self.renderer.draw_status_bar(&self.panes[1], &mut status_bar)?;
+        rows.push(status_bar);
+        let (removed, added) = self.changes;
+        let msg = format!("-{} +{} lines | Tab = switch side | q = quit", removed, added);
+        let mut message_bar: Vec<u8> = msg.chars().take(self.screen_cols).collect::<String>().into_bytes();
+        message_bar.extend_from_slice(b"\x1b[K");
+        rows.push(message_bar);
+
+        let pane = &self.panes[self.active];
+        let (row, col) = pane.buffer().screen_cursor(pane.text_cols());
+        let pane_left = if self.active == 0 { 0 } else { left_width + 1 };
+        self.screen.update(rows, row, &mut buf)?;
+        write!(buf, "\x1b[{};{}H", row + 1, pane_left + pane.text_left() + col + 1)?;
+        buf.write_all(b"\x1b[?25h")?;
+
+        let mut stdout = io::stdout();
+        stdout.write_all(&buf)?;
+        stdout.flush()
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        signal_hook::flag::register(signal_hook::consts::SIGWINCH, Arc::clone(&self.resized))?;
+        self.sync_scroll();
+        self.refresh_screen()?;
+        while let Some(seq) = self.input.next() {
+            if self.resized.swap(false, Ordering::Relaxed) {
+                if let Some((w, h)) = term_size::dimensions_stdout() {
+                    self.resize(w, h);
+                }
+            }
+            match seq? {
+                InputSeq::Unidentified => continue,
+                InputSeq::Key(b'q', false) | InputSeq::Key(b'q', true) => break,
+                InputSeq::Key(b'i', true) => self.active = 1 - self.active,
+                seq if is_movement(&seq) => {
+                    self.panes[self.active].run_command(seq, None)?;
+                }
+                _ => {}
+            }
+            self.sync_scroll();
+            self.refresh_screen()?;
+        }
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[2J\x1b[H")?;
+        stdout.flush()
+    }
+}
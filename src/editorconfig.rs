@@ -0,0 +1,280 @@
+use crate::settings::Settings;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Properties from the .editorconfig files which apply to one file. None when unset
+#[derive(Default, Debug)]
+pub struct EditorConfig {
+    indent_style: Option<String>,
+    // "tab" means the same as tab_width
+    indent_size: Option<String>,
+    tab_width: Option<usize>,
+    end_of_line: Option<String>,
+    trim_trailing_whitespace: Option<bool>,
+    insert_final_newline: Option<bool>,
+}
+
+fn absolute(path: &Path) -> Option<PathBuf> {
+    if let Ok(path) = fs::canonicalize(path) {
+        return Some(path);
+    }
+    // The file may not exist yet
+    let name = path.file_name()?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::canonicalize(dir).ok()?,
+        _ => env::current_dir().ok()?,
+    };
+    Some(dir.join(name))
+}
+
+// A section's glob and its properties in file order
+type Section = (String, Vec<(String, String)>);
+
+// Sections of one .editorconfig file and whether it has root = true
+fn read_file(path: &Path) -> Option<(bool, Vec<Section>)> {
+    let text = fs::read_to_string(path).ok()?;
+    let mut root = false;
+    let mut sections: Vec<Section> = vec![];
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((glob.to_string(), vec![]));
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim().to_string()),
+            None => continue,
+        };
+        match sections.last_mut() {
+            Some((_, props)) => props.push((key, value)),
+            None if key == "root" => root = value.eq_ignore_ascii_case("true"),
+            None => {}
+        }
+    }
+    Some((root, sections))
+}
+
+// Matches the glob syntax of the EditorConfig spec: *, **, ?, [set], [!set], {a,b} and {n..m}
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_chars(&pattern, &text)
+}
+
+// Index of the `close` matching the opening bracket at pattern[0]
+fn find_close(pattern: &[char], close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < pattern.len() {
+        match pattern[i] {
+            '\\' => i += 1,
+            c if c == pattern[0] => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn match_set(set: &[char], c: char) -> bool {
+    let (negated, set) = match set.first() {
+        Some('!') => (true, &set[1..]),
+        _ => (false, set),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            found |= set[i] <= c && c <= set[i + 2];
+            i += 3;
+        } else {
+            found |= set[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+fn parse_num_range(inner: &[char]) -> Option<(i64, i64)> {
+    let inner: String = inner.iter().collect();
+    let (lo, hi) = inner.split_once("..")?;
+    Some((lo.parse().ok()?, hi.parse().ok()?))
+}
+
+// Alternatives separated by commas at the top level of a brace group
+fn split_alternatives(inner: &[char]) -> Vec<&[char]> {
+    let mut alternatives = vec![];
+    let (mut depth, mut start, mut i) = (0, 0, 0);
+    while i < inner.len() {
+        match inner[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    alternatives.push(&inner[start..]);
+    alternatives
+}
+
+fn match_chars(pattern: &[char], text: &[char]) -> bool {
+    let p = match pattern.first() {
+        Some(&p) => p,
+        None => return text.is_empty(),
+    };
+    match p {
+        '*' if pattern.get(1) == Some(&'*') => (0..=text.len()).any(|i| match_chars(&pattern[2..], &text[i..])),
+        '*' => {
+            let limit = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+            (0..=limit).any(|i| match_chars(&pattern[1..], &text[i..]))
+        }
+        '?' => !text.is_empty() && text[0] != '/' && match_chars(&pattern[1..], &text[1..]),
+        '[' => match find_close(pattern, ']') {
+            Some(end) => {
+                !text.is_empty()
+                    && text[0] != '/'
+                    && match_set(&pattern[1..end], text[0])
+                    && match_chars(&pattern[end + 1..], &text[1..])
+            }
+            None => text.first() == Some(&'[') && match_chars(&pattern[1..], &text[1..]),
+        },
+        '{' => {
+            let end = match find_close(pattern, '}') {
+                Some(end) => end,
+                None => return text.first() == Some(&'{') && match_chars(&pattern[1..], &text[1..]),
+            };
+            let (inner, rest) = (&pattern[1..end], &pattern[end + 1..]);
+            if let Some((lo, hi)) = parse_num_range(inner) {
+                let digits = text
+                    .iter()
+                    .enumerate()
+                    .take_while(|&(i, &c)| c.is_ascii_digit() || (i == 0 && c == '-'))
+                    .count();
+                return (1..=digits).any(|len| {
+                    let n: String = text[..len].iter().collect();
+                    n.parse::<i64>().is_ok_and(|n| lo <= n && n <= hi) && match_chars(rest, &text[len..])
+                });
+            }
+            let alternatives = split_alternatives(inner);
+            if alternatives.len() == 1 {
+                // A single word in braces is matched literally
+                return text.first() == Some(&'{') && match_chars(&pattern[1..], &text[1..]);
+            }
+            alternatives.iter().any(|alt| {
+                let mut expanded = alt.to_vec();
+                expanded.extend_from_slice(rest);
+                match_chars(&expanded, text)
+            })
+        }
+        '\\' if pattern.len() > 1 => text.first() == Some(&pattern[1]) && match_chars(&pattern[2..], &text[1..]),
+        p => text.first() == Some(&p) && match_chars(&pattern[1..], &text[1..]),
+    }
+}
+
+// Whether a section header of the .editorconfig in `dir` applies to `path`
+fn section_matches(glob: &str, dir: &Path, path: &Path) -> bool {
+    let relative = match path.strip_prefix(dir) {
+        Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+        Err(_) => return false,
+    };
+    if glob.contains('/') {
+        glob_match(glob.strip_prefix('/').unwrap_or(glob), &relative)
+    } else {
+        // Globs without a slash match the file name in any directory
+        let name = relative.rsplit('/').next().unwrap_or("");
+        glob_match(glob, name)
+    }
+}
+
+impl EditorConfig {
+    pub fn for_file(path: &Path) -> EditorConfig {
+        let mut config = EditorConfig::default();
+        let path = match absolute(path) {
+            Some(path) => path,
+            None => return config,
+        };
+
+        // Closer files take precedence, so collect them up to the root and apply outermost first
+        let mut files = vec![];
+        let mut dir = path.parent();
+        while let Some(d) = dir {
+            if let Some((root, sections)) = read_file(&d.join(".editorconfig")) {
+                files.push((d.to_path_buf(), sections));
+                if root {
+                    break;
+                }
+            }
+            dir = d.parent();
+        }
+
+        for (dir, sections) in files.iter().rev() {
+            for (glob, props) in sections {
+                if section_matches(glob, dir, &path) {
+                    for (key, value) in props {
+                        config.set(key, value);
+                    }
+                }
+            }
+        }
+        config
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let value = value.to_lowercase();
+        let unset = value == "unset";
+        let flag = match value.as_str() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+        match key {
+            "indent_style" => self.indent_style = Some(value).filter(|_| !unset),
+            "indent_size" => self.indent_size = Some(value).filter(|_| !unset),
+            "tab_width" => self.tab_width = value.parse().ok().filter(|&n| n > 0),
+            "end_of_line" => self.end_of_line = Some(value).filter(|_| !unset),
+            // Only UTF-8 is supported, so charset has nothing to change
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = flag,
+            "insert_final_newline" => self.insert_final_newline = flag,
+            _ => {}
+        }
+    }
+
+    pub fn apply(&self, mut settings: Settings) -> Settings {
+        match self.indent_style.as_deref() {
+            Some("tab") => settings.expand_tabs = false,
+            Some("space") => settings.expand_tabs = true,
+            _ => {}
+        }
+        let indent_size = self.indent_size.as_deref().and_then(|size| size.parse().ok()).filter(|&n| n > 0);
+        if let Some(width) = self.tab_width.or(indent_size) {
+            settings.tab_stop = width;
+        }
+        match self.end_of_line.as_deref() {
+            Some("lf") => settings.crlf = false,
+            Some("crlf") => settings.crlf = true,
+            _ => {}
+        }
+        if let Some(trim) = self.trim_trailing_whitespace {
+            settings.trim_trailing_whitespace = trim;
+        }
+        if let Some(newline) = self.insert_final_newline {
+            settings.insert_final_newline = newline;
+        }
+        settings
+    }
+}
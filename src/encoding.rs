@@ -0,0 +1,86 @@
+use std::borrow::Cow;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Windows1252,
+}
+
+// Characters of Windows-1252 from 0x80 to 0x9f. The five bytes it leaves undefined stand for
+// the C1 control characters, as they do in Latin-1
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Latin1 => "Latin-1",
+            Encoding::Windows1252 => "Windows-1252",
+        }
+    }
+
+    // UTF-8 when `bytes` is valid UTF-8, Latin-1 otherwise, which any bytes are. A character
+    // cut off at the end of `bytes` still counts as valid
+    pub fn detect(bytes: &[u8]) -> Encoding {
+        match std::str::from_utf8(bytes) {
+            Err(err) if err.error_len().is_some() => Encoding::Latin1,
+            _ => Encoding::Utf8,
+        }
+    }
+
+    // Gives the bytes back when they aren't valid in the encoding, which only happens with UTF-8
+    pub fn decode(self, bytes: Vec<u8>) -> Result<String, Vec<u8>> {
+        match self {
+            Encoding::Utf8 => String::from_utf8(bytes).map_err(|err| err.into_bytes()),
+            Encoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+            Encoding::Windows1252 => Ok(
+                bytes
+                    .iter()
+                    .map(|&b| match b {
+                        0x80..=0x9f => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                        _ => b as char,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    // Characters the encoding has no byte for are written as ? and counted in `replaced`
+    pub fn encode<'a>(self, text: &'a str, replaced: &mut usize) -> Cow<'a, [u8]> {
+        if self == Encoding::Utf8 || text.is_ascii() {
+            return Cow::Borrowed(text.as_bytes());
+        }
+        let bytes = text
+            .chars()
+            .map(|c| {
+                let byte = match (self, c as u32) {
+                    (Encoding::Windows1252, _) if WINDOWS_1252_HIGH.contains(&c) => {
+                        WINDOWS_1252_HIGH.iter().position(|&h| h == c).map(|i| 0x80 + i as u8)
+                    }
+                    // The C1 controls which Windows-1252 uses for other characters
+                    (Encoding::Windows1252, 0x80..=0x9f) => None,
+                    (_, n @ 0..=0xff) => Some(n as u8),
+                    _ => None,
+                };
+                byte.unwrap_or_else(|| {
+                    *replaced += 1;
+                    b'?'
+                })
+            })
+            .collect();
+        Cow::Owned(bytes)
+    }
+}
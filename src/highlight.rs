@@ -0,0 +1,489 @@
+use crate::rope::Rope;
+use std::borrow::Cow;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Highlight {
+    Normal,
+    Number,
+    String,
+    Comment,
+    Keyword,
+    Match,
+    Selection,
+    TrailingWhitespace,
+}
+
+impl Highlight {
+    pub fn color(self) -> &'static [u8] {
+        match self {
+            Highlight::Normal => b"",
+            Highlight::Number => b"\x1b[31m",
+            Highlight::String => b"\x1b[32m",
+            Highlight::Comment => b"\x1b[36m",
+            Highlight::Keyword => b"\x1b[33m",
+            Highlight::Match => b"\x1b[7m",
+            Highlight::Selection => b"\x1b[44m",
+            Highlight::TrailingWhitespace => b"\x1b[41m",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorDepth {
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    // Terminals with 24-bit color say so in $COLORTERM
+    pub fn detect() -> ColorDepth {
+        match std::env::var("COLORTERM").as_deref() {
+            Ok("truecolor" | "24bit") => ColorDepth::TrueColor,
+            _ => ColorDepth::Ansi256,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    // The nearest color of the 6x6x6 cube or the gray ramp of the 256-color palette
+    fn ansi256(self) -> u8 {
+        let Rgb(r, g, b) = self;
+        let level = |c: u8| if c < 48 { 0 } else if c < 115 { 1 } else { (c - 35) / 40 };
+        let value = |l: u8| if l == 0 { 0 } else { 55 + l * 40 };
+        let (lr, lg, lb) = (level(r), level(g), level(b));
+        let cube = (value(lr), value(lg), value(lb));
+        let avg = (r as u32 + g as u32 + b as u32) / 3;
+        let gray_level = if avg > 238 { 23 } else { (avg.saturating_sub(3) / 10) as u8 };
+        let gray = 8 + gray_level * 10;
+        let dist = |(x, y, z): (u8, u8, u8)| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            d(x, r) + d(y, g) + d(z, b)
+        };
+        if dist((gray, gray, gray)) < dist(cube) {
+            232 + gray_level
+        } else {
+            16 + 36 * lr + 6 * lg + lb
+        }
+    }
+
+    fn sgr(self, layer: u8, depth: ColorDepth) -> String {
+        match depth {
+            ColorDepth::TrueColor => format!("\x1b[{}8;2;{};{};{}m", layer, self.0, self.1, self.2),
+            ColorDepth::Ansi256 => format!("\x1b[{}8;5;{}m", layer, self.ansi256()),
+        }
+    }
+
+    pub fn fg(self, depth: ColorDepth) -> String {
+        self.sgr(3, depth)
+    }
+
+    pub fn bg(self, depth: ColorDepth) -> String {
+        self.sgr(4, depth)
+    }
+}
+
+// Colors of a theme, which replace the terminal's own colors everywhere in the editor
+pub struct Palette {
+    pub fg_default: Rgb,
+    pub bg_default: Rgb,
+    pub fg_keyword: Rgb,
+    pub fg_string: Rgb,
+    pub fg_comment: Rgb,
+    pub fg_number: Rgb,
+    pub bg_current_line: Rgb,
+    pub bg_selection: Rgb,
+    pub bg_search_match: Rgb,
+    pub bg_status_bar: Rgb,
+    pub fg_status_bar: Rgb,
+    pub fg_gutter: Rgb,
+}
+
+pub const MONOKAI: Palette = Palette {
+    fg_default: Rgb(0xf8, 0xf8, 0xf2),
+    bg_default: Rgb(0x27, 0x28, 0x22),
+    fg_keyword: Rgb(0xf9, 0x26, 0x72),
+    fg_string: Rgb(0xe6, 0xdb, 0x74),
+    fg_comment: Rgb(0x75, 0x71, 0x5e),
+    fg_number: Rgb(0xae, 0x81, 0xff),
+    bg_current_line: Rgb(0x3e, 0x3d, 0x32),
+    bg_selection: Rgb(0x49, 0x48, 0x3e),
+    bg_search_match: Rgb(0x75, 0x71, 0x5e),
+    bg_status_bar: Rgb(0x75, 0x71, 0x5e),
+    fg_status_bar: Rgb(0xf8, 0xf8, 0xf2),
+    fg_gutter: Rgb(0x90, 0x90, 0x8a),
+};
+
+pub const SOLARIZED_DARK: Palette = Palette {
+    fg_default: Rgb(0x83, 0x94, 0x96),
+    bg_default: Rgb(0x00, 0x2b, 0x36),
+    fg_keyword: Rgb(0x85, 0x99, 0x00),
+    fg_string: Rgb(0x2a, 0xa1, 0x98),
+    fg_comment: Rgb(0x58, 0x6e, 0x75),
+    fg_number: Rgb(0xd3, 0x36, 0x82),
+    bg_current_line: Rgb(0x07, 0x36, 0x42),
+    bg_selection: Rgb(0x27, 0x4b, 0x55),
+    bg_search_match: Rgb(0xb5, 0x89, 0x00),
+    bg_status_bar: Rgb(0x07, 0x36, 0x42),
+    fg_status_bar: Rgb(0x93, 0xa1, 0xa1),
+    fg_gutter: Rgb(0x58, 0x6e, 0x75),
+};
+
+pub const DRACULA: Palette = Palette {
+    fg_default: Rgb(0xf8, 0xf8, 0xf2),
+    bg_default: Rgb(0x28, 0x2a, 0x36),
+    fg_keyword: Rgb(0xff, 0x79, 0xc6),
+    fg_string: Rgb(0xf1, 0xfa, 0x8c),
+    fg_comment: Rgb(0x62, 0x72, 0xa4),
+    fg_number: Rgb(0xbd, 0x93, 0xf9),
+    bg_current_line: Rgb(0x44, 0x47, 0x5a),
+    bg_selection: Rgb(0x44, 0x47, 0x5a),
+    bg_search_match: Rgb(0x62, 0x72, 0xa4),
+    bg_status_bar: Rgb(0x62, 0x72, 0xa4),
+    fg_status_bar: Rgb(0xf8, 0xf8, 0xf2),
+    fg_gutter: Rgb(0x62, 0x72, 0xa4),
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorScheme {
+    Default,
+    // Only search matches and the selection are colored
+    Mono,
+    Monokai,
+    SolarizedDark,
+    Dracula,
+}
+
+impl ColorScheme {
+    pub fn from_name(name: &str) -> Option<ColorScheme> {
+        match name {
+            "default" => Some(ColorScheme::Default),
+            "mono" => Some(ColorScheme::Mono),
+            "monokai" => Some(ColorScheme::Monokai),
+            "solarized_dark" => Some(ColorScheme::SolarizedDark),
+            "dracula" => Some(ColorScheme::Dracula),
+            _ => None,
+        }
+    }
+
+    // The default and mono schemes keep the terminal's colors
+    pub fn palette(self) -> Option<&'static Palette> {
+        match self {
+            ColorScheme::Default | ColorScheme::Mono => None,
+            ColorScheme::Monokai => Some(&MONOKAI),
+            ColorScheme::SolarizedDark => Some(&SOLARIZED_DARK),
+            ColorScheme::Dracula => Some(&DRACULA),
+        }
+    }
+
+    pub fn color(self, hl: Highlight, depth: ColorDepth) -> Cow<'static, [u8]> {
+        let palette = match (self.palette(), self, hl) {
+            (Some(palette), ..) => palette,
+            (None, ColorScheme::Mono, Highlight::Match | Highlight::Selection | Highlight::TrailingWhitespace) | (None, ColorScheme::Default, _) => return Cow::Borrowed(hl.color()),
+            (None, ..) => return Cow::Borrowed(b""),
+        };
+        let color = match hl {
+            Highlight::Normal => palette.fg_default.fg(depth),
+            Highlight::Number => palette.fg_number.fg(depth),
+            Highlight::String => palette.fg_string.fg(depth),
+            Highlight::Comment => palette.fg_comment.fg(depth),
+            Highlight::Keyword => palette.fg_keyword.fg(depth),
+            Highlight::Match => palette.bg_search_match.bg(depth) + &palette.fg_default.fg(depth),
+            Highlight::Selection => palette.bg_selection.bg(depth) + &palette.fg_default.fg(depth),
+            Highlight::TrailingWhitespace => return Cow::Borrowed(hl.color()),
+        };
+        Cow::Owned(color.into_bytes())
+    }
+}
+
+// Found by --check-syntax. `line` is 0-based
+#[derive(Clone, PartialEq, Debug)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub message: String,
+}
+
+// What a row ends inside of, and so the row after it starts in
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum LineState {
+    #[default]
+    Code,
+    Str,
+    // With the number of # after the r
+    RawStr(usize),
+    // With how deeply the comments are nested
+    BlockComment(usize),
+}
+
+pub trait Highlighter {
+    // Returns one highlight per character of the rendered row
+    fn highlight(&self, row: &str) -> Vec<Highlight>;
+
+    // Like highlight, for a row which starts in `state`. Leaves `state` as the row ends, for
+    // languages with strings and comments which span lines
+    fn highlight_line(&self, row: &str, _state: &mut LineState) -> Vec<Highlight> {
+        self.highlight(row)
+    }
+
+    // Starts a line comment in the language, if it has them
+    fn comment_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    // What the highlighter can tell is wrong with the whole file, like a string which is never
+    // closed. Far from a parser, so no errors is no promise that the file compiles
+    fn check(&self, _rows: &Rope) -> Vec<SyntaxError> {
+        vec![]
+    }
+}
+
+pub struct PlainHighlighter;
+
+impl Highlighter for PlainHighlighter {
+    fn highlight(&self, row: &str) -> Vec<Highlight> {
+        vec![Highlight::Normal; row.chars().count()]
+    }
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe",
+    "use", "where", "while",
+];
+
+pub struct RustHighlighter;
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Index just past the quote which closes a raw string with `hashes` # signs, if it is on the row
+fn raw_string_end(chars: &[char], from: usize, hashes: usize) -> Option<usize> {
+    (from..chars.len())
+        .find(|&i| chars[i] == '"' && chars[i + 1..].iter().take_while(|&&c| c == '#').count() >= hashes)
+        .map(|i| i + 1 + hashes)
+}
+
+// Highlights a row of Rust which starts in `state`, and leaves `state` as the row ends. Returns
+// whether what the row ends inside of was opened on this row
+fn lex_rust(chars: &[char], state: &mut LineState, hl: &mut [Highlight]) -> bool {
+    let mut opened = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let start = i;
+        let kind = match *state {
+            LineState::Str => {
+                while i < chars.len() {
+                    match chars[i] {
+                        '\\' => i += 2,
+                        '"' => {
+                            i += 1;
+                            *state = LineState::Code;
+                            break;
+                        }
+                        _ => i += 1,
+                    }
+                }
+                Highlight::String
+            }
+            LineState::RawStr(hashes) => {
+                i = match raw_string_end(chars, i, hashes) {
+                    Some(end) => {
+                        *state = LineState::Code;
+                        end
+                    }
+                    None => chars.len(),
+                };
+                Highlight::String
+            }
+            LineState::BlockComment(depth) => {
+                if c == '/' && next == Some('*') {
+                    *state = LineState::BlockComment(depth + 1);
+                    i += 2;
+                } else if c == '*' && next == Some('/') {
+                    *state = if depth == 1 { LineState::Code } else { LineState::BlockComment(depth - 1) };
+                    i += 2;
+                } else {
+                    i += 1;
+                }
+                Highlight::Comment
+            }
+            LineState::Code if c == '/' && next == Some('/') => {
+                i = chars.len();
+                Highlight::Comment
+            }
+            LineState::Code if c == '/' && next == Some('*') => {
+                *state = LineState::BlockComment(1);
+                opened = true;
+                i += 2;
+                Highlight::Comment
+            }
+            LineState::Code if c == '"' => {
+                *state = LineState::Str;
+                opened = true;
+                i += 1;
+                Highlight::String
+            }
+            // A character literal, which may well be a quote, or else a lifetime
+            LineState::Code if c == '\'' => {
+                if next == Some('\\') {
+                    i = (i + 3..chars.len()).find(|&j| chars[j] == '\'').map_or(chars.len(), |j| j + 1);
+                    Highlight::String
+                } else if chars.get(i + 2) == Some(&'\'') {
+                    i += 3;
+                    Highlight::String
+                } else {
+                    i += 1;
+                    Highlight::Normal
+                }
+            }
+            // Words are consumed as a whole below, so a digit here always starts a literal
+            LineState::Code if c.is_ascii_digit() => {
+                while i < chars.len() {
+                    let c = chars[i];
+                    let is_fraction = c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit());
+                    if !is_ident_char(c) && !is_fraction {
+                        break;
+                    }
+                    i += 1;
+                }
+                Highlight::Number
+            }
+            LineState::Code if is_ident_char(c) => {
+                while i < chars.len() && is_ident_char(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let hashes = chars[i..].iter().take_while(|&&c| c == '#').count();
+                if (word == "r" || word == "br") && chars.get(i + hashes) == Some(&'"') {
+                    *state = LineState::RawStr(hashes);
+                    opened = true;
+                    i += hashes + 1;
+                    Highlight::String
+                } else if RUST_KEYWORDS.contains(&word.as_str()) {
+                    Highlight::Keyword
+                } else {
+                    Highlight::Normal
+                }
+            }
+            LineState::Code => {
+                i += 1;
+                Highlight::Normal
+            }
+        };
+        let end = i.min(hl.len());
+        for h in hl[start..end].iter_mut() {
+            *h = kind;
+        }
+        if *state == LineState::Code {
+            opened = false;
+        }
+    }
+    opened
+}
+
+impl Highlighter for RustHighlighter {
+    fn comment_prefix(&self) -> Option<&'static str> {
+        Some("//")
+    }
+
+    fn highlight(&self, row: &str) -> Vec<Highlight> {
+        self.highlight_line(row, &mut LineState::Code)
+    }
+
+    fn highlight_line(&self, row: &str, state: &mut LineState) -> Vec<Highlight> {
+        let chars: Vec<char> = row.chars().collect();
+        let mut hl = vec![Highlight::Normal; chars.len()];
+        lex_rust(&chars, state, &mut hl);
+        hl
+    }
+
+    fn check(&self, rows: &Rope) -> Vec<SyntaxError> {
+        let (mut state, mut opened_at) = (LineState::Code, 0);
+        for (y, row) in rows.iter().enumerate() {
+            let chars: Vec<char> = row.buf.chars().collect();
+            if lex_rust(&chars, &mut state, &mut vec![Highlight::Normal; chars.len()]) {
+                opened_at = y;
+            }
+        }
+        let message = match state {
+            LineState::Code => return vec![],
+            LineState::Str => "unclosed string literal",
+            LineState::RawStr(_) => "unclosed raw string literal",
+            LineState::BlockComment(_) => "unclosed block comment",
+        };
+        vec![SyntaxError { line: opened_at, message: message.to_string() }]
+    }
+}
+
+// The name a file's language goes by, from its extension
+pub fn file_type(path: &Path) -> Option<&'static str> {
+    let file_type = match path.extension()?.to_str()? {
+        "rs" => "rust",
+        "py" => "python",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        "go" => "go",
+        "js" | "mjs" => "javascript",
+        "ts" => "typescript",
+        "json" => "json",
+        "css" => "css",
+        "html" => "html",
+        "md" | "markdown" => "markdown",
+        "toml" => "toml",
+        "sh" => "sh",
+        _ => return None,
+    };
+    Some(file_type)
+}
+
+pub fn for_path<P: AsRef<Path>>(path: P) -> Box<dyn Highlighter> {
+    match path.as_ref().extension().and_then(|e| e.to_str()) {
+        Some("rs") => Box::new(RustHighlighter),
+        _ => Box::new(PlainHighlighter),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::row::Row;
+
+    fn check(text: &str) -> Vec<SyntaxError> {
+        RustHighlighter.check(&text.lines().map(Row::new).collect())
+    }
+
+    #[test]
+    fn strings_may_span_lines() {
+        assert!(check("let s = \"one\ntwo\";\nlet c = '\"';\nlet r = r#\"a \" b\"#;\n").is_empty());
+        assert!(check("fn f<'a>(x: &'a str) -> char { '\\'' }\n// \"\n/* \" /* */ */\n").is_empty());
+    }
+
+    #[test]
+    fn unclosed_string_reported_where_it_starts() {
+        let errors = check("fn main() {\n    let s = \"abc;\n}\n");
+        assert_eq!(errors, [SyntaxError { line: 1, message: "unclosed string literal".to_string() }]);
+        assert_eq!(check("let r = r##\"x\"#;\n")[0].message, "unclosed raw string literal");
+        assert_eq!(check("/* /* */\n")[0].line, 0);
+    }
+
+    #[test]
+    fn state_carries_over_rows() {
+        let mut state = LineState::Code;
+        let rows: Vec<_> = ["let s = r#\"a", "\" b\"# + 1", "/* x /*", "*/ y */ z"]
+            .iter()
+            .map(|row| RustHighlighter.highlight_line(row, &mut state))
+            .collect();
+        assert_eq!(state, LineState::Code);
+        assert_eq!(rows[1][..5], [Highlight::String; 5]);
+        assert_eq!(rows[1][8], Highlight::Number);
+        assert!(rows[3][..7].iter().all(|&hl| hl == Highlight::Comment));
+        assert_eq!(rows[3][8], Highlight::Normal);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::config::parse_string;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Files remembered at most, the most recently closed first
+const MAX_POSITIONS: usize = 500;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SavedPosition {
+    pub cy: usize,
+    pub cx: usize,
+    pub rowoff: usize,
+}
+
+// Where the cursor was in each file when it was last closed, written as a [[position]] table
+// per file in the same TOML subset as the config
+pub struct CursorHistory {
+    path: PathBuf,
+    positions: Vec<(PathBuf, SavedPosition)>,
+}
+
+// $XDG_DATA_HOME/rustitor/positions.toml, or ~/.local/share/rustitor/positions.toml
+pub fn default_path() -> Option<PathBuf> {
+    let data_dir = match (std::env::var_os("XDG_DATA_HOME"), std::env::var_os("HOME")) {
+        (Some(dir), _) if !dir.is_empty() => PathBuf::from(dir),
+        (_, Some(home)) => Path::new(&home).join(".local").join("share"),
+        _ => return None,
+    };
+    Some(data_dir.join("rustitor").join("positions.toml"))
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Files are known by their absolute path, whatever they were opened as
+fn key(file: &Path) -> PathBuf {
+    fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf())
+}
+
+impl CursorHistory {
+    // A missing or unreadable file gives an empty history, and entries which don't parse are
+    // skipped, since losing a cursor position is harmless
+    pub fn load(path: &Path) -> CursorHistory {
+        let mut history = CursorHistory { path: path.to_path_buf(), positions: vec![] };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return history,
+        };
+        let mut entry: Option<(Option<PathBuf>, SavedPosition)> = None;
+        let mut push = |entry: Option<(Option<PathBuf>, SavedPosition)>| {
+            if let Some((Some(file), pos)) = entry {
+                history.positions.push((file, pos));
+            }
+        };
+        for line in text.lines().map(str::trim) {
+            if line == "[[position]]" {
+                push(entry.take());
+                entry = Some((None, SavedPosition { cy: 0, cx: 0, rowoff: 0 }));
+                continue;
+            }
+            let ((file, pos), (key, value)) = match (entry.as_mut(), line.split_once('=')) {
+                (Some(entry), Some((key, value))) => (entry, (key.trim(), value.trim())),
+                _ => continue,
+            };
+            match key {
+                "path" => *file = parse_string(value).map(PathBuf::from),
+                "cy" => pos.cy = value.parse().unwrap_or(0),
+                "cx" => pos.cx = value.parse().unwrap_or(0),
+                "rowoff" => pos.rowoff = value.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+        push(entry);
+        history
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut out = String::new();
+        for (file, pos) in &self.positions {
+            // A path which isn't UTF-8 couldn't be read back
+            let file = match file.to_str() {
+                Some(file) => file,
+                None => continue,
+            };
+            out.push_str("[[position]]\n");
+            out.push_str(&format!("path = {}\n", quote(file)));
+            out.push_str(&format!("cy = {}\ncx = {}\nrowoff = {}\n\n", pos.cy, pos.cx, pos.rowoff));
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, out)
+    }
+
+    pub fn get(&self, file: &Path) -> Option<SavedPosition> {
+        let file = key(file);
+        self.positions.iter().find(|(f, _)| *f == file).map(|&(_, pos)| pos)
+    }
+
+    pub fn set(&mut self, file: &Path, pos: SavedPosition) {
+        let file = key(file);
+        self.positions.retain(|(f, _)| *f != file);
+        self.positions.insert(0, (file, pos));
+        self.positions.truncate(MAX_POSITIONS);
+    }
+}
@@ -0,0 +1,426 @@
+use crate::clipboard;
+use std::io::{self, Read};
+use std::str;
+
+// Empty reads, each the 100ms VTIME of the terminal, before the rest of an escape sequence is
+// given up on
+const MAX_EMPTY_READS: usize = 50;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum InputSeq {
+    Unidentified,
+    Key(u8, bool),
+    // A character typed outside of ASCII, sent as UTF-8
+    Char(char),
+    LeftKey,
+    RightKey,
+    UpKey,
+    DownKey,
+    AltLeft,
+    AltRight,
+    CtrlLeft,
+    CtrlRight,
+    AltUp,
+    AltDown,
+    ShiftLeft,
+    ShiftRight,
+    ShiftUp,
+    ShiftDown,
+    // Alt+Shift+arrows, for the column selection
+    AltShiftLeft,
+    AltShiftRight,
+    AltShiftUp,
+    AltShiftDown,
+    PageUpKey,
+    PageDownKey,
+    HomeKey,
+    EndKey,
+    DeleteKey,
+    BackTab,
+    CtrlTab,
+    CtrlShiftTab,
+    AltKey(u8),
+    // ESC followed by a control character, as the lowercase letter
+    CtrlAltKey(u8),
+    // Function keys by number
+    FnKey(u8),
+    ShiftFnKey(u8),
+    CtrlFnKey(u8),
+    // Decoded contents of an OSC 52 clipboard reply
+    Clipboard(Vec<u8>),
+    // Text pasted between ESC [ 200 ~ and ESC [ 201 ~
+    Paste(Vec<u8>),
+    // 1-based screen row and column of a left button press or release
+    MouseClick(usize, usize),
+    MouseRelease(usize, usize),
+    ScrollUp,
+    ScrollDown,
+    Cursor(usize, usize),
+}
+
+// Function key for the number in ESC [ n ~
+fn function_key_number(n: &[u8]) -> Option<u8> {
+    let n = match n {
+        b"11" => 1,
+        b"12" => 2,
+        b"13" => 3,
+        b"14" => 4,
+        b"15" => 5,
+        b"17" => 6,
+        b"18" => 7,
+        b"19" => 8,
+        b"20" => 9,
+        b"21" => 10,
+        b"23" => 11,
+        b"24" => 12,
+        _ => return None,
+    };
+    Some(n)
+}
+
+// `pressed` is None for X10 reports, which signal a release with button 3
+fn mouse_event(button: usize, row: usize, col: usize, pressed: Option<bool>) -> InputSeq {
+    match (button, pressed) {
+        (0, None | Some(true)) => InputSeq::MouseClick(row, col),
+        (3, None) | (0, Some(false)) => InputSeq::MouseRelease(row, col),
+        (64, None | Some(true)) => InputSeq::ScrollUp,
+        (65, None | Some(true)) => InputSeq::ScrollDown,
+        _ => InputSeq::Unidentified,
+    }
+}
+
+// Keys decoded from the bytes of a terminal in raw mode, or of any other reader
+pub struct InputSequences<R: Read> {
+    stdin: R,
+    next_byte: u8,
+}
+
+impl<R: Read> InputSequences<R> {
+    pub fn new(stdin: R) -> InputSequences<R> {
+        InputSequences { stdin, next_byte: 0 }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut one_byte: [u8; 1] = [0];
+        if self.stdin.read(&mut one_byte)? == 0 {
+            return Ok(0);
+        }
+        Ok(one_byte[0])
+    }
+
+    fn read_blocking(&mut self) -> io::Result<u8> {
+        let mut one_byte: [u8; 1] = [0];
+        for _ in 0..MAX_EMPTY_READS {
+            if self.stdin.read(&mut one_byte)? > 0 {
+                return Ok(one_byte[0]);
+            }
+        }
+        Err(io::ErrorKind::UnexpectedEof.into())
+    }
+
+    pub fn decode(&mut self, b: u8) -> io::Result<InputSeq> {
+        match self.decode_key(b) {
+            // The rest of an escape sequence never came
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(InputSeq::Unidentified),
+            result => result,
+        }
+    }
+
+    fn decode_key(&mut self, b: u8) -> io::Result<InputSeq> {
+        match b {
+            0x1b => {
+                
+                match self.read_byte()? {
+                    b'[' => {  }
+                    b']' => return self.decode_osc(),
+                    // SS3 sequences, sent for F1-F4 and by some terminals for arrows, Home and End
+                    b'O' => {
+                        return Ok(match self.read_byte()? {
+                            0 => InputSeq::AltKey(b'O'),
+                            b @ b'P'..=b'S' => InputSeq::FnKey(b - b'P' + 1),
+                            b'A' => InputSeq::UpKey,
+                            b'B' => InputSeq::DownKey,
+                            b'C' => InputSeq::RightKey,
+                            b'D' => InputSeq::LeftKey,
+                            b'H' => InputSeq::HomeKey,
+                            b'F' => InputSeq::EndKey,
+                            _ => InputSeq::Unidentified,
+                        });
+                    }
+                    0 => return Ok(InputSeq::Key(0x1b, false)),
+                    b @ 0x20..=0x7e => return Ok(InputSeq::AltKey(b)),
+                    // Esc quickly followed by Enter is still taken as two keys
+                    b @ 0x01..=0x1a if b != b'\r' => return Ok(InputSeq::CtrlAltKey(b | 0x60)),
+                    b => {
+                        self.next_byte = b;
+                        return Ok(InputSeq::Key(0x1b, false));
+                    }
+                };
+                
+                let mut buf = vec![];
+                let cmd = loop {
+                    let b = self.read_blocking()?;
+                    match b {
+                        b'A' | b'B' | b'C' | b'D' | b'F' | b'H' | b'K' | b'J' | b'P' | b'Q' | b'R' | b'S' | b'c'
+                        | b'f' | b'g' | b'h' | b'l' | b'm' | b'M' | b'n' | b'q' | b'y' | b'Z' | b'~' => break b,
+                        b'O' => {
+                            buf.push(b'O');
+                            let b = self.read_blocking()?;
+                            match b {
+                                b'F' | b'H' => break b,
+                                _ => buf.push(b),
+                            };
+                        }
+                        _ => buf.push(b),
+                    }
+                };
+
+                let mut args = buf.split(|b| *b == b';');
+                match cmd {
+                    // F1-F4 with modifiers. A modified F3 looks like a cursor position report for
+                    // row 1, which the editor never asks for
+                    b'P' | b'Q' | b'R' | b'S' if buf.starts_with(b"1;") => Ok(match &buf[2..] {
+                        b"2" => InputSeq::ShiftFnKey(cmd - b'P' + 1),
+                        b"5" => InputSeq::CtrlFnKey(cmd - b'P' + 1),
+                        // Alt and the other combinations have nothing bound
+                        _ => InputSeq::Unidentified,
+                    }),
+                    b'P' | b'Q' | b'S' => Ok(InputSeq::Unidentified),
+                    b'R' => {
+                        let mut i = args
+                            .map(|b| str::from_utf8(b).ok().and_then(|s| s.parse::<usize>().ok()));
+                        match (i.next(), i.next()) {
+                            (Some(Some(r)), Some(Some(c))) => Ok(InputSeq::Cursor(r, c)),
+                            _ => Ok(InputSeq::Unidentified),
+                        }
+                    }
+                    b'A' | b'B' | b'C' | b'D' => {
+                        let modifier = match (args.next(), args.next()) {
+                            (Some(b"1"), Some(m)) => m,
+                            _ => b"",
+                        };
+                        let seq = match (cmd, modifier) {
+                            (b'A', b"2") => InputSeq::ShiftUp,
+                            (b'A', b"3") => InputSeq::AltUp,
+                            (b'B', b"3") => InputSeq::AltDown,
+                            (b'B', b"2") => InputSeq::ShiftDown,
+                            (b'C', b"2") => InputSeq::ShiftRight,
+                            (b'D', b"2") => InputSeq::ShiftLeft,
+                            (b'A', b"4") => InputSeq::AltShiftUp,
+                            (b'B', b"4") => InputSeq::AltShiftDown,
+                            (b'C', b"4") => InputSeq::AltShiftRight,
+                            (b'D', b"4") => InputSeq::AltShiftLeft,
+                            (b'C', b"3") => InputSeq::AltRight,
+                            (b'D', b"3") => InputSeq::AltLeft,
+                            (b'C', b"5") => InputSeq::CtrlRight,
+                            (b'D', b"5") => InputSeq::CtrlLeft,
+                            (b'A', _) => InputSeq::UpKey,
+                            (b'B', _) => InputSeq::DownKey,
+                            (b'C', _) => InputSeq::RightKey,
+                            _ => InputSeq::LeftKey,
+                        };
+                        Ok(seq)
+                    }
+                    b'~' => {
+                        
+                        match args.next() {
+                            // xterm's modifyOtherKeys form of Ctrl+Tab and Ctrl+Shift+Tab
+                            Some(b"27") => match (args.next(), args.next()) {
+                                (Some(b"5"), Some(b"9")) => Ok(InputSeq::CtrlTab),
+                                (Some(b"6"), Some(b"9")) => Ok(InputSeq::CtrlShiftTab),
+                                _ => Ok(InputSeq::Unidentified),
+                            },
+                            Some(b"200") => self.decode_paste(),
+                            Some(b"5") => Ok(InputSeq::PageUpKey),
+                            Some(b"6") => Ok(InputSeq::PageDownKey),
+                            Some(b"1") | Some(b"7") => Ok(InputSeq::HomeKey),
+                            Some(b"4") | Some(b"8") => Ok(InputSeq::EndKey),
+                            Some(b"3") => Ok(InputSeq::DeleteKey),
+                            Some(n) => {
+                                let modifier = args.next();
+                                match (function_key_number(n), modifier) {
+                                    (Some(n), Some(b"2")) => Ok(InputSeq::ShiftFnKey(n)),
+                                    (Some(n), Some(b"5")) => Ok(InputSeq::CtrlFnKey(n)),
+                                    (Some(n), _) => Ok(InputSeq::FnKey(n)),
+                                    (None, _) => Ok(InputSeq::Unidentified),
+                                }
+                            }
+                            None => Ok(InputSeq::Unidentified),
+                        }
+                    }
+                    // X10 mouse report: button, column and row as single bytes offset by 32
+                    b'M' if buf.is_empty() => {
+                        let b = self.read_blocking()?;
+                        let col = self.read_blocking()?;
+                        let row = self.read_blocking()?;
+                        let (col, row) = (col.saturating_sub(32) as usize, row.saturating_sub(32) as usize);
+                        Ok(mouse_event(b.saturating_sub(32) as usize, row, col, None))
+                    }
+                    // SGR mouse report: ESC [ < button ; column ; row, then M on press and m on release
+                    b'M' | b'm' if buf.first() == Some(&b'<') => {
+                        let mut i = buf[1..]
+                            .split(|b| *b == b';')
+                            .map(|b| str::from_utf8(b).ok().and_then(|s| s.parse::<usize>().ok()));
+                        match (i.next(), i.next(), i.next()) {
+                            (Some(Some(b)), Some(Some(col)), Some(Some(row))) => {
+                                Ok(mouse_event(b, row, col, Some(cmd == b'M')))
+                            }
+                            _ => Ok(InputSeq::Unidentified),
+                        }
+                    }
+                    b'H' => Ok(InputSeq::HomeKey),
+                    b'F' => Ok(InputSeq::EndKey),
+                    b'Z' => Ok(InputSeq::BackTab),
+                    // Replies and sequences the editor doesn't ask for or know
+                    _ => Ok(InputSeq::Unidentified),
+                }
+            }
+            0x20..=0x7f => Ok(InputSeq::Key(b, false)),
+            0x01..=0x1f => Ok(InputSeq::Key(b | 0b1100000, true)),
+            0x80..=0xff => self.decode_utf8(b),
+            _ => Ok(InputSeq::Unidentified),
+        }
+    }
+
+    // The terminal sends the rest of the character right after its first byte
+    fn decode_utf8(&mut self, first: u8) -> io::Result<InputSeq> {
+        let len = match first {
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => return Ok(InputSeq::Unidentified),
+        };
+        let mut bytes = vec![first];
+        while bytes.len() < len {
+            let b = self.read_blocking()?;
+            if b & 0xc0 != 0x80 {
+                // Not a continuation byte, so it starts the next key
+                self.next_byte = b;
+                return Ok(InputSeq::Unidentified);
+            }
+            bytes.push(b);
+        }
+        match str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+            Some(c) => Ok(InputSeq::Char(c)),
+            None => Ok(InputSeq::Unidentified),
+        }
+    }
+
+    fn decode_osc(&mut self) -> io::Result<InputSeq> {
+        // Operating system commands end with BEL or ESC \
+        let mut buf = vec![];
+        loop {
+            match self.read_blocking()? {
+                0x07 => break,
+                0x1b => {
+                    self.read_blocking()?;
+                    break;
+                }
+                b => buf.push(b),
+            }
+        }
+
+        let mut args = buf.splitn(3, |b| *b == b';');
+        match (args.next(), args.next(), args.next()) {
+            (Some(b"52"), Some(_), Some(data)) => match clipboard::base64_decode(data) {
+                Some(data) => Ok(InputSeq::Clipboard(data)),
+                None => Ok(InputSeq::Unidentified),
+            },
+            _ => Ok(InputSeq::Unidentified),
+        }
+    }
+
+    fn decode_paste(&mut self) -> io::Result<InputSeq> {
+        const END: &[u8] = b"\x1b[201~";
+        let mut buf = vec![];
+        while !buf.ends_with(END) {
+            buf.push(self.read_blocking()?);
+        }
+        buf.truncate(buf.len() - END.len());
+        Ok(InputSeq::Paste(buf))
+    }
+
+    fn read_seq(&mut self) -> io::Result<InputSeq> {
+        let b = match self.next_byte {
+            0 => {
+                let mut one_byte: [u8; 1] = [0];
+                if self.stdin.read(&mut one_byte)? == 0 {
+                    return Ok(InputSeq::Unidentified);
+                }
+                // Ctrl-Space sends NUL, which read_byte can't tell apart from no input
+                if one_byte[0] == 0 {
+                    return Ok(InputSeq::Key(b' ', true));
+                }
+                one_byte[0]
+            }
+            b => {
+                self.next_byte = 0;
+                b
+            }
+        };
+        self.decode(b)
+    }
+}
+
+impl<R: Read> Iterator for InputSequences<R> {
+    type Item = io::Result<InputSeq>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.read_seq())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(bytes: &[u8]) -> Vec<InputSeq> {
+        let mut keys = InputSequences::new(bytes);
+        (0..=bytes.len())
+            .map(|_| keys.next().unwrap().unwrap())
+            .filter(|seq| *seq != InputSeq::Unidentified)
+            .collect()
+    }
+
+    #[test]
+    fn decodes_escape_sequences() {
+        assert_eq!(decode_all(b"a\x1b[A\x1b[1;5C\x1b[15~"), [
+            InputSeq::Key(b'a', false),
+            InputSeq::UpKey,
+            InputSeq::CtrlRight,
+            InputSeq::FnKey(5),
+        ]);
+        assert_eq!(decode_all(b"\x1b[200~hi\x1b[201~"), [InputSeq::Paste(b"hi".to_vec())]);
+    }
+
+    #[test]
+    fn unfinished_sequence_is_unidentified() {
+        assert_eq!(decode_all(b"\x1b[1;5"), []);
+        assert_eq!(decode_all(b"\x1b[200~never ends"), []);
+        assert_eq!(decode_all(b"\x1b]52;c;"), []);
+    }
+
+    #[test]
+    fn modified_f3_is_not_a_cursor_report() {
+        assert_eq!(decode_all(b"\x1b[1;2R\x1b[1;5R"), [InputSeq::ShiftFnKey(3), InputSeq::CtrlFnKey(3)]);
+        assert_eq!(decode_all(b"\x1b[1;3R\x1b[1;6R\x1b[1;4R"), []);
+        assert_eq!(decode_all(b"\x1b[24;80R"), [InputSeq::Cursor(24, 80)]);
+    }
+
+    #[test]
+    fn decodes_utf8_characters() {
+        assert_eq!(decode_all("é\u{4f60}\u{1f600}x".as_bytes()), [
+            InputSeq::Char('é'),
+            InputSeq::Char('\u{4f60}'),
+            InputSeq::Char('\u{1f600}'),
+            InputSeq::Key(b'x', false),
+        ]);
+        // A broken sequence is dropped, but not the key after it
+        assert_eq!(decode_all(b"\xe4\xbdx\xff\x80y"), [InputSeq::Key(b'x', false), InputSeq::Key(b'y', false)]);
+    }
+
+    #[test]
+    fn unknown_final_byte_is_unidentified() {
+        assert_eq!(decode_all(b"\x1b[2J\x1b[5nx"), [InputSeq::Key(b'x', false)]);
+    }
+}
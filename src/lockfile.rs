@@ -0,0 +1,72 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// `.rustitor.NAME.lock` next to the file, holding the PID of the editor which has it open.
+// It is removed when the buffer holding it goes away
+pub struct LockFile {
+    path: PathBuf,
+}
+
+pub enum LockError {
+    // Another editor which is still running holds the lock
+    Held(u32),
+    Io(io::Error),
+}
+
+fn lock_path(file: &Path) -> Option<PathBuf> {
+    let name = file.file_name()?.to_string_lossy();
+    let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    Some(dir.join(format!(".rustitor.{}.lock", name)))
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+// EPERM means the process exists but belongs to someone else
+fn is_running(pid: u32) -> bool {
+    let pid = match libc::pid_t::try_from(pid) {
+        Ok(pid) if pid > 0 => pid,
+        _ => return false,
+    };
+    let alive = unsafe { libc::kill(pid, 0) == 0 };
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+impl LockFile {
+    // Created with O_EXCL, so two editors can't both take it. A lock left behind by an editor
+    // which is no longer running is taken over
+    pub fn acquire(file: &Path) -> Result<LockFile, LockError> {
+        let path = lock_path(file).ok_or_else(|| LockError::Io(io::ErrorKind::InvalidInput.into()))?;
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut f) => {
+                    write!(f, "{}", std::process::id()).map_err(LockError::Io)?;
+                    return Ok(LockFile { path });
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => match read_pid(&path) {
+                    Some(pid) if pid != std::process::id() && is_running(pid) => return Err(LockError::Held(pid)),
+                    _ => {
+                        let _ = fs::remove_file(&path);
+                    }
+                },
+                Err(err) => return Err(LockError::Io(err)),
+            }
+        }
+        Err(LockError::Io(io::ErrorKind::AlreadyExists.into()))
+    }
+
+    // False once another editor took the lock over or removed it
+    pub fn is_ours(&self) -> bool {
+        read_pid(&self.path) == Some(std::process::id())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.is_ours() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use crate::highlight::file_type;
+use crate::json::Json;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[31m!\x1b[m",
+            Severity::Warning => "\x1b[33mw\x1b[m",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+// The language server command for a file, and the language id the server knows it by
+pub fn server_for(path: &Path) -> Option<(&'static str, &'static str)> {
+    let language = file_type(path)?;
+    let command = match language {
+        "rust" => "rust-analyzer",
+        "python" => "pylsp",
+        "c" | "cpp" => "clangd",
+        "go" => "gopls",
+        _ => return None,
+    };
+    Some((command, language))
+}
+
+fn file_uri(path: &Path) -> String {
+    let path = fs::canonicalize(path)
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf());
+    let mut uri = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => uri.push(b as char),
+            b => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+fn write_message<W: Write>(mut w: W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
+
+// None at the end of the stream
+fn read_message<R: BufRead>(r: &mut R) -> io::Result<Option<Json>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(n) = line.strip_prefix("Content-Length:") {
+            len = n.trim().parse().ok();
+        }
+    }
+    let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; len];
+    r.read_exact(&mut body)?;
+    Ok(Some(Json::parse(&String::from_utf8_lossy(&body)).unwrap_or(Json::Null)))
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([("jsonrpc", "2.0".into()), ("method", method.into()), ("params", params)])
+}
+
+const INITIALIZE_ID: u64 = 1;
+
+struct Writer {
+    stdin: ChildStdin,
+    // Nothing may be sent until the server has answered initialize, so it waits here
+    queue: Option<Vec<Json>>,
+}
+
+impl Writer {
+    fn send(&mut self, message: Json) -> io::Result<()> {
+        match self.queue {
+            Some(ref mut queue) => {
+                queue.push(message);
+                Ok(())
+            }
+            None => write_message(&mut self.stdin, &message),
+        }
+    }
+
+    fn initialized(&mut self) -> io::Result<()> {
+        write_message(&mut self.stdin, &notification("initialized", Json::object::<&str, _>([])))?;
+        for message in self.queue.take().unwrap_or_default() {
+            write_message(&mut self.stdin, &message)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_diagnostics(params: &Json) -> Option<(String, Vec<Diagnostic>)> {
+    let uri = params.get("uri")?.as_str()?.to_string();
+    let mut diagnostics = vec![];
+    for d in params.get("diagnostics")?.as_array()? {
+        // Information and hints are left out. A missing severity is up to the client
+        let severity = match d.get("severity").and_then(Json::as_u64) {
+            Some(1) | None => Severity::Error,
+            Some(2) => Severity::Warning,
+            _ => continue,
+        };
+        let line = d.get("range")?.get("start")?.get("line")?.as_u64()? as usize;
+        let message = d.get("message")?.as_str()?.to_string();
+        diagnostics.push(Diagnostic { line, severity, message });
+    }
+    Some((uri, diagnostics))
+}
+
+// Runs on its own thread until the server exits
+fn read_messages<R: BufRead>(mut r: R, writer: Arc<Mutex<Writer>>, updates: Sender<(String, Vec<Diagnostic>)>) {
+    while let Ok(Some(message)) = read_message(&mut r) {
+        let method = message.get("method").and_then(Json::as_str);
+        let result = match (message.get("id"), method) {
+            (Some(id), Some(_)) => {
+                // None of the server's requests are supported, but each needs a response
+                let response = Json::object([("jsonrpc", "2.0".into()), ("id", id.clone()), ("result", Json::Null)]);
+                writer.lock().map_or(Ok(()), |mut w| write_message(&mut w.stdin, &response))
+            }
+            (Some(id), None) if id.as_u64() == Some(INITIALIZE_ID) => {
+                writer.lock().map_or(Ok(()), |mut w| w.initialized())
+            }
+            (None, Some("textDocument/publishDiagnostics")) => {
+                if let Some(update) = message.get("params").and_then(parse_diagnostics) {
+                    if updates.send(update).is_err() {
+                        return;
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        if result.is_err() {
+            return;
+        }
+    }
+}
+
+struct Document {
+    path: PathBuf,
+    uri: String,
+    version: u64,
+    // The buffer's change count when its text was last sent
+    changes: u64,
+}
+
+pub struct LspClient {
+    pub command: &'static str,
+    child: Child,
+    writer: Arc<Mutex<Writer>>,
+    updates: Receiver<(String, Vec<Diagnostic>)>,
+    documents: Vec<Document>,
+    // Keyed by document URI
+    diagnostics: HashMap<String, Vec<Diagnostic>>,
+}
+
+impl LspClient {
+    pub fn start(command: &'static str) -> io::Result<LspClient> {
+        let mut child = Command::new(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => return Err(io::Error::other("no pipe to the language server")),
+        };
+
+        let root = std::env::current_dir().map(|dir| file_uri(&dir)).unwrap_or_default();
+        let initialize = Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", INITIALIZE_ID.into()),
+            ("method", "initialize".into()),
+            (
+                "params",
+                Json::object([
+                    ("processId", u64::from(std::process::id()).into()),
+                    ("rootUri", root.into()),
+                    (
+                        "capabilities",
+                        Json::object([(
+                            "textDocument",
+                            Json::object([("publishDiagnostics", Json::object::<&str, _>([]))]),
+                        )]),
+                    ),
+                ]),
+            ),
+        ]);
+        let mut writer = Writer { stdin, queue: Some(vec![]) };
+        write_message(&mut writer.stdin, &initialize)?;
+
+        let writer = Arc::new(Mutex::new(writer));
+        let (tx, rx) = mpsc::channel();
+        let thread_writer = Arc::clone(&writer);
+        thread::spawn(move || read_messages(BufReader::new(stdout), thread_writer, tx));
+
+        Ok(LspClient {
+            command,
+            child,
+            writer,
+            updates: rx,
+            documents: vec![],
+            diagnostics: HashMap::new(),
+        })
+    }
+
+    fn send(&self, message: Json) -> io::Result<()> {
+        match self.writer.lock() {
+            Ok(mut writer) => writer.send(message),
+            Err(_) => Err(io::Error::other("language server writer panicked")),
+        }
+    }
+
+    // Sends the whole text of a document which the server hasn't seen or which changed since
+    pub fn sync<F: FnOnce() -> String>(&mut self, path: &Path, language: &str, changes: u64, text: F) -> io::Result<()> {
+        let message = match self.documents.iter_mut().find(|d| d.path == path) {
+            Some(doc) if doc.changes == changes => return Ok(()),
+            Some(doc) => {
+                doc.version += 1;
+                doc.changes = changes;
+                let document = Json::object([("uri", doc.uri.clone().into()), ("version", doc.version.into())]);
+                let change = Json::object([("text", text().into())]);
+                notification(
+                    "textDocument/didChange",
+                    Json::object([("textDocument", document), ("contentChanges", Json::Array(vec![change]))]),
+                )
+            }
+            None => {
+                let uri = file_uri(path);
+                let document = Json::object([
+                    ("uri", uri.clone().into()),
+                    ("languageId", language.into()),
+                    ("version", 1u64.into()),
+                    ("text", text().into()),
+                ]);
+                self.documents.push(Document { path: path.to_path_buf(), uri, version: 1, changes });
+                notification("textDocument/didOpen", Json::object([("textDocument", document)]))
+            }
+        };
+        self.send(message)
+    }
+
+    pub fn close(&mut self, path: &Path) -> io::Result<()> {
+        let i = match self.documents.iter().position(|d| d.path == path) {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let doc = self.documents.remove(i);
+        self.diagnostics.remove(&doc.uri);
+        let document = Json::object([("uri", doc.uri.into())]);
+        self.send(notification("textDocument/didClose", Json::object([("textDocument", document)])))
+    }
+
+    // Takes in what the server sent since the last call. True when anything changed
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok((uri, diagnostics)) = self.updates.try_recv() {
+            self.diagnostics.insert(uri, diagnostics);
+            changed = true;
+        }
+        changed
+    }
+
+    // None when the document is not open in this server
+    pub fn diagnostics(&self, path: &Path) -> Option<&[Diagnostic]> {
+        let doc = self.documents.iter().find(|d| d.path == path)?;
+        Some(self.diagnostics.get(&doc.uri).map_or(&[], |d| d))
+    }
+}
+
+impl Drop for LspClient {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
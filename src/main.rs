@@ -1,28 +1,80 @@
+mod args;
+mod benchmark;
+mod browser;
+mod buffer;
+mod clipboard;
+mod config;
+mod diff;
+mod diffview;
+mod editorconfig;
+mod encoding;
+mod highlight;
+mod history;
+mod input;
+mod json;
+mod lockfile;
+mod lsp;
+mod render;
+mod rope;
+mod row;
+mod script;
+mod session;
+mod settings;
+
+use args::{Args, USAGE};
+use browser::{common_prefix, complete_path, FileBrowser, SIDEBAR_WIDTH};
+use buffer::{BufferStats, CursorDir, EditorBuffer, SearchDir, WriteResult};
+use clipboard::ClipboardProvider;
+use config::{Config, Key, KeyBindingTable};
+use diffview::DiffView;
+use editorconfig::EditorConfig;
+use encoding::Encoding;
+use input::{InputSeq, InputSequences};
+use script::ScriptCommand;
+use session::Session;
+use settings::Settings;
+use highlight::ColorDepth;
+use history::{CursorHistory, SavedPosition};
+use lockfile::{LockError, LockFile};
+use lsp::{Diagnostic, LspClient};
+use render::{AnsiRenderer, DumbRenderer, Renderer};
+use std::collections::HashMap;
 use std::cmp;
-use std::fs;
-use std::io::{self, BufRead, Read, Write};
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Read, Write};
+use std::mem;
 use std::ops::{Deref, DerefMut};
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 use std::time::SystemTime;
+use unicode_width::UnicodeWidthStr;
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const TAB_STOP: usize = 8;
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const KILL_RING_SIZE: usize = 30;
+const HELP_BUFFER: &str = "*help*";
+const STDIN_BUFFER: &str = "[stdin]";
+// Smaller files only get the scrollbar
+const MINIMAP_MIN_ROWS: usize = 10_000;
 
 struct StdinRawMode {
     stdin: io::Stdin,
     orig: termios::Termios,
+    mouse: bool,
 }
 
 impl StdinRawMode {
-    fn new() -> io::Result<StdinRawMode> {
+    fn new(mouse: bool) -> io::Result<StdinRawMode> {
         use termios::*;
         
         let stdin = io::stdin();
         let fd = stdin.as_raw_fd();
         let mut termios = Termios::from_fd(fd)?;
-        let orig = termios.clone();
+        let orig = termios;
         
         termios.c_lflag &= !(ECHO | ICANON | ISIG | IEXTEN);
         termios.c_iflag &= !(IXON | ICRNL | BRKINT | INPCK | ISTRIP);
@@ -31,175 +83,154 @@ impl StdinRawMode {
         termios.c_cc[VMIN] = 0;
         termios.c_cc[VTIME] = 1;
         
-        tcsetattr(fd, TCSAFLUSH, &mut termios)?;
+        tcsetattr(fd, TCSAFLUSH, &termios)?;
 
-        Ok(StdinRawMode { stdin, orig })
-    }
-
-    fn input_keys(self) -> InputSequences {
-        InputSequences {
-            stdin: self,
-            next_byte: 0,
+        // Bracketed paste, so pasted text isn't run as key commands
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?2004h")?;
+        if mouse {
+            // Button reports, in the SGR format where the terminal supports it
+            stdout.write_all(b"\x1b[?1000h\x1b[?1006h")?;
         }
-    }
-}
+        stdout.flush()?;
 
-impl Drop for StdinRawMode {
-    fn drop(&mut self) {
-        termios::tcsetattr(self.stdin.as_raw_fd(), termios::TCSAFLUSH, &mut self.orig).unwrap();
+        Ok(StdinRawMode { stdin, orig, mouse })
     }
-}
 
-impl Deref for StdinRawMode {
-    type Target = io::Stdin;
+    // For when stdin was a pipe which has been read. The terminal is put in its place as fd 0,
+    // so that shell commands and the cooked mode around them find it where they expect
+    fn from_tty(mouse: bool) -> io::Result<StdinRawMode> {
+        let tty = std::fs::File::open("/dev/tty")?;
+        if unsafe { libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        StdinRawMode::new(mouse)
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.stdin
+    fn input_keys(self) -> InputSequences<StdinRawMode> {
+        InputSequences::new(self)
     }
 }
 
-impl DerefMut for StdinRawMode {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.stdin
+impl Drop for StdinRawMode {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[?2004l");
+        if self.mouse {
+            let _ = stdout.write_all(b"\x1b[?1006l\x1b[?1000l");
+        }
+        let _ = stdout.flush();
+        termios::tcsetattr(self.stdin.as_raw_fd(), termios::TCSAFLUSH, &self.orig).unwrap();
     }
 }
 
-#[derive(PartialEq, Debug)]
-enum InputSeq {
-    Unidentified,
-    Key(u8, bool),
-    LeftKey,
-    RightKey,
-    UpKey,
-    DownKey,
-    PageUpKey,
-    PageDownKey,
-    HomeKey,
-    EndKey,
-    DeleteKey,
-    Cursor(usize, usize),
+// Gives the terminal back its usual line editing and echo while a child process runs
+struct CookedMode {
+    raw: Option<termios::Termios>,
+    mouse: bool,
 }
 
-struct InputSequences {
-    stdin: StdinRawMode,
-    next_byte: u8,
-}
+impl CookedMode {
+    fn new(mouse: bool) -> CookedMode {
+        use termios::*;
 
-impl InputSequences {
-    fn read_byte(&mut self) -> io::Result<u8> {
-        let mut one_byte: [u8; 1] = [0];
-        self.stdin.read(&mut one_byte)?;
-        Ok(one_byte[0])
+        let fd = io::stdin().as_raw_fd();
+        let raw = Termios::from_fd(fd).ok();
+        if let Some(raw) = raw {
+            let mut cooked = raw;
+            cooked.c_lflag |= ECHO | ICANON | ISIG | IEXTEN;
+            cooked.c_iflag |= ICRNL | IXON;
+            cooked.c_oflag |= OPOST;
+            let _ = tcsetattr(fd, TCSANOW, &cooked);
+        }
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[?2004l");
+        if mouse {
+            let _ = stdout.write_all(b"\x1b[?1006l\x1b[?1000l");
+        }
+        let _ = stdout.flush();
+        CookedMode { raw, mouse }
     }
+}
 
-    fn read_blocking(&mut self) -> io::Result<u8> {
-        let mut one_byte: [u8; 1] = [0];
-        loop {
-            if self.stdin.read(&mut one_byte)? > 0 {
-                return Ok(one_byte[0]);
-            }
+impl Drop for CookedMode {
+    fn drop(&mut self) {
+        if let Some(ref raw) = self.raw {
+            let _ = termios::tcsetattr(io::stdin().as_raw_fd(), termios::TCSANOW, raw);
+        }
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[?2004h");
+        if self.mouse {
+            let _ = stdout.write_all(b"\x1b[?1000h\x1b[?1006h");
         }
+        let _ = stdout.flush();
     }
+}
 
-    fn decode(&mut self, b: u8) -> io::Result<InputSeq> {
-        match b {
-            0x1b => {
-                
-                match self.read_byte()? {
-                    b'[' => {  }
-                    0 => return Ok(InputSeq::Key(0x1b, false)),
-                    b => {
-                        self.next_byte = b;
-                        return Ok(InputSeq::Key(0x1b, false));
-                    }
-                };
-                
-                let mut buf = vec![];
-                let cmd = loop {
-                    let b = self.read_blocking()?;
-                    match b {
-                        b'A' | b'B' | b'C' | b'D' | b'F' | b'H' | b'K' | b'J' | b'R' | b'c'
-                        | b'f' | b'g' | b'h' | b'l' | b'm' | b'n' | b'q' | b'y' | b'~' => break b,
-                        b'O' => {
-                            buf.push(b'O');
-                            let b = self.read_blocking()?;
-                            match b {
-                                b'F' | b'H' => break b,
-                                _ => buf.push(b),
-                            };
-                        }
-                        _ => buf.push(b),
-                    }
-                };
-
-                let mut args = buf.split(|b| *b == b';');
-                match cmd {
-                    b'R' => {
-                        let mut i = args
-                            .map(|b| str::from_utf8(b).ok().and_then(|s| s.parse::<usize>().ok()));
-                        match (i.next(), i.next()) {
-                            (Some(Some(r)), Some(Some(c))) => Ok(InputSeq::Cursor(r, c)),
-                            _ => Ok(InputSeq::Unidentified),
-                        }
-                    }
-                    b'A' => Ok(InputSeq::UpKey),
-                    b'B' => Ok(InputSeq::DownKey),
-                    b'C' => Ok(InputSeq::RightKey),
-                    b'D' => Ok(InputSeq::LeftKey),
-                    b'~' => {
-                        
-                        match args.next() {
-                            Some(b"5") => Ok(InputSeq::PageUpKey),
-                            Some(b"6") => Ok(InputSeq::PageDownKey),
-                            Some(b"1") | Some(b"7") => Ok(InputSeq::HomeKey),
-                            Some(b"4") | Some(b"8") => Ok(InputSeq::EndKey),
-                            Some(b"3") => Ok(InputSeq::DeleteKey),
-                            _ => Ok(InputSeq::Unidentified),
-                        }
-                    }
-                    b'H' => Ok(InputSeq::HomeKey),
-                    b'F' => Ok(InputSeq::EndKey),
-                    _ => unreachable!(),
-                }
-            }
-            0x20..=0x7f => Ok(InputSeq::Key(b, false)),
-            0x01..=0x1f => Ok(InputSeq::Key(b | 0b1100000, true)),
-            _ => Ok(InputSeq::Unidentified),
-        }
+// Runs `sh -c command` with `input` on its stdin, or the terminal's without one
+fn run_shell(command: &str, input: Option<&str>) -> io::Result<Output> {
+    let stdin = if input.is_some() { Stdio::piped() } else { Stdio::inherit() };
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        // Written on another thread, since the command may fill its stdout before reading everything
+        let input = input.to_string();
+        thread::spawn(move || stdin.write_all(input.as_bytes()));
     }
+    child.wait_with_output()
+}
 
-    fn read_seq(&mut self) -> io::Result<InputSeq> {
-        let b = match self.next_byte {
-            0 => self.read_byte()?,
-            b => {
-                self.next_byte = 0;
-                b
-            }
-        };
-        self.decode(b)
+impl Deref for StdinRawMode {
+    type Target = io::Stdin;
+
+    fn deref(&self) -> &Self::Target {
+        &self.stdin
     }
 }
 
-impl Iterator for InputSequences {
-    type Item = io::Result<InputSeq>;
-    
-    fn next(&mut self) -> Option<Self::Item> {
-        Some(self.read_seq())
+impl DerefMut for StdinRawMode {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.stdin
     }
 }
 
-struct FilePath {
-    path: PathBuf,
-    display: String,
+impl Read for StdinRawMode {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdin.read(buf)
+    }
 }
 
-impl FilePath {
-    fn from<P: AsRef<Path>>(path: P) -> FilePath {
-        let path = path.as_ref();
-        FilePath {
-            path: PathBuf::from(path),
-            display: path.to_string_lossy().to_string(),
-        }
+fn key_seq(key: Key) -> InputSeq {
+    match key {
+        // Terminals send 0x1f for Ctrl-/, which is decoded like DEL with Ctrl
+        Key::Ctrl(b'/') => InputSeq::Key(0x7f, true),
+        Key::Ctrl(b) => InputSeq::Key(b, true),
+        Key::Alt(b) => InputSeq::AltKey(b),
+        Key::CtrlAlt(b) => InputSeq::CtrlAltKey(b),
+        Key::Up => InputSeq::UpKey,
+        Key::Down => InputSeq::DownKey,
+        Key::Left => InputSeq::LeftKey,
+        Key::Right => InputSeq::RightKey,
+        Key::AltUp => InputSeq::AltUp,
+        Key::AltDown => InputSeq::AltDown,
+        Key::AltLeft => InputSeq::AltLeft,
+        Key::AltRight => InputSeq::AltRight,
+        Key::CtrlLeft => InputSeq::CtrlLeft,
+        Key::CtrlRight => InputSeq::CtrlRight,
+        Key::Home => InputSeq::HomeKey,
+        Key::End => InputSeq::EndKey,
+        Key::PageUp => InputSeq::PageUpKey,
+        Key::PageDown => InputSeq::PageDownKey,
+        Key::Delete => InputSeq::DeleteKey,
+        Key::BackTab => InputSeq::BackTab,
+        Key::F(n) => InputSeq::FnKey(n),
+        Key::ShiftF(n) => InputSeq::ShiftFnKey(n),
+        Key::CtrlF(n) => InputSeq::CtrlFnKey(n),
     }
 }
 
@@ -215,479 +246,1670 @@ impl StatusMessage {
             timestamp: SystemTime::now(),
         }
     }
+}
 
-    fn reset_timestamp(&mut self) {
-        self.timestamp = SystemTime::now();
-    }
+// What the previous key did, for commands which behave differently when repeated
+#[derive(Clone, Copy, PartialEq)]
+enum LastAction {
+    Other,
+    // Consecutive kills are collected into one kill ring entry
+    Kill,
+    // Index of the kill ring entry which was yanked
+    Yank(usize),
+    // Typed text is collected for the . register
+    Insert,
 }
 
-struct Row {
-    buf: String,
-    render: String,
+// Emacs' names for commands which have keys of their own
+fn default_ctrl_x_keys() -> HashMap<u8, InputSeq> {
+    HashMap::from([
+        (b'f' & 0x1f, InputSeq::Key(b'o', true)),
+        (b's' & 0x1f, InputSeq::Key(b's', true)),
+        (b'c' & 0x1f, InputSeq::Key(b'q', true)),
+        (b'k', InputSeq::Key(b'w', true)),
+    ])
 }
 
-impl Row {
-    fn new<S: Into<String>>(line: S) -> Row {
-        let mut row = Row {
-            buf: line.into(),
-            render: "".to_string(),
-        };
-        row.update_render();
-        row
+// Commands which change the text or write the file
+fn modifies_buffer(seq: &InputSeq) -> bool {
+    use InputSeq::*;
+    match seq {
+        DeleteKey | BackTab | AltUp | AltDown | Paste(_) | Char(_) => true,
+        AltKey(b'd' | b'/' | b'y' | b'r' | b's' | b't') | CtrlAltKey(b'f' | b'u') => true,
+        Key(b' ', true) => true,
+        Key(b'h' | b'd' | b'j' | b'k' | b'v' | b'z' | b'y' | b'i' | b'm' | b's' | 0x7f, true) => true,
+        Key(b, false) => *b != 0x1b,
+        _ => false,
     }
+}
 
-    fn empty() -> Row {
-        Row {
-            buf: "".to_string(),
-            render: "".to_string(),
-        }
+// Commands which a numeric argument runs that many times
+fn is_repeatable(seq: &InputSeq) -> bool {
+    use InputSeq::*;
+    match seq {
+        UpKey | DownKey | LeftKey | RightKey | ShiftUp | ShiftDown | ShiftLeft | ShiftRight
+        | AltShiftUp | AltShiftDown | AltShiftLeft | AltShiftRight
+        | AltUp | AltDown | AltLeft | AltRight | CtrlLeft | CtrlRight | PageUpKey | PageDownKey | DeleteKey | Char(_) => true,
+        AltKey(b'd') | AltKey(b'/') | AltKey(b't') | CtrlAltKey(b'u') => true,
+        Key(b'p' | b'd' | b'j' | b'k' | b'z' | b'y' | b'h' | b'i' | b'm' | 0x7f, true) => true,
+        Key(b, false) => *b != 0x1b,
+        _ => false,
     }
+}
 
-    fn update_render(&mut self) {
-        self.render = String::with_capacity(self.buf.len());
-        let mut index = 0;
-        for c in self.buf.chars() {
-            if c == '\t' {
-                loop {
-                    self.render.push(' ');
-                    index += 1;
-                    if index % TAB_STOP == 0 {
-                        break;
-                    }
-                }
-            } else {
-                self.render.push(c);
-                index += 1;
-            }
-        }
+// Commands which keep the column selection. Any other one ends it
+fn is_column_command(seq: &InputSeq) -> bool {
+    use InputSeq::*;
+    match seq {
+        AltShiftUp | AltShiftDown | AltShiftLeft | AltShiftRight | DeleteKey | Paste(_) | Char(_) => true,
+        Key(b'h' | b'c' | b'x' | b'v', true) => true,
+        Key(b, false) => *b != 0x1b && *b != b'\r',
+        _ => false,
     }
+}
 
-    fn rx_from_cx(&self, cx: usize) -> usize {
-        self.buf.chars().take(cx).fold(0, |rx, ch| {
-            if ch == '\t' {
-                rx + TAB_STOP - (rx % TAB_STOP)
-            } else {
-                rx + 1
-            }
-        })
-    }
+// Words offered by Ctrl-Space, drawn in a popup below the cursor
+struct Completion {
+    items: Vec<String>,
+    selected: usize,
+    // Bytes of the word before the cursor, which every item starts with
+    prefix_len: usize,
+}
 
-    fn insert_char(&mut self, at: usize, c: char) {
-        if self.buf.len() <= at {
-            self.buf.push(c);
-        } else {
-            self.buf.insert(at, c);
-        }
-        self.update_render();
-    }
+// Rows last written to the terminal, so that only the ones which change are written again
+#[derive(Default)]
+struct ScreenBuffer {
+    rows: Vec<Vec<u8>>,
+}
 
-    fn delete_char(&mut self, at: usize) {
-        if at < self.buf.len() {
-            self.buf.remove(at);
-            self.update_render();
+impl ScreenBuffer {
+    // Writes the rows which differ from the last frame, and the cursor's row in any case, and
+    // returns how many were written
+    fn update<W: Write>(&mut self, rows: Vec<Vec<u8>>, cursor_row: usize, mut buf: W) -> io::Result<usize> {
+        if rows.len() != self.rows.len() {
+            self.rows.clear();
         }
-    }
-
-    fn append<S: AsRef<str>>(&mut self, s: S) {
-        let s = s.as_ref();
-        if s.is_empty() {
-            return;
+        let mut written = 0;
+        for (y, row) in rows.iter().enumerate() {
+            if y != cursor_row && self.rows.get(y) == Some(row) {
+                continue;
+            }
+            write!(buf, "\x1b[{};1H", y + 1)?;
+            buf.write_all(row)?;
+            written += 1;
         }
-        self.buf.push_str(s);
-        self.update_render();
+        self.rows = rows;
+        Ok(written)
     }
 
-    fn truncate(&mut self, at: usize) {
-        if at < self.buf.len() {
-            self.buf.truncate(at);
-            self.update_render();
-        }
+    // Makes the next frame write every row
+    fn invalidate(&mut self) {
+        self.rows.clear();
     }
 }
 
-enum CursorDir {
-    Left,
-    Right,
-    Up,
-    Down,
-}
-
 #[derive(PartialEq)]
 enum AfterKeyPress {
     Quit,
     Continue,
 }
+// Buffer index, change count, row count and screen rows the minimap was worked out for
+type MinimapKey = (usize, u64, usize, usize);
+
 struct Editor<I: Iterator<Item = io::Result<InputSeq>>> {
     input: I,
-    
-    file: Option<FilePath>,
 
-    cx: usize,
-    cy: usize,
+    buffers: Vec<EditorBuffer>,
+    current: usize,
 
-    rx: usize,
+    settings: Settings,
 
     screen_rows: usize,
     screen_cols: usize,
-    row: Vec<Row>,
-    rowoff: usize,
-    coloff: usize,
 
     message: StatusMessage,
-    dirty: bool,
+    // Last terminal title written, so it's only sent again when the file name or its unsaved
+    // changes mark change
+    title: Option<String>,
+    // Column of the cursor in the message bar while a prompt is open
+    prompt_cursor: Option<usize>,
     quitting: bool,
+    closing: bool,
+
+    show_line_numbers: bool,
+    show_stats: bool,
+    // Stats of the current buffer, along with the buffer index, change count and row count they
+    // were counted at
+    cached_stats: Option<((usize, u64, usize), BufferStats)>,
+    // Shade of each screen row of the minimap
+    cached_minimap: Option<(MinimapKey, Vec<u8>)>,
+
+    search_query: Option<String>,
+    search_match: Option<usize>,
+    search_ignore_case: bool,
+    // Query of the last search which found something, for F3 and Shift-F3
+    last_search: Option<String>,
+
+    clipboard: Vec<String>,
+
+    // Keys recorded so far while a keyboard macro is being defined
+    macro_buffer: Option<Vec<InputSeq>>,
+    last_macro: Vec<InputSeq>,
+    // Keys to be read before any new input
+    replay: VecDeque<InputSeq>,
+    kill_ring: VecDeque<String>,
+    last_action: LastAction,
+    // Numeric argument typed with Alt+digits, or digits after Ctrl-U, applied to the next command
+    pending_count: Option<usize>,
+    // Set by Ctrl-U while the digits after it are typed
+    count_prefix: bool,
+    // Text stored with Alt-" and a register name
+    registers: HashMap<char, String>,
+    // Set by Alt-" until the name is typed
+    register_prefix: bool,
+    // Register named for the next copy, cut or paste
+    pending_register: Option<char>,
+    // Prefix key such as C-g or C-x, until the key after it is pressed
+    pending_seq: Option<InputSeq>,
+    // Keys after C-x which are other names for a command, by the byte the terminal sends for
+    // them, with the key which runs the command
+    ctrl_x_keys: HashMap<u8, InputSeq>,
+    // Text typed since the last other command, for the . register
+    last_insert: String,
+    completion: Option<Completion>,
+    browser: FileBrowser,
+    // Keys go to the file browser rather than the buffer
+    sidebar_focused: bool,
+    // Formatter command for each file type
+    formatters: Vec<(String, String)>,
+    // Keys from the config file, each replaced by the default key of its command
+    keymap: Vec<(InputSeq, InputSeq)>,
+    // Listed by the help buffer
+    bindings: KeyBindingTable,
+    // Background color of whole lines, which the --diff view gives to lines that differ
+    line_backgrounds: HashMap<usize, u8>,
+    // Files are locked against other editors. Only the interactive editor locks them
+    lock_files: bool,
+    // Whether the theme's colors are written as 24-bit or the nearest of 256
+    color_depth: ColorDepth,
+    // Where cursor positions are remembered between runs. Only the interactive editor has one
+    history_path: Option<PathBuf>,
+    // When text was last typed, and whether any buffer changed since the last auto-save
+    last_edit_time: SystemTime,
+    auto_save_pending: bool,
+    // Directory auto-save writes into instead of over the files
+    auto_save_path: Option<PathBuf>,
+    // (row, col) of the bracket matching the one under the cursor, found again after every key
+    bracket_match: Option<(usize, usize)>,
+
+    renderer: Box<dyn Renderer<I>>,
+    // One client for each language server command
+    lsp: Vec<LspClient>,
+    // Servers which failed to start, so that they are not tried again
+    lsp_unavailable: Vec<&'static str>,
+    // Buffer and row of the cursor when its diagnostic was last reported
+    diagnostic_line: Option<(usize, usize)>,
+    // Applied again over the settings from each file's .editorconfig, so that the command line
+    // still wins over them
+    command_line: Args,
+    // Shown in the status bar with RUSTITOR_DEBUG set
+    debug: bool,
+    frames: u64,
+
+    // Set from the SIGWINCH handler. The handler must not do anything but flip this flag
+    resized: Arc<AtomicBool>,
+    // Set on SIGTERM, which ends the input so that the editor quits without saving
+    terminated: Arc<AtomicBool>,
+}
+
+// Keys are given to process_keypress directly, and `input` only feeds prompts
+impl Editor<std::vec::IntoIter<io::Result<InputSeq>>> {
+    // An editor holding `content` in a buffer without a file, which reads neither the disk nor
+    // the terminal. The editor itself starts from main, so only the tests and the fuzz target
+    // build one this way
+    #[cfg(any(test, fuzzing))]
+    pub fn new_from_string(content: &str, window_size: (usize, usize)) -> Self {
+        let mut editor = Editor::new(Some(window_size), vec![].into_iter(), Settings::default());
+        editor.buffers[0] = EditorBuffer::from_text(content, editor.settings);
+        editor
+    }
 }
 
 impl<I: Iterator<Item = io::Result<InputSeq>>> Editor<I> {
-    fn new(window_size: Option<(usize, usize)>, input: I) -> Editor<I> {
+    fn new(window_size: Option<(usize, usize)>, input: I, settings: Settings) -> Editor<I> {
         let (w, h) = window_size.unwrap_or((0, 0));
         Editor {
             input,
-            file: None,
-            cx: 0,
-            cy: 0,
-            rx: 0,
+            buffers: vec![EditorBuffer::new(settings)],
+            current: 0,
+            settings,
             screen_cols: w,
             screen_rows: h.saturating_sub(2),
-            row: Vec::with_capacity(h),
-            rowoff: 0,
-            coloff: 0,
-            message: StatusMessage::new("HELP: Ctrl-S = save | Ctrl-Q = quit"),
-            dirty: false,
+            message: StatusMessage::new("HELP: F1 = keys | Ctrl-S = save | Ctrl-Q = quit | Ctrl-F = find"),
+            title: None,
+            prompt_cursor: None,
             quitting: false,
+            closing: false,
+            show_line_numbers: settings.show_line_numbers,
+            show_stats: settings.show_stats,
+            cached_stats: None,
+            cached_minimap: None,
+            search_query: None,
+            search_match: None,
+            search_ignore_case: false,
+            last_search: None,
+            clipboard: vec![],
+            macro_buffer: None,
+            last_macro: vec![],
+            replay: VecDeque::new(),
+            kill_ring: VecDeque::new(),
+            last_action: LastAction::Other,
+            pending_count: None,
+            count_prefix: false,
+            registers: HashMap::new(),
+            register_prefix: false,
+            pending_register: None,
+            pending_seq: None,
+            ctrl_x_keys: default_ctrl_x_keys(),
+            last_insert: String::new(),
+            completion: None,
+            browser: FileBrowser::new(std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))),
+            sidebar_focused: false,
+            formatters: vec![("rust".to_string(), "rustfmt".to_string())],
+            keymap: vec![],
+            bindings: KeyBindingTable::new(&[]),
+            line_backgrounds: HashMap::new(),
+            lock_files: false,
+            color_depth: ColorDepth::detect(),
+            history_path: None,
+            last_edit_time: SystemTime::now(),
+            auto_save_pending: false,
+            auto_save_path: None,
+            bracket_match: None,
+            renderer: Box::new(AnsiRenderer::default()),
+            lsp: vec![],
+            lsp_unavailable: vec![],
+            diagnostic_line: None,
+            command_line: Args::default(),
+            debug: false,
+            frames: 0,
+            resized: Arc::new(AtomicBool::new(false)),
+            terminated: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    fn trim_line<'a, S: AsRef<str>>(&self, line: &'a S) -> &'a str {
-        let mut line = line.as_ref();
-        if line.len() <= self.coloff {
-            return "";
-        }
-        if self.coloff > 0 {
-            line = &line[self.coloff..];
-        }
-        if line.len() > self.screen_cols {
-            line = &line[..self.screen_cols]
-        }
-        line
+    fn buffer(&self) -> &EditorBuffer {
+        &self.buffers[self.current]
     }
 
-    fn draw_status_bar<W: Write>(&self, mut buf: W) -> io::Result<()> {
-        buf.write(b"\x1b[7m")?;
+    fn buffer_mut(&mut self) -> &mut EditorBuffer {
+        &mut self.buffers[self.current]
+    }
 
-        let file = if let Some(ref f) = self.file {
-            f.display.as_str()
+    fn line_number_width(&self) -> usize {
+        if self.show_line_numbers {
+            self.buffer().rows.len().to_string().len() + 2
         } else {
-            "[No Name]"
-        };
+            0
+        }
+    }
 
-        let modified = if self.dirty { "(modified) " } else { "" };
-        let left = format!("{:<20?} - {} lines {}", file, self.row.len(), modified);
-        let left = &left[..cmp::min(left.len(), self.screen_cols)];
-        buf.write(left.as_bytes())?;
+    // Diff marks and diagnostics, then line numbers
+    fn gutter_width(&self) -> usize {
+        let sign_width = if self.has_sign_column() { 1 } else { 0 };
+        sign_width + self.line_number_width()
+    }
 
-        let rest_len = self.screen_cols - left.len();
-        if rest_len == 0 {
-            return Ok(());
-        }
+    fn has_sign_column(&self) -> bool {
+        self.buffer().diff.is_some() || self.diagnostics().is_some()
+    }
 
-        let right = format!("{}/{}", self.cy, self.row.len());
-        if right.len() > rest_len {
-            for _ in 0..rest_len {
-                buf.write(b" ")?;
-            }
-            return Ok(());
+    // None unless a language server has the current file open
+    fn diagnostics(&self) -> Option<&[Diagnostic]> {
+        let path = &self.buffer().file.as_ref()?.path;
+        self.lsp.iter().find_map(|client| client.diagnostics(path))
+    }
+
+    // The most severe diagnostic on the cursor's line
+    fn cursor_diagnostic(&self) -> Option<&Diagnostic> {
+        let cy = self.buffer().cy;
+        let on_line = self.diagnostics()?.iter().filter(|d| d.line == cy);
+        on_line.reduce(|worst, d| if d.severity > worst.severity { d } else { worst })
+    }
+
+    // Columns taken by the file browser, border included
+    fn sidebar_width(&self) -> usize {
+        if self.browser.open {
+            cmp::min(SIDEBAR_WIDTH, self.screen_cols / 2)
+        } else {
+            0
         }
+    }
 
-        for _ in 0..rest_len - right.len() {
-            buf.write(b" ")?;
+    // The gutter starts after the sidebar
+    fn text_left(&self) -> usize {
+        self.sidebar_width() + self.gutter_width()
+    }
+
+    fn text_cols(&self) -> usize {
+        self.screen_cols.saturating_sub(self.text_left() + self.scrollbar_width())
+    }
+
+    fn has_minimap(&self) -> bool {
+        self.settings.show_minimap && self.buffer().rows.len() > MINIMAP_MIN_ROWS
+    }
+
+    // Columns at the right edge taken by the scrollbar and the minimap
+    fn scrollbar_width(&self) -> usize {
+        let minimap = if self.has_minimap() { 2 } else { 0 };
+        minimap + self.settings.show_scrollbar as usize
+    }
+
+    fn update_minimap(&mut self) {
+        let buffer = self.buffer();
+        let key = (self.current, buffer.changes, buffer.rows.len(), self.screen_rows);
+        if !matches!(self.cached_minimap, Some((cached_key, _)) if cached_key == key) {
+            let counts = buffer.non_blank_counts(self.screen_rows);
+            let most = counts.iter().copied().max().unwrap_or(0).max(1);
+            // From the darkest gray of the palette for an empty region to the lightest for the fullest
+            let shades = counts.iter().map(|&n| 232 + (n * 23 / most) as u8).collect();
+            self.cached_minimap = Some((key, shades));
         }
-        buf.write(right.as_bytes())?;
+    }
 
-        buf.write(b"\x1b[m")?;
-        buf.write(b"\r\n")?;
-        Ok(())
+    // Thumb of the scrollbar as its first screen row and its height
+    fn scrollbar_thumb(&self) -> (usize, usize) {
+        let (rows, len) = (self.screen_rows, self.buffer().rows.len());
+        let height = cmp::min(cmp::max(1, rows * rows / cmp::max(1, len)), rows);
+        let max_off = len.saturating_sub(rows);
+        let top = cmp::min(self.buffer().rowoff, max_off) * (rows - height) / cmp::max(1, max_off);
+        (top, height)
     }
 
-    fn draw_message_bar<W: Write>(&self, mut buf: W) -> io::Result<()> {
-        if let Ok(d) = SystemTime::now().duration_since(self.message.timestamp) {
-            if d.as_secs() < 5 {
-                let msg = &self.message.text[..cmp::min(self.message.text.len(), self.screen_cols)];
-                buf.write(msg.as_bytes())?;
+    // Counted again only when the buffer changed since the last call
+    fn stats(&mut self) -> BufferStats {
+        let buffer = self.buffer();
+        let key = (self.current, buffer.changes, buffer.rows.len());
+        match self.cached_stats {
+            Some((cached_key, stats)) if cached_key == key => stats,
+            _ => {
+                let stats = buffer.stats();
+                self.cached_stats = Some((key, stats));
+                stats
             }
         }
-        buf.write(b"\x1b[K")?;
-        Ok(())
     }
 
-    fn draw_rows<W: Write>(&self, mut buf: W) -> io::Result<()> {
-        for y in 0..self.screen_rows {
-            let file_row = y + self.rowoff;
-            if file_row >= self.row.len() {
-                if self.row.is_empty() && y == self.screen_rows / 3 {
-                    let msg_buf = format!("Rustitor editor -- version {}", VERSION);
-                    let welcome = self.trim_line(&msg_buf);
-                    let padding = (self.screen_cols - welcome.len()) / 2;
-                    if padding > 0 {
-                        buf.write(b"~")?;
-                        for _ in 0..padding - 1 {
-                            buf.write(b" ")?;
-                        }
-                    }
-                    buf.write(welcome.as_bytes())?;
-                } else {
-                    buf.write(b"~")?;
-                }
-            } else {
-                let line = self.trim_line(&self.row[file_row].render);
-                buf.write(line.as_bytes())?;
+    // Screen row and column of the cursor, counted from 0
+    fn cursor_position(&self) -> (usize, usize) {
+        match self.prompt_cursor {
+            Some(col) => (self.screen_rows + 1, cmp::min(col, self.screen_cols.saturating_sub(1))),
+            None if self.sidebar_focused => (self.browser.selected_row(), 0),
+            None => {
+                let (row, col) = self.buffer().screen_cursor(self.text_cols());
+                (row, col + self.text_left())
             }
-            
-            buf.write(b"\x1b[K")?;
-            buf.write(b"\r\n")?;
         }
-        Ok(())
     }
 
-    fn refresh_screen(&self) -> io::Result<()> {
+    fn refresh_screen(&mut self) -> io::Result<()> {
         let mut buf = Vec::with_capacity((self.screen_rows + 1) * self.screen_cols);
-        
-        buf.write(b"\x1b[?25l")?;
-        buf.write(b"\x1b[H")?;
-
-        self.draw_rows(&mut buf)?;
-        self.draw_status_bar(&mut buf)?;
-        self.draw_message_bar(&mut buf)?;
-
-        let cursor_row = self.cy - self.rowoff + 1;
-        let cursor_col = self.rx - self.coloff + 1;
-        write!(buf, "\x1b[{};{}H", cursor_row, cursor_col)?;
-        
-        buf.write(b"\x1b[?25h")?;
+        self.draw_frame(&mut buf)?;
 
+        // The tests draw frames without a terminal to draw them on
+        if cfg!(test) {
+            return Ok(());
+        }
         let mut stdout = io::stdout();
-        stdout.write(&buf)?;
+        stdout.write_all(&buf)?;
         stdout.flush()
     }
 
-    fn clear_screen(&self) -> io::Result<()> {
+    // The next frame with the terminal title, when that changed
+    fn draw_frame(&mut self, buf: &mut Vec<u8>) -> io::Result<()> {
+        if self.settings.set_title {
+            // A control character in the file name would end the escape sequence early
+            let name: String = self.buffer().name().chars().filter(|c| !c.is_control()).collect();
+            let title = format!("{}rustitor - {}", if self.buffer().is_dirty() { "• " } else { "" }, name);
+            if self.title.as_ref() != Some(&title) {
+                write!(buf, "\x1b]2;{}\x07", title)?;
+                self.title = Some(title);
+            }
+        }
+
+        self.browser.scroll(self.screen_rows);
+        if self.show_stats {
+            self.stats();
+        }
+        if self.has_minimap() {
+            self.update_minimap();
+        }
+        self.frames += 1;
+        self.renderer.render(self, buf)
+    }
+
+    fn clear_screen(&mut self) -> io::Result<()> {
+        self.renderer.invalidate();
         let mut stdout = io::stdout();
-        stdout.write(b"\x1b[2J")?;
-        stdout.write(b"\x1b[H")?;
+        if self.title.is_some() {
+            stdout.write_all(b"\x1b]2;\x07")?;
+        }
+        stdout.write_all(b"\x1b[2J")?;
+        stdout.write_all(b"\x1b[H")?;
         stdout.flush()
     }
 
+    // Stops the process as Ctrl-Z does in a shell, with the terminal as it was before the editor
+    // started. Returns once the shell continues it, with the screen to be drawn again
+    fn suspend(&mut self) -> io::Result<()> {
+        self.clear_screen()?;
+        self.title = None;
+        {
+            let _cooked = CookedMode::new(self.settings.mouse_support);
+            // Raw mode turns off ISIG, so the terminal never sends this on its own
+            if unsafe { libc::raise(libc::SIGTSTP) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        // A resize while stopped only signals the shell, so the size is read again
+        if let Some((w, h)) = term_size::dimensions_stdout() {
+            self.screen_cols = w;
+            self.screen_rows = h.saturating_sub(2);
+        }
+        self.renderer.invalidate();
+        self.setup_scroll();
+        self.refresh_screen()
+    }
+
     fn open_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        let path = path.as_ref();
-        let file = fs::File::open(path)?;
-        for line in io::BufReader::new(file).lines() {
-            self.row.push(Row::new(line?));
+        // Project settings from .editorconfig files override the user's own, but not the command line
+        let mut settings = EditorConfig::for_file(path.as_ref()).apply(self.settings);
+        self.command_line.apply(&mut settings);
+        let mut buffer = EditorBuffer::open(path.as_ref(), settings)?;
+        if let Some(pos) = self.history_path.as_deref().and_then(|h| CursorHistory::load(h).get(path.as_ref())) {
+            // The file may have shrunk since
+            let _ = buffer.load_rows((pos.cy + 1).saturating_sub(buffer.rows.len()));
+            buffer.cy = cmp::min(pos.cy, buffer.rows.len().saturating_sub(1));
+            buffer.cx = buffer.rows.get(buffer.cy).map_or(0, |row| cmp::min(pos.cx, row.grapheme_count()));
+            buffer.rowoff = cmp::min(pos.rowoff, buffer.cy);
+        }
+        if self.lock_files && !buffer.settings.read_only {
+            match LockFile::acquire(path.as_ref()) {
+                Ok(lock) => buffer.lock = Some(lock),
+                Err(LockError::Held(pid)) => {
+                    self.message = StatusMessage::new(format!(
+                        "File is being edited by another rustitor process (PID {})! \
+                         Press Ctrl+L to open read-only, any other key to open anyway.",
+                        pid
+                    ));
+                    self.refresh_screen()?;
+                    if self.read_key()? == Some(InputSeq::Key(b'l', true)) {
+                        buffer.settings.read_only = true;
+                    }
+                    self.message = StatusMessage::new("");
+                }
+                // A directory which can't be written to can't hold a lock, nor the saved file
+                Err(LockError::Io(err)) if err.kind() == io::ErrorKind::PermissionDenied => {}
+                Err(LockError::Io(err)) => {
+                    self.message = StatusMessage::new(format!("Can't create the lock file: {}", err));
+                }
+            }
         }
-        self.file = Some(FilePath::from(path));
-        self.dirty = false;
+        self.add_buffer(buffer);
         Ok(())
     }
 
-    fn save(&mut self) -> io::Result<()> {
-        if self.file.is_none() {
-            if let Some(input) = self.prompt("Save as: ")? {
-                self.file = Some(FilePath {
-                    path: PathBuf::from(&input),
-                    display: input,
-                });
+    // Saves the cursor position of each buffer with a file, or just of `only`
+    fn remember_positions(&self, only: Option<usize>) {
+        let history_path = match self.history_path {
+            Some(ref path) => path,
+            None => return,
+        };
+        // Read again in case another instance wrote to it since
+        let mut history = CursorHistory::load(history_path);
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            if only.is_some_and(|only| only != i) {
+                continue;
+            }
+            if let Some(ref file) = buffer.file {
+                let (cy, cx, rowoff) = (buffer.cy, buffer.cx, buffer.rowoff);
+                history.set(&file.path, SavedPosition { cy, cx, rowoff });
             }
         }
-        let ref file = if let Some(ref file) = self.file {
-            file
+        let _ = history.save();
+    }
+
+    // Takes the place of an untouched buffer, or opens next to the others
+    fn add_buffer(&mut self, buffer: EditorBuffer) {
+        // The new buffer may take the index and change count of the stats
+        self.cached_stats = None;
+        self.cached_minimap = None;
+        if self.buffer().is_pristine() {
+            *self.buffer_mut() = buffer;
         } else {
-            return Ok(());
+            self.buffers.push(buffer);
+            self.current = self.buffers.len() - 1;
+        }
+    }
+
+    // Puts the cursor on a 1-based line and column, with the line in the middle of the screen
+    fn jump_to(&mut self, line: usize, col: usize) {
+        let rows = self.screen_rows;
+        let buffer = self.buffer_mut();
+        // A read error is reported when the file is saved, which needs every line
+        let _ = buffer.load_rows(line.saturating_sub(buffer.rows.len()));
+        buffer.cy = cmp::min(line.saturating_sub(1), buffer.rows.len().saturating_sub(1));
+        buffer.cx = match buffer.rows.get(buffer.cy) {
+            Some(row) => cmp::min(col.saturating_sub(1), row.grapheme_count()),
+            None => 0,
         };
+        buffer.rowoff = buffer.cy.saturating_sub(rows / 2);
+        buffer.wrapoff = 0;
+        self.setup_scroll();
+        self.message = StatusMessage::new(format!("Jumped to line {}", self.buffer().cy + 1));
+    }
 
-        let mut f = io::BufWriter::new(fs::File::create(&file.path)?);
-        let mut bytes = 0;
-        for line in self.row.iter() {
-            let b = line.buf.as_bytes();
-            f.write(b)?;
-            f.write(b"\n")?;
-            bytes += b.len() + 1;
+    fn save(&mut self) -> io::Result<()> {
+        if self.buffer().file.is_none() {
+            return self.save_as(false);
         }
-        f.flush()?;
-
-        let msg = format!("{} bytes written to {}", bytes, &file.display);
-        self.message = StatusMessage::new(msg);
-        self.dirty = false;
+        let written = self.buffer_mut().write_file();
+        self.report_write(written, self.buffer().name().to_string());
         Ok(())
     }
 
-    fn setup_scroll(&mut self) {
-
-        if self.cy < self.row.len() {
-            self.rx = self.row[self.cy].rx_from_cx(self.cx);
+    // With `copy` the buffer keeps its current file and dirty state
+    fn save_as(&mut self, copy: bool) -> io::Result<()> {
+        let label = if copy { "Save a copy as: " } else { "Save as: " };
+        let path = match self.prompt(label)? {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let written = if copy {
+            self.buffer_mut().write_to(Path::new(&path))
         } else {
-            self.rx = 0;
-        }
-
-        if self.cy < self.rowoff {
-
-            self.rowoff = self.cy;
-        }
-        if self.cy >= self.rowoff + self.screen_rows {
+            self.buffer_mut().write_file_as(&path)
+        };
+        self.report_write(written, path);
+        Ok(())
+    }
 
-            self.rowoff = self.cy - self.screen_rows + 1;
-        }
-        if self.rx < self.coloff {
-            self.coloff = self.rx;
+    // Tab completes the path as far as the matching entries agree, then cycles through them
+    fn open_prompt(&mut self) -> io::Result<()> {
+        let mut cycle: Option<(Vec<String>, usize)> = None;
+        let path = self.prompt_with("Open file: ", |editor, input, seq| {
+            if *seq != InputSeq::Key(b'i', true) {
+                return;
+            }
+            if let Some((ref paths, ref mut i)) = cycle {
+                if paths[*i] == *input {
+                    *i = (*i + 1) % paths.len();
+                    *input = paths[*i].clone();
+                    return;
+                }
+            }
+            let paths = complete_path(input);
+            let common = match paths.first() {
+                Some(first) => paths.iter().fold(first.as_str(), |common, path| common_prefix(common, path)),
+                None => {
+                    editor.message.text.push_str(" (no matches)");
+                    return;
+                }
+            };
+            if common.len() > input.len() || paths.len() == 1 {
+                *input = common.to_string();
+            } else {
+                *input = paths[0].clone();
+                cycle = Some((paths, 0));
+            }
+        })?;
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(()),
+        };
+        if !path.exists() {
+            self.message = StatusMessage::new(format!("Can't open {}: no such file", path.display()));
+            return Ok(());
         }
-        if self.rx >= self.coloff + self.screen_cols {
-            self.coloff = self.rx - self.screen_cols + 1;
+        if let Err(err) = self.open_file(&path) {
+            self.message = StatusMessage::new(format!("Can't open {}: {}", path.display(), err));
         }
+        Ok(())
     }
 
-    fn insert_char(&mut self, ch: char) {
-        if self.cy == self.row.len() {
-            self.row.push(Row::empty());
-        }
-        self.row[self.cy].insert_char(self.cx, ch);
-        self.cx += 1;
-        self.dirty = true;
+    fn report_write(&mut self, written: io::Result<WriteResult>, name: String) {
+        let msg = match written {
+            Ok(written) if written.replaced > 0 => format!(
+                "{} bytes written to {}, {} characters not in {} were written as ?",
+                written.bytes,
+                name,
+                written.replaced,
+                self.buffer().encoding.name()
+            ),
+            Ok(written) if written.copied => format!(
+                "{} bytes written to {} (copied, the save was not atomic)",
+                written.bytes, name
+            ),
+            Ok(written) => format!("{} bytes written to {}", written.bytes, name),
+            Err(err) => format!("Can't save {}: {}", name, err),
+        };
+        self.message = StatusMessage::new(msg);
     }
 
-    fn delete_char(&mut self) {
-        if self.cy == self.row.len() || self.cx == 0 && self.cy == 0 {
-            return;
+    fn new_buffer(&mut self) {
+        self.buffers.push(EditorBuffer::new(self.settings));
+        self.current = self.buffers.len() - 1;
+    }
+
+    // Closing the last buffer leaves an empty one rather than quitting
+    fn close_buffer(&mut self) {
+        self.remember_positions(Some(self.current));
+        if let Some(ref file) = self.buffers[self.current].file {
+            for client in &mut self.lsp {
+                let _ = client.close(&file.path);
+            }
         }
-        if self.cx > 0 {
-            self.row[self.cy].delete_char(self.cx - 1);
-            self.cx -= 1;
-        } else {
-            self.cx = self.row[self.cy - 1].buf.len();
-            let row = self.row.remove(self.cy);
-            self.cy -= 1;
-            self.row[self.cy].append(row.buf);
+        self.buffers.remove(self.current);
+        self.cached_stats = None;
+        if self.buffers.is_empty() {
+            self.buffers.push(EditorBuffer::new(self.settings));
+        }
+        if self.current >= self.buffers.len() {
+            self.current = self.buffers.len() - 1;
         }
-        self.dirty = true;
     }
 
-    fn insert_line(&mut self) {
-        if self.cy >= self.row.len() {
-            self.row.push(Row::new(""));
-        } else if self.cx >= self.row[self.cy].buf.len() {
-            self.row.insert(self.cy + 1, Row::new(""));
+    fn switch_buffer(&mut self, forward: bool) {
+        let len = self.buffers.len();
+        self.current = if forward {
+            (self.current + 1) % len
         } else {
-            let split = String::from(&self.row[self.cy].buf[self.cx..]);
-            self.row[self.cy].truncate(self.cx);
-            self.row.insert(self.cy + 1, Row::new(split));
-        }
-        self.cy += 1;
-        self.cx = 0;
-    }
-
-    fn move_cursor(&mut self, dir: CursorDir) {
-        match dir {
-            CursorDir::Up => self.cy = self.cy.saturating_sub(1),
-            CursorDir::Left => {
-                if self.cx > 0 {
-                    self.cx -= 1;
-                } else if self.cy > 0 {
-                    self.cy -= 1;
-                    self.cx = self.row[self.cy].buf.len();
-                }
-            }
-            CursorDir::Down => {
-                if self.cy < self.row.len() {
-                    self.cy += 1;
-                }
-            }
-            CursorDir::Right => {
-                if self.cy < self.row.len() {
-                    let len = self.row[self.cy].buf.len();
-                    if self.cx < len {
-                        self.cx += 1;
-                    } else if self.cx >= len {
-                        self.cy += 1;
-                        self.cx = 0;
-                    }
-                }
+            (self.current + len - 1) % len
+        };
+    }
+
+    // Tab completes the name from the buffers listed in the prompt
+    fn switch_to_named_buffer(&mut self) -> io::Result<()> {
+        let names: Vec<String> = self.buffers.iter().map(|buffer| buffer.name().to_string()).collect();
+        let prompt = format!("Switch to buffer ({}): ", names.join(", "));
+        let name = self.prompt_with(prompt, |_, input, seq| {
+            if *seq != InputSeq::Key(b'i', true) {
+                return;
+            }
+            let mut matches = names.iter().filter(|name| name.starts_with(input.as_str()));
+            if let Some(first) = matches.next() {
+                *input = matches.fold(first.as_str(), |common, name| common_prefix(common, name)).to_string();
             }
+        })?;
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(()),
         };
-        let len = self.row.get(self.cy).map(|r| r.buf.len()).unwrap_or(0);
-        if self.cx > len {
-            self.cx = len;
+        let exact = self.buffers.iter().position(|buffer| buffer.name() == name);
+        let prefixed: Vec<usize> = (0..self.buffers.len()).filter(|&i| self.buffers[i].name().starts_with(&name)).collect();
+        match (exact, prefixed.as_slice()) {
+            (Some(i), _) | (None, &[i]) => self.current = i,
+            (None, []) => self.message = StatusMessage::new(format!("No buffer named {}", name)),
+            (None, _) => self.message = StatusMessage::new(format!("More than one buffer starts with {}", name)),
         }
+        Ok(())
+    }
+
+    // Only in place of an empty screen, until the first character is typed or a file is opened
+    fn shows_welcome(&self) -> bool {
+        self.settings.show_welcome && self.buffer().rows.is_empty() && self.buffer().file.is_none()
+    }
+
+    fn setup_scroll(&mut self) {
+        let (rows, cols) = (self.screen_rows, self.text_cols());
+        self.buffer_mut().setup_scroll(rows, cols);
+        self.bracket_match = self.buffer().matching_bracket();
     }
 
     fn prompt<S: Into<String>>(&mut self, prompt: S) -> io::Result<Option<String>> {
+        self.prompt_with(prompt, |_, _, _| {})
+    }
+
+    fn prompt_with<S, F>(&mut self, prompt: S, mut on_key: F) -> io::Result<Option<String>>
+    where
+        S: Into<String>,
+        F: FnMut(&mut Self, &mut String, &InputSeq),
+    {
         let prompt = prompt.into();
-        let prompt_len = prompt.len();
-        self.message = StatusMessage::new(prompt);
+        let mut input = String::new();
+        self.message = StatusMessage::new(prompt.clone());
+        self.prompt_cursor = Some(prompt.width());
         self.refresh_screen()?;
 
-        while let Some(seq) = self.input.next() {
-            self.message.reset_timestamp();
+        while let Some(seq) = self.next_seq() {
+            let seq = seq?;
 
-            match seq? {
+            let mut finished = false;
+            let mut canceled = false;
+            match seq {
                 InputSeq::Unidentified => continue,
-                InputSeq::Key(b'h', true) | InputSeq::Key(0x7f, false) | InputSeq::DeleteKey
-                    if self.message.text.len() > prompt_len => 
-                    {
-                        self.message.text.pop();
-                    }
-                InputSeq::Key(b'g', true) | InputSeq::Key(0x1b, false) => {
-                    self.message = StatusMessage::new("Canceled.");
-                    return Ok(None);
-                }
-                InputSeq::Key(b'\r', false) | InputSeq::Key(b'm', true) => break,
-                InputSeq::Key(b, false) => {
-                    self.message.text.push(b as char);
+                InputSeq::Key(b'h', true) | InputSeq::Key(0x7f, false) | InputSeq::DeleteKey => {
+                    input.pop();
                 }
-                _ => continue,
+                InputSeq::Key(b'g', true) | InputSeq::Key(0x1b, false) => canceled = true,
+                InputSeq::Key(b'\r', false) | InputSeq::Key(b'm', true) => finished = true,
+                InputSeq::Key(b, false) if b != 0x7f => input.push(b as char),
+                InputSeq::Char(c) => input.push(c),
+                _ => {}
+            }
+
+            // The callback may append extra information after the input, or change the input
+            self.message = StatusMessage::new(format!("{}{}", prompt, input));
+            self.prompt_cursor = Some(prompt.width() + input.width());
+            let typed = input.clone();
+            on_key(self, &mut input, &seq);
+            if input != typed {
+                self.message = StatusMessage::new(format!("{}{}", prompt, input));
+                self.prompt_cursor = Some(prompt.width() + input.width());
+            }
+
+            if canceled {
+                self.prompt_cursor = None;
+                self.message = StatusMessage::new("Canceled.");
+                return Ok(None);
+            }
+            if finished {
+                break;
             }
             self.refresh_screen()?;
         }
 
-        let input = if self.message.text.len() > prompt_len {
-            Some(String::from(&self.message.text[prompt_len..]))
+        self.prompt_cursor = None;
+        self.message.text.clear();
+        if input.is_empty() {
+            Ok(None)
         } else {
-            None
+            Ok(Some(input))
+        }
+    }
+
+    // Commands typed after Alt-:. Shell commands are written as !command
+    fn command_line(&mut self) -> io::Result<()> {
+        let command = match self.prompt(":")? {
+            Some(command) => command,
+            None => return Ok(()),
         };
+        if let Some(shell) = command.strip_prefix('!') {
+            if !shell.trim().is_empty() {
+                self.shell_command(shell);
+            }
+            return Ok(());
+        }
+        let (name, arg) = command.trim().split_once(' ').unwrap_or((command.trim(), ""));
+        match name {
+            "" => {}
+            "set" => self.set_option(arg.trim()),
+            "mksession" => {
+                let path = if arg.trim().is_empty() { "session.rstor" } else { arg.trim() };
+                self.message = match Session::save(self, Path::new(path)) {
+                    Ok(()) => StatusMessage::new(format!("Session saved to {}", path)),
+                    Err(err) => StatusMessage::new(format!("Can't save the session to {}: {}", path, err)),
+                };
+            }
+            _ => self.message = StatusMessage::new(format!("Unknown command: {}", command)),
+        }
+        Ok(())
+    }
 
-        self.message.text.clear();
-        Ok(input)
+    // `:set name=value` for the options of the current buffer
+    fn set_option(&mut self, option: &str) {
+        let (name, value) = option.split_once('=').unwrap_or((option, ""));
+        match name.trim() {
+            "encoding" => match Encoding::from_name(value.trim()) {
+                Some(_) if self.buffer().settings.read_only => self.message = StatusMessage::new("File is read-only"),
+                Some(encoding) => {
+                    self.buffer_mut().set_encoding(encoding);
+                    self.message = StatusMessage::new(format!("The file will be saved as {}", encoding.name()));
+                }
+                None => self.message = StatusMessage::new(format!("Unknown encoding: {}", value.trim())),
+            },
+            name => self.message = StatusMessage::new(format!("Unknown option: {}", name)),
+        }
     }
 
-    fn process_keypress(&mut self, seq: InputSeq) -> io::Result<AfterKeyPress> {
+    // The selected text is piped through the command and replaced by its output. Without a
+    // selection the output opens in a new read-only buffer
+    fn shell_command(&mut self, command: &str) {
+        let input = self.buffer().selected_text().map(|lines| lines.join("\n"));
+        if input.is_some() && self.buffer().settings.read_only {
+            self.message = StatusMessage::new("File is read-only");
+            return;
+        }
+        let output = {
+            let _cooked = CookedMode::new(self.settings.mouse_support);
+            run_shell(command, input.as_deref())
+        };
+        // Anything the command wrote to the terminal is drawn over
+        self.renderer.invalidate();
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                self.message = StatusMessage::new(format!("Can't run sh: {}", err));
+                return;
+            }
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let error = stderr.lines().find(|line| !line.trim().is_empty()).unwrap_or("");
+            self.message = StatusMessage::new(format!("Command failed ({}): {}", output.status, error));
+            return;
+        }
 
-        match seq {
-            InputSeq::Key(b'p', true) | InputSeq::UpKey => self.move_cursor(CursorDir::Up),
-            InputSeq::Key(b'b', true) | InputSeq::LeftKey => self.move_cursor(CursorDir::Left),
-            InputSeq::Key(b'n', true) | InputSeq::DownKey => self.move_cursor(CursorDir::Down),
-            InputSeq::Key(b'f', true) | InputSeq::RightKey => self.move_cursor(CursorDir::Right),
-            InputSeq::PageUpKey => {
-                self.cy = self.rowoff;
-                for _ in 0..self.screen_rows {
-                    self.move_cursor(CursorDir::Up);
+        let mut stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        match input {
+            Some(input) => {
+                // Most commands end their output with a newline, even when the input had none
+                if !input.ends_with('\n') && stdout.ends_with('\n') {
+                    stdout.pop();
                 }
+                let lines: Vec<String> = stdout.split('\n').map(String::from).collect();
+                self.buffer_mut().replace_selection(&lines);
             }
-            InputSeq::PageDownKey => {
-                self.cy = cmp::min(self.rowoff + self.screen_rows - 1, self.row.len());
-                for _ in 0..self.screen_rows {
-                    self.move_cursor(CursorDir::Down)
-                }
+            None => {
+                let settings = Settings { read_only: true, ..self.settings };
+                let lines = stdout.lines().map(String::from).collect();
+                self.add_buffer(EditorBuffer::scratch("*shell output*", lines, settings));
             }
-            InputSeq::Key(b'a', true) | InputSeq::HomeKey => self.cx = 0,
-            InputSeq::Key(b'e', true) | InputSeq::EndKey => {
-                if self.cy < self.row.len() {
-                    self.cx = self.screen_cols - 1;
-                }
+        }
+    }
+
+    // Formats the selection, or the whole buffer, with the formatter for the file's type
+    fn format(&mut self) {
+        let file_type = self.buffer().file.as_ref().and_then(|file| highlight::file_type(&file.path));
+        let formatter = file_type.and_then(|t| self.formatters.iter().find(|(name, _)| name == t));
+        match formatter {
+            Some((_, command)) => {
+                let command = command.clone();
+                self.format_selection_with(&command);
             }
-            InputSeq::DeleteKey | InputSeq::Key(b'd', true) => {
-                self.move_cursor(CursorDir::Right);
-                self.delete_char();
-            } 
-            
-            InputSeq::Key(b'q', true) => {
-                if !self.dirty || self.quitting {
-                    return Ok(AfterKeyPress::Quit);
+            None => self.message = StatusMessage::new("No formatter for this file type"),
+        }
+    }
+
+    // Writes the selected rows, or every row without a selection, to a temporary file and runs
+    // `cmd` with the file's path appended. What the command leaves in the file replaces the rows
+    fn format_selection_with(&mut self, cmd: &str) {
+        if let Err(err) = self.buffer_mut().load_all() {
+            self.message = StatusMessage::new(format!("Can't read the whole file: {}", err));
+            return;
+        }
+        let buffer = self.buffer();
+        let (start, end) = match buffer.selected_row_range() {
+            Some(range) => range,
+            None if buffer.rows.is_empty() => return,
+            None => (0, buffer.rows.len() - 1),
+        };
+        let mut text = String::new();
+        for y in start..=end {
+            text.push_str(&buffer.rows[y].buf);
+            text.push('\n');
+        }
+        // Formatters tell the language by the extension
+        let extension = buffer.file.as_ref().and_then(|f| f.path.extension()).and_then(|e| e.to_str());
+        let suffix = format!(".{}", extension.unwrap_or("txt"));
+        let (path, mut file) = match buffer::create_temp_file(&std::env::temp_dir(), &suffix) {
+            Ok(created) => created,
+            Err(err) => {
+                self.message = StatusMessage::new(format!("Can't create a temporary file: {}", err));
+                return;
+            }
+        };
+
+        // The file is removed whatever happens from here on
+        let result = file.write_all(text.as_bytes()).and_then(|_| {
+            drop(file);
+            Command::new("sh")
+                .arg("-c")
+                .arg(format!("{} \"$1\"", cmd))
+                .arg("sh")
+                .arg(&path)
+                .stdin(Stdio::null())
+                .output()
+        });
+        let formatted = std::fs::read_to_string(&path);
+        let _ = std::fs::remove_file(&path);
+        let output = match result {
+            Ok(output) => output,
+            Err(err) => {
+                self.message = StatusMessage::new(format!("Can't run {}: {}", cmd, err));
+                return;
+            }
+        };
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let error = stderr.lines().find(|line| !line.trim().is_empty());
+        if !output.status.success() {
+            self.message = StatusMessage::new(format!("Formatter failed: {}", error.unwrap_or("")));
+            return;
+        }
+        let formatted = match formatted {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                self.message = StatusMessage::new(format!("Can't read the formatted text: {}", err));
+                return;
+            }
+        };
+
+        let lines: Vec<String> = formatted.lines().map(String::from).collect();
+        let changed = self.buffer_mut().replace_rows(start, end, &lines);
+        self.message = match (error, changed) {
+            (Some(error), _) => StatusMessage::new(error),
+            (None, true) => StatusMessage::new("Formatted"),
+            (None, false) => StatusMessage::new("Already formatted"),
+        };
+    }
+
+    // Every match of `query` in the rendered rows, as rows and indexes in their `render`. Unlike the
+    // find prompt this is exact, and sees tabs as the spaces they are drawn as
+    #[cfg(test)]
+    pub fn search_all(&mut self, query: &str) -> Vec<(usize, usize)> {
+        let _ = self.buffer_mut().load_all();
+        let rows = self.buffer().rows.iter().enumerate();
+        rows.flat_map(|(y, row)| row.matches(query).into_iter().map(move |x| (y, x))).collect()
+    }
+
+    fn go_to_line(&mut self) -> io::Result<()> {
+        let _ = self.buffer_mut().load_all();
+        let total = self.buffer().rows.len();
+        let prompt = "Go to line: ";
+        let buffer = self.buffer();
+        let (saved_cx, saved_cy) = (buffer.cx, buffer.cy);
+        let saved_view = (buffer.rowoff, buffer.coloff, buffer.wrapoff);
+
+        let input = self.prompt_with(prompt, |editor, input, _| {
+            editor.message.text.push_str(&format!(" (of {})", total));
+            if let Ok(line) = input.parse::<usize>() {
+                if line > 0 && line <= total {
+                    let buffer = editor.buffer_mut();
+                    buffer.cy = line - 1;
+                    buffer.cx = 0;
+                    editor.setup_scroll();
+                }
+            }
+        })?;
+
+        // Undo the preview done while typing, the cursor only moves to a valid line below
+        let buffer = self.buffer_mut();
+        buffer.cx = saved_cx;
+        buffer.cy = saved_cy;
+        (buffer.rowoff, buffer.coloff, buffer.wrapoff) = saved_view;
+        let input = match input {
+            Some(input) => input,
+            None => return Ok(()),
+        };
+
+        match input.parse::<usize>() {
+            Ok(line) if line > 0 && line <= total => {
+                let buffer = self.buffer_mut();
+                buffer.cy = line - 1;
+                buffer.cx = 0;
+            }
+            Ok(line) => {
+                self.message = StatusMessage::new(format!("Line {} is out of range (1-{})", line, total));
+            }
+            Err(_) => {
+                self.message = StatusMessage::new(format!("Not a line number: {}", input));
+            }
+        }
+        Ok(())
+    }
+
+    fn search(&mut self, dir: SearchDir) -> io::Result<()> {
+        let label = match dir {
+            SearchDir::Forward => "Find: ",
+            SearchDir::Backward => "Find backward: ",
+        };
+        self.search_with(label, dir)?;
+        Ok(())
+    }
+
+    // Leaves the cursor on the match and returns the query when something was found
+    fn search_with(&mut self, label: &str, dir: SearchDir) -> io::Result<Option<String>> {
+        // Matches can be anywhere in the file, not only in the lines read so far
+        let _ = self.buffer_mut().load_all();
+        let buffer = self.buffer();
+        let (saved_cx, saved_cy) = (buffer.cx, buffer.cy);
+        let saved_view = (buffer.rowoff, buffer.coloff, buffer.wrapoff);
+
+        let mut dir = dir;
+        let query = self.prompt_with(label, |editor, query, seq| {
+            let (cx, cy, skip) = match seq {
+                InputSeq::Key(b'f', true) | InputSeq::DownKey | InputSeq::RightKey | InputSeq::FnKey(3) => {
+                    dir = SearchDir::Forward;
+                    (editor.buffer().cx, editor.buffer().cy, true)
+                }
+                InputSeq::Key(b'r', true) | InputSeq::UpKey | InputSeq::LeftKey | InputSeq::ShiftFnKey(3) => {
+                    dir = SearchDir::Backward;
+                    (editor.buffer().cx, editor.buffer().cy, true)
+                }
+                InputSeq::Key(b'\r', false)
+                | InputSeq::Key(b'm', true)
+                | InputSeq::Key(b'g', true)
+                | InputSeq::Key(0x1b, false) => return,
+                InputSeq::AltKey(b'i') => {
+                    editor.search_ignore_case = !editor.search_ignore_case;
+                    (saved_cx, saved_cy, false)
+                }
+                _ => (saved_cx, saved_cy, false),
+            };
+
+            editor.search_query = Some(query.to_string());
+            editor.search_match = None;
+            let ignore_case = editor.search_ignore_case;
+            let found = editor.buffer().find_match(query, cx, cy, dir, skip, ignore_case);
+            if let Some((x, y)) = found {
+                editor.buffer_mut().cx = x;
+                editor.buffer_mut().cy = y;
+                editor.search_match = Some(y);
+            } else if query.is_empty() {
+                editor.buffer_mut().cx = saved_cx;
+                editor.buffer_mut().cy = saved_cy;
+            }
+            editor.setup_scroll();
+        })?;
+
+        let found = self.search_match.take().is_some();
+        self.search_query = None;
+        if query.is_none() || !found {
+            let buffer = self.buffer_mut();
+            buffer.cx = saved_cx;
+            buffer.cy = saved_cy;
+            (buffer.rowoff, buffer.coloff, buffer.wrapoff) = saved_view;
+        }
+        match query {
+            Some(query) if !found => {
+                self.message = StatusMessage::new(format!("Not found: {}", query));
+                Ok(None)
+            }
+            Some(query) => {
+                self.last_search = Some(query.clone());
+                Ok(Some(query))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Goes to the next match of the last search, or the previous one
+    fn search_again(&mut self, dir: SearchDir) {
+        let query = match self.last_search {
+            Some(ref query) => query.clone(),
+            None => {
+                self.message = StatusMessage::new("No previous search");
+                return;
+            }
+        };
+        let _ = self.buffer_mut().load_all();
+        let (cx, cy, ignore_case) = (self.buffer().cx, self.buffer().cy, self.search_ignore_case);
+        match self.buffer().find_match(&query, cx, cy, dir, true, ignore_case) {
+            Some((x, y)) => {
+                self.buffer_mut().cx = x;
+                self.buffer_mut().cy = y;
+            }
+            None => self.message = StatusMessage::new(format!("Not found: {}", query)),
+        }
+    }
+
+    fn toggle_bookmark(&mut self) {
+        let cy = self.buffer().cy;
+        let msg = if self.buffer_mut().toggle_bookmark(cy) { "Bookmark set" } else { "Bookmark cleared" };
+        self.message = StatusMessage::new(format!("{} at line {}", msg, cy + 1));
+    }
+
+    fn jump_to_bookmark(&mut self, forward: bool) {
+        match self.buffer().next_bookmark(self.buffer().cy, forward) {
+            Some(y) => {
+                self.jump_to(y + 1, 1);
+                self.message = StatusMessage::new(format!("Bookmark at line {}", y + 1));
+            }
+            None => self.message = StatusMessage::new("No bookmarks"),
+        }
+    }
+
+    // Lists the keys in a read-only buffer, or switches to the one already open
+    fn show_help(&mut self) {
+        if let Some(i) = self.buffers.iter().position(|b| b.file.is_none() && b.name() == HELP_BUFFER) {
+            self.current = i;
+            return;
+        }
+        let mut buffer = EditorBuffer::scratch(HELP_BUFFER, self.bindings.help_lines(), self.settings);
+        buffer.settings.read_only = true;
+        self.add_buffer(buffer);
+    }
+
+    fn replace(&mut self) -> io::Result<()> {
+        let query = match self.search_with("Replace: ", SearchDir::Forward)? {
+            Some(query) => query,
+            None => return Ok(()),
+        };
+        let replacement = match self.prompt(format!("Replace {} with: ", query))? {
+            Some(replacement) => replacement,
+            None => return Ok(()),
+        };
+
+        let ignore_case = self.search_ignore_case;
+        let mut count = 0;
+        self.search_query = Some(query.clone());
+        loop {
+            self.search_match = Some(self.buffer().cy);
+            self.message = StatusMessage::new("Replace? (y)es, (n)o, (a)ll, (q)uit");
+            self.setup_scroll();
+            self.refresh_screen()?;
+
+            let (cx, cy) = (self.buffer().cx, self.buffer().cy);
+            let skip = match self.next_seq() {
+                Some(seq) => match seq? {
+                    InputSeq::Key(b'y', false) => {
+                        if self.buffer_mut().replace_next(&query, &replacement, ignore_case) {
+                            count += 1;
+                        }
+                        false
+                    }
+                    InputSeq::Key(b'n', false) => true,
+                    InputSeq::Key(b'a', false) => {
+                        count += self.buffer_mut().replace_all(&query, &replacement, ignore_case);
+                        break;
+                    }
+                    InputSeq::Key(b'q', false) | InputSeq::Key(0x1b, false) | InputSeq::Key(b'g', true) => break,
+                    _ => continue,
+                },
+                None => break,
+            };
+
+            // Stop instead of wrapping around so that a replacement containing the query terminates
+            let buffer = self.buffer_mut();
+            match buffer.find_match(&query, buffer.cx, buffer.cy, SearchDir::Forward, skip, ignore_case) {
+                Some((x, y)) if (y, x) > (cy, cx) || (!skip && (y, x) == (cy, cx)) => {
+                    buffer.cx = x;
+                    buffer.cy = y;
+                }
+                _ => break,
+            }
+        }
+        self.search_query = None;
+        self.search_match = None;
+
+        self.message = StatusMessage::new(format!("{} replacements made", count));
+        Ok(())
+    }
+
+    fn select(&mut self, dir: CursorDir) {
+        let buffer = self.buffer_mut();
+        if buffer.selection.is_none() {
+            buffer.selection = Some((buffer.cy, buffer.cx));
+        }
+        buffer.move_cursor(dir);
+    }
+
+    // Returns false when nothing is selected
+    fn yank_selection(&mut self) -> bool {
+        match self.buffer().column_text().or_else(|| self.buffer().selected_text()) {
+            Some(text) => {
+                match self.pending_register.take() {
+                    Some(name) => self.set_register(name, text.join("\n")),
+                    None => {
+                        self.copy_to_system(&text.join("\n"));
+                        self.clipboard = text;
+                    }
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // + also goes to the system clipboard. % and . can't be set
+    fn set_register(&mut self, name: char, value: String) {
+        match name {
+            '%' | '.' => self.message = StatusMessage::new(format!("Register {} is read-only", name)),
+            '+' => {
+                self.copy_to_system(&value);
+                self.registers.insert(name, value);
+            }
+            _ => {
+                self.registers.insert(name, value);
+            }
+        }
+    }
+
+    // % is the file name and . the text typed last. + holds what was last copied to or pasted
+    // from the system clipboard
+    fn get_register(&self, name: char) -> Option<&str> {
+        match name {
+            '%' => self.buffer().file.as_ref().map(|file| file.display.as_str()),
+            '.' if !self.last_insert.is_empty() => Some(&self.last_insert),
+            '.' => None,
+            _ => self.registers.get(&name).map(String::as_str),
+        }
+    }
+
+    fn paste_register(&mut self, name: char) -> io::Result<()> {
+        if name == '+' {
+            if let Some(text) = self.paste_from_system()? {
+                self.registers.insert(name, text);
+            }
+        }
+        let text: Vec<String> = match self.get_register(name) {
+            Some(text) => text.split('\n').map(|line| line.trim_end_matches('\r').to_string()).collect(),
+            None => {
+                self.message = StatusMessage::new(format!("Register {} is empty", name));
+                return Ok(());
+            }
+        };
+        self.paste_lines(&text);
+        Ok(())
+    }
+
+    fn delete_selection(&mut self) -> bool {
+        if self.buffer().column_selection.is_some() {
+            return self.buffer_mut().replace_columns(&[]);
+        }
+        self.buffer_mut().delete_selection()
+    }
+
+    // Each line goes to a row of its own when columns are selected
+    fn paste_lines(&mut self, lines: &[String]) {
+        if self.buffer().column_selection.is_some() {
+            self.buffer_mut().replace_columns(lines);
+            return;
+        }
+        self.delete_selection();
+        self.buffer_mut().paste(lines);
+    }
+
+    fn copy_to_system(&mut self, text: &str) {
+        let provider = self.settings.clipboard_provider;
+        // Terminals do not acknowledge OSC 52 writes, so a clipboard tool is used as well when there is one
+        if clipboard::uses_osc52(provider) && clipboard::write_osc52(io::stdout(), text).is_err() {
+            self.message = StatusMessage::new("Could not copy to the terminal clipboard");
+        }
+        if let Some(tool) = clipboard::find_tool(provider) {
+            if clipboard::copy_with_tool(tool, text).is_err() {
+                self.message = StatusMessage::new("Could not copy to the system clipboard");
+            }
+        }
+    }
+
+    fn paste_from_system(&mut self) -> io::Result<Option<String>> {
+        let provider = self.settings.clipboard_provider;
+        if clipboard::uses_osc52(provider) {
+            // The tests queue the reply in their input, without a terminal to ask
+            if !cfg!(test) {
+                clipboard::request_osc52(io::stdout())?;
+            }
+            // Wait for the reply until the input times out. Keys typed in between are run after the paste
+            let mut reply = None;
+            let mut typed = vec![];
+            for seq in &mut self.input {
+                match seq? {
+                    InputSeq::Clipboard(data) => {
+                        if !data.is_empty() {
+                            reply = Some(String::from_utf8_lossy(&data).into_owned());
+                        }
+                        break;
+                    }
+                    InputSeq::Unidentified => break,
+                    InputSeq::Cursor(..) => {}
+                    seq => typed.push(seq),
+                }
+            }
+            // The keys come from the input, so a macro being recorded gets them too
+            if let Some(buf) = self.macro_buffer.as_mut() {
+                buf.extend(typed.iter().cloned());
+            }
+            self.replay.extend(typed);
+            if reply.is_some() {
+                return Ok(reply);
+            }
+        }
+        if let Some(tool) = clipboard::find_tool(provider) {
+            if let Ok(Some(text)) = clipboard::paste_with_tool(tool) {
+                return Ok(Some(text));
+            }
+        }
+        Ok(None)
+    }
+
+    fn paste(&mut self) -> io::Result<()> {
+        if let Some(name) = self.pending_register.take() {
+            return self.paste_register(name);
+        }
+        if let Some(text) = self.paste_from_system()? {
+            self.clipboard = text.split('\n').map(|line| line.trim_end_matches('\r').to_string()).collect();
+        }
+        let text = self.clipboard.clone();
+        self.paste_lines(&text);
+        Ok(())
+    }
+
+    fn bracketed_paste(&mut self, data: &[u8]) {
+        let text = String::from_utf8_lossy(data).replace("\r\n", "\n").replace('\r', "\n");
+        // Keep tabs, but no other control characters
+        let text: String = text.chars().filter(|&c| c == '\n' || c == '\t' || !c.is_control()).collect();
+        let lines: Vec<String> = text.split('\n').map(String::from).collect();
+        self.paste_lines(&lines);
+    }
+
+    fn start_completion(&mut self) {
+        let prefix = self.buffer().word_before_cursor().to_string();
+        let mut items = self.buffer().completions(&prefix);
+        if items.is_empty() {
+            self.message = StatusMessage::new("No completions");
+            return;
+        }
+        items.truncate(8);
+        self.completion = Some(Completion { items, selected: 0, prefix_len: prefix.len() });
+    }
+
+    // Keys for the completion popup while it's open. Any other key closes it and runs as usual
+    fn completion_key(&mut self, seq: &InputSeq) -> bool {
+        let completion = match self.completion {
+            Some(ref mut completion) => completion,
+            None => return false,
+        };
+        let len = completion.items.len();
+        match seq {
+            InputSeq::UpKey => completion.selected = (completion.selected + len - 1) % len,
+            InputSeq::DownKey => completion.selected = (completion.selected + 1) % len,
+            InputSeq::Key(b'\r', false) | InputSeq::Key(b'm', true) | InputSeq::Key(b'i', true) => {
+                let rest = completion.items[completion.selected][completion.prefix_len..].to_string();
+                self.completion = None;
+                self.buffer_mut().paste(&[rest]);
+            }
+            InputSeq::Key(0x1b, false) => self.completion = None,
+            _ => {
+                self.completion = None;
+                return false;
+            }
+        }
+        true
+    }
+
+    fn toggle_sidebar(&mut self) {
+        if self.browser.open {
+            self.browser.open = false;
+            self.sidebar_focused = false;
+            return;
+        }
+        // Listed again on every opening, so that new files show up
+        if let Err(err) = self.browser.read_dir() {
+            self.message = StatusMessage::new(format!("Can't list {}: {}", self.browser.path.display(), err));
+            return;
+        }
+        self.browser.open = true;
+        self.sidebar_focused = true;
+    }
+
+    // Keys for the file browser while it has the focus. Other keys than these go to the editor as usual
+    fn sidebar_key(&mut self, seq: &InputSeq) -> bool {
+        if !self.sidebar_focused {
+            return false;
+        }
+        let rows = self.screen_rows;
+        match seq {
+            InputSeq::UpKey => self.browser.move_selection(true, 1),
+            InputSeq::DownKey => self.browser.move_selection(false, 1),
+            InputSeq::PageUpKey => self.browser.move_selection(true, rows),
+            InputSeq::PageDownKey => self.browser.move_selection(false, rows),
+            InputSeq::Key(b'i', true) | InputSeq::Key(0x1b, false) => self.sidebar_focused = false,
+            InputSeq::Key(b'\r', false) | InputSeq::Key(b'm', true) => match self.browser.enter() {
+                Ok(Some(path)) => match self.open_file(&path) {
+                    Ok(()) => self.sidebar_focused = false,
+                    Err(err) => self.message = StatusMessage::new(format!("Can't open {}: {}", path.display(), err)),
+                },
+                Ok(None) => {}
+                Err(err) => self.message = StatusMessage::new(format!("Can't list directory: {}", err)),
+            },
+            // Typing would otherwise go into a buffer which isn't focused
+            InputSeq::Key(_, false) | InputSeq::Char(_) => {}
+            _ => return false,
+        }
+        true
+    }
+
+    fn note_insert(&mut self, c: char, previous: LastAction) {
+        if previous != LastAction::Insert {
+            self.last_insert.clear();
+        }
+        self.last_insert.push(c);
+        self.last_action = LastAction::Insert;
+    }
+
+    // `backward` kills are prepended when joining the previous kill
+    fn kill(&mut self, killed: Option<String>, previous: LastAction, backward: bool) {
+        let text = match killed {
+            Some(text) => text,
+            None => return,
+        };
+        self.last_action = LastAction::Kill;
+        if previous == LastAction::Kill {
+            if let Some(last) = self.kill_ring.back_mut() {
+                if backward {
+                    last.insert_str(0, &text);
+                } else {
+                    last.push_str(&text);
+                }
+                return;
+            }
+        }
+        if self.kill_ring.len() == KILL_RING_SIZE {
+            self.kill_ring.pop_front();
+        }
+        self.kill_ring.push_back(text);
+    }
+
+    // Yanking again right after a yank replaces the text with the previous entry
+    fn yank(&mut self, previous: LastAction) {
+        let len = self.kill_ring.len();
+        if len == 0 {
+            self.message = StatusMessage::new("Kill ring is empty");
+            return;
+        }
+        let idx = match previous {
+            LastAction::Yank(idx) => {
+                self.buffer_mut().undo();
+                (idx + len - 1) % len
+            }
+            _ => len - 1,
+        };
+        let text: Vec<String> = self.kill_ring[idx].split('\n').map(String::from).collect();
+        self.buffer_mut().paste(&text);
+        self.last_action = LastAction::Yank(idx);
+    }
+
+    fn process_keypress(&mut self, seq: InputSeq) -> io::Result<AfterKeyPress> {
+        if let Some(prefix) = self.pending_seq.take() {
+            return self.prefix_command(prefix, seq);
+        }
+        if self.completion_key(&seq) || self.sidebar_key(&seq) {
+            return Ok(AfterKeyPress::Continue);
+        }
+        let seq = match self.keymap.iter().find(|(from, _)| *from == seq) {
+            Some((_, to)) => to.clone(),
+            None => seq,
+        };
+        if self.register_prefix {
+            self.register_prefix = false;
+            match seq {
+                InputSeq::Key(c @ (b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'%' | b'+' | b'.'), false) => {
+                    self.pending_register = Some(c as char);
+                    self.message = StatusMessage::new(format!("Register {}", c as char));
+                }
+                _ => self.message = StatusMessage::new("Not a register name"),
+            }
+            return Ok(AfterKeyPress::Continue);
+        }
+        if seq == InputSeq::AltKey(b'"') {
+            self.register_prefix = true;
+            self.message = StatusMessage::new("Register: ");
+            return Ok(AfterKeyPress::Continue);
+        }
+        if seq == InputSeq::Key(b'u', true) {
+            self.count_prefix = true;
+            self.pending_count = None;
+            self.message = StatusMessage::new("Count: ");
+            return Ok(AfterKeyPress::Continue);
+        }
+        if let InputSeq::AltKey(d @ b'0'..=b'9') = seq {
+            self.add_count_digit(d);
+            return Ok(AfterKeyPress::Continue);
+        }
+        if self.count_prefix {
+            if let InputSeq::Key(d @ b'0'..=b'9', false) = seq {
+                self.add_count_digit(d);
+                return Ok(AfterKeyPress::Continue);
+            }
+            // Like Emacs, Ctrl-U on its own means 4
+            self.count_prefix = false;
+            self.pending_count.get_or_insert(4);
+        }
+
+        let count = self.pending_count.take();
+        if count.is_some() || self.pending_register.is_some() {
+            self.message = StatusMessage::new("");
+        }
+        let times = if is_repeatable(&seq) { count.unwrap_or(1) } else { 1 };
+        for _ in 0..times {
+            if self.run_command(seq.clone(), count)? == AfterKeyPress::Quit {
+                return Ok(AfterKeyPress::Quit);
+            }
+        }
+        // Only a copy, cut or paste right after the name uses the register
+        self.pending_register = None;
+        Ok(AfterKeyPress::Continue)
+    }
+
+    fn add_count_digit(&mut self, d: u8) {
+        let count = self.pending_count.unwrap_or(0).saturating_mul(10).saturating_add((d - b'0') as usize);
+        self.pending_count = Some(count);
+        self.message = StatusMessage::new(format!("Count: {}", count));
+    }
+
+    fn run_command(&mut self, seq: InputSeq, count: Option<usize>) -> io::Result<AfterKeyPress> {
+        if self.buffer().settings.read_only && modifies_buffer(&seq) {
+            self.message = StatusMessage::new("File is read-only");
+            return Ok(AfterKeyPress::Continue);
+        }
+        let last_action = self.last_action;
+        self.last_action = LastAction::Other;
+        if !is_column_command(&seq) {
+            self.buffer_mut().column_selection = None;
+        }
+        let columns = self.buffer().column_selection.is_some();
+
+        match seq {
+            InputSeq::Key(b'p', true) | InputSeq::UpKey => self.buffer_mut().move_cursor(CursorDir::Up),
+            InputSeq::LeftKey => self.buffer_mut().move_cursor(CursorDir::Left),
+            InputSeq::DownKey => self.buffer_mut().move_cursor(CursorDir::Down),
+            InputSeq::RightKey => self.buffer_mut().move_cursor(CursorDir::Right),
+            InputSeq::ShiftUp => self.select(CursorDir::Up),
+            InputSeq::ShiftDown => self.select(CursorDir::Down),
+            InputSeq::ShiftLeft => self.select(CursorDir::Left),
+            InputSeq::ShiftRight => self.select(CursorDir::Right),
+            InputSeq::AltShiftUp => self.buffer_mut().move_cursor(CursorDir::ColumnUp),
+            InputSeq::AltShiftDown => self.buffer_mut().move_cursor(CursorDir::ColumnDown),
+            InputSeq::AltShiftLeft => self.buffer_mut().move_cursor(CursorDir::ColumnLeft),
+            InputSeq::AltShiftRight => self.buffer_mut().move_cursor(CursorDir::ColumnRight),
+            InputSeq::AltKey(b'v') => {
+                let buffer = self.buffer_mut();
+                buffer.selection = match buffer.selection {
+                    Some(_) => None,
+                    None => Some((buffer.cy, buffer.cx)),
+                };
+            }
+            InputSeq::Key(b'c', true) => {
+                if self.yank_selection() {
+                    self.buffer_mut().selection = None;
+                    self.buffer_mut().column_selection = None;
+                } else {
+                    self.message = StatusMessage::new("Nothing selected");
+                }
+            }
+            // Like Emacs' CUA mode, C-x cuts when there is a selection and is a prefix otherwise
+            InputSeq::Key(b'x', true) => {
+                if self.buffer().settings.read_only && self.buffer().selection.is_some() {
+                    self.message = StatusMessage::new("File is read-only");
+                } else if self.yank_selection() {
+                    self.delete_selection();
+                } else {
+                    self.start_prefix(seq, "C-x-", count);
+                }
+            }
+            InputSeq::Key(b'v', true) => self.paste()?,
+            InputSeq::Paste(ref data) => self.bracketed_paste(data),
+            InputSeq::MouseClick(row, col) if col <= self.sidebar_width() => {
+                if row >= 1 && row <= self.screen_rows {
+                    self.browser.click(row - 1);
+                    self.sidebar_focused = true;
+                }
+            }
+            InputSeq::MouseClick(row, col) => {
+                self.sidebar_focused = false;
+                if row >= 1 && row <= self.screen_rows {
+                    let x = col.saturating_sub(1).saturating_sub(self.text_left());
+                    let cols = self.text_cols();
+                    self.buffer_mut().click(row - 1, x, cols);
+                }
+            }
+            InputSeq::MouseRelease(..) => {}
+            InputSeq::ScrollUp | InputSeq::ScrollDown => {
+                let (lines, rows, cols) = (self.settings.mouse_scroll_lines, self.screen_rows, self.text_cols());
+                self.buffer_mut().scroll(seq == InputSeq::ScrollUp, lines, rows, cols);
+            }
+            InputSeq::AltUp => self.buffer_mut().move_line_up(),
+            InputSeq::AltDown => self.buffer_mut().move_line_down(),
+            InputSeq::AltLeft => self.buffer_mut().move_cursor(CursorDir::WordLeft),
+            InputSeq::AltRight => self.buffer_mut().move_cursor(CursorDir::WordRight),
+            InputSeq::CtrlLeft => self.buffer_mut().move_cursor(CursorDir::TokenLeft),
+            InputSeq::CtrlRight => self.buffer_mut().move_cursor(CursorDir::TokenRight),
+            InputSeq::PageUpKey => {
+                let rows = self.screen_rows;
+                let buffer = self.buffer_mut();
+                buffer.cy = buffer.rowoff;
+                for _ in 0..rows {
+                    buffer.move_cursor(CursorDir::Up);
+                }
+            }
+            InputSeq::PageDownKey => {
+                let rows = self.screen_rows;
+                let buffer = self.buffer_mut();
+                buffer.cy = cmp::min(buffer.rowoff + rows - 1, buffer.rows.len());
+                for _ in 0..rows {
+                    buffer.move_cursor(CursorDir::Down)
+                }
+            }
+            InputSeq::Key(b'a', true) | InputSeq::HomeKey => self.buffer_mut().smart_home(),
+            InputSeq::Key(b'e', true) | InputSeq::EndKey => self.buffer_mut().line_end(),
+            InputSeq::DeleteKey if columns => {
+                self.buffer_mut().delete_columns(true);
+            }
+            InputSeq::Key(b'h', true) | InputSeq::Key(0x08, false) | InputSeq::Key(0x7f, false) if columns => {
+                self.buffer_mut().delete_columns(false);
+            }
+            InputSeq::DeleteKey | InputSeq::AltKey(b'd')
+            | InputSeq::Key(b'h', true) | InputSeq::Key(0x08, false) | InputSeq::Key(0x7f, false)
+                if self.delete_selection() => {}
+            InputSeq::DeleteKey | InputSeq::AltKey(b'd') => self.buffer_mut().delete_char_forward(),
+            InputSeq::Key(b'd', true) => self.buffer_mut().duplicate_line(),
+            InputSeq::Key(b'j', true) => self.buffer_mut().join_lines(),
+            // Terminals send 0x1f for Ctrl-/
+            InputSeq::Key(0x7f, true) | InputSeq::AltKey(b'/') => self.buffer_mut().toggle_comment(),
+            InputSeq::Key(b'k', true) => {
+                let killed = self.buffer_mut().kill_to_end();
+                self.kill(killed, last_action, false);
+            }
+            InputSeq::CtrlAltKey(b'u') => {
+                let killed = self.buffer_mut().kill_to_start();
+                self.kill(killed, last_action, true);
+            }
+            InputSeq::AltKey(b'y') => self.yank(last_action),
+            InputSeq::AltKey(b't') => self.buffer_mut().transpose_chars(),
+            InputSeq::Key(b'q', true) => {
+                if !self.buffers.iter().any(|b| b.is_dirty()) || self.quitting {
+                    return Ok(AfterKeyPress::Quit);
                 } else {
                     self.quitting = true;
                     self.message = StatusMessage::new(
@@ -695,18 +1917,82 @@ impl<I: Iterator<Item = io::Result<InputSeq>>> Editor<I> {
                     return Ok(AfterKeyPress::Continue);
                 }
             }
-            InputSeq::Key(b'\r', false) | InputSeq::Key(b'm', true) => self.insert_line(),
-            InputSeq::Key(b'h', true) | InputSeq::Key(0x08, false) | InputSeq::Key(0x7f, false) => {
-                self.delete_char();
+            InputSeq::Key(b'\r', false) | InputSeq::Key(b'm', true) => {
+                self.note_insert('\n', last_action);
+                self.buffer_mut().insert_line();
             }
-            InputSeq::Key(b'l', true) | InputSeq::Key(0x1b, false) => {
+            InputSeq::Key(b'h', true) | InputSeq::Key(0x08, false) | InputSeq::Key(0x7f, false) => {
+                self.buffer_mut().delete_char();
             }
+            InputSeq::Key(b'l', true) => {}
+            InputSeq::Key(0x1b, false) => self.buffer_mut().selection = None,
             InputSeq::Key(b's', true) => self.save()?,
-            InputSeq::Key(b, false) => self.insert_char(b as char),
-            InputSeq::Key(..) => { }
-            _ => unreachable!(),
+            InputSeq::Key(b'o', true) => self.open_prompt()?,
+            InputSeq::AltKey(b's') => self.save_as(false)?,
+            InputSeq::AltKey(b'S') => self.save_as(true)?,
+            InputSeq::Key(b'n', true) => self.show_line_numbers = !self.show_line_numbers,
+            InputSeq::Key(b'z', true) => {
+                if !self.buffer_mut().undo() {
+                    self.message = StatusMessage::new("Nothing to undo");
+                }
+            }
+            InputSeq::Key(b'y', true) => {
+                if !self.buffer_mut().redo() {
+                    self.message = StatusMessage::new("Nothing to redo");
+                }
+            }
+            InputSeq::FnKey(1) => self.show_help(),
+            InputSeq::FnKey(3) => self.search_again(SearchDir::Forward),
+            InputSeq::ShiftFnKey(3) => self.search_again(SearchDir::Backward),
+            InputSeq::CtrlFnKey(2) => self.toggle_bookmark(),
+            InputSeq::FnKey(2) => self.jump_to_bookmark(true),
+            InputSeq::ShiftFnKey(2) => self.jump_to_bookmark(false),
+            InputSeq::Key(b'f', true) => self.search(SearchDir::Forward)?,
+            InputSeq::Key(b'r', true) => self.search(SearchDir::Backward)?,
+            InputSeq::AltKey(b'r') => self.replace()?,
+            InputSeq::AltKey(b':') => self.command_line()?,
+            InputSeq::CtrlAltKey(b'f') => self.format(),
+            InputSeq::CtrlAltKey(b'i') => self.show_stats = !self.show_stats,
+            InputSeq::Key(b'g', true) => self.start_prefix(seq, "C-g-", count),
+            InputSeq::Key(b't', true) => self.new_buffer(),
+            InputSeq::Key(b'w', true) => {
+                // The second Ctrl-W has to follow within the 5 seconds the warning is shown
+                let confirmed = self.closing && self.message.timestamp.elapsed().is_ok_and(|d| d.as_secs() < 5);
+                if self.buffer().is_dirty() && !confirmed {
+                    self.closing = true;
+                    self.message = StatusMessage::new("Unsaved changes! Press Ctrl-W again to force close");
+                    return Ok(AfterKeyPress::Continue);
+                }
+                self.close_buffer();
+            }
+            InputSeq::CtrlTab | InputSeq::AltKey(b'n') => self.switch_buffer(true),
+            InputSeq::CtrlShiftTab | InputSeq::AltKey(b'p') => self.switch_buffer(false),
+            InputSeq::Key(b'i', true) => self.buffer_mut().insert_tab(),
+            InputSeq::Key(b' ', true) => self.start_completion(),
+            InputSeq::Key(b'b', true) => self.toggle_sidebar(),
+            InputSeq::BackTab => self.buffer_mut().unindent(),
+            InputSeq::Key(b, false) if columns => {
+                self.note_insert(b as char, last_action);
+                self.buffer_mut().insert_in_columns(b as char);
+            }
+            InputSeq::Key(b, false) => {
+                self.note_insert(b as char, last_action);
+                self.buffer_mut().insert_char(b as char);
+            }
+            InputSeq::Char(c) if columns => {
+                self.note_insert(c, last_action);
+                self.buffer_mut().insert_in_columns(c);
+            }
+            InputSeq::Char(c) => {
+                self.note_insert(c, last_action);
+                self.buffer_mut().insert_char(c);
+            }
+            // Keys with nothing bound, and replies to requests that are no longer waited for
+            InputSeq::Key(..) | InputSeq::AltKey(_) | InputSeq::CtrlAltKey(_) | InputSeq::FnKey(_) | InputSeq::ShiftFnKey(_)
+            | InputSeq::CtrlFnKey(_) | InputSeq::Clipboard(_) | InputSeq::Cursor(..) | InputSeq::Unidentified => {}
         }
         self.quitting = false;
+        self.closing = false;
         Ok(AfterKeyPress::Continue)
     }
 
@@ -714,10 +2000,16 @@ impl<I: Iterator<Item = io::Result<InputSeq>>> Editor<I> {
         if self.screen_cols > 0 && self.screen_rows > 0 {
             return Ok(());
         }
+        self.query_screen_size()
+    }
 
-        let mut stdout = io::stdout();
-        stdout.write(b"\x1b[9999C\x1b[9999B\x1b[6n")?;
-        stdout.flush()?;
+    fn query_screen_size(&mut self) -> io::Result<()> {
+        // The tests queue the reply in their input, without a terminal to ask
+        if !cfg!(test) {
+            let mut stdout = io::stdout();
+            stdout.write_all(b"\x1b[9999C\x1b[9999B\x1b[6n")?;
+            stdout.flush()?;
+        }
 
         for seq in &mut self.input {
             if let InputSeq::Cursor(r, c) = seq? {
@@ -729,34 +2021,1153 @@ impl<I: Iterator<Item = io::Result<InputSeq>>> Editor<I> {
         Ok(())
     }
 
+    fn handle_resize(&mut self) -> io::Result<()> {
+        if !self.resized.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.query_screen_size()?;
+        // The terminal may have rewrapped or dropped what was on the screen
+        self.renderer.invalidate();
+        self.setup_scroll();
+        self.refresh_screen()
+    }
+
+    // Every key the user types goes through here, so macros see keys typed into prompts too
+    fn next_seq(&mut self) -> Option<io::Result<InputSeq>> {
+        if self.terminated.load(Ordering::Relaxed) {
+            return None;
+        }
+        if let Some(seq) = self.replay.pop_front() {
+            return Some(Ok(seq));
+        }
+        let seq = self.input.next();
+        if let (Some(buf), Some(Ok(seq))) = (self.macro_buffer.as_mut(), seq.as_ref()) {
+            match seq {
+                InputSeq::Unidentified | InputSeq::Cursor(..) | InputSeq::Clipboard(_) => {}
+                seq => buf.push(seq.clone()),
+            }
+        }
+        seq
+    }
+
+    // The key after the prefix is handled by process_keypress, with the count typed before the prefix
+    fn start_prefix(&mut self, seq: InputSeq, message: &str, count: Option<usize>) {
+        self.pending_seq = Some(seq);
+        self.pending_count = count;
+        self.message = StatusMessage::new(message);
+    }
+
+    fn prefix_command(&mut self, prefix: InputSeq, seq: InputSeq) -> io::Result<AfterKeyPress> {
+        let count = self.pending_count.take();
+        self.message = StatusMessage::new("");
+        let command = match prefix {
+            InputSeq::Key(b'g', true) => self.ctrl_g_command(seq)?,
+            _ => self.ctrl_x_command(seq, count)?,
+        };
+        self.pending_register = None;
+        match command {
+            Some(seq) => self.run_command(seq, count),
+            None => Ok(AfterKeyPress::Continue),
+        }
+    }
+
+    // C-g C-g goes to the top of the file and C-g C-e to the end, like Emacs' M-< and M->
+    fn ctrl_g_command(&mut self, seq: InputSeq) -> io::Result<Option<InputSeq>> {
+        match seq {
+            InputSeq::Key(b'g', true) => {
+                let buffer = self.buffer_mut();
+                buffer.cy = 0;
+                buffer.cx = 0;
+            }
+            InputSeq::Key(b'e', true) => {
+                let _ = self.buffer_mut().load_all();
+                let buffer = self.buffer_mut();
+                buffer.cy = buffer.rows.len().saturating_sub(1);
+                buffer.line_end();
+            }
+            // The first digit of a line number
+            InputSeq::Key(b'0'..=b'9', false) => {
+                self.replay.push_front(seq);
+                self.go_to_line()?;
+            }
+            InputSeq::Key(0x1b, false) => {}
+            _ => self.message = StatusMessage::new("Unknown command"),
+        }
+        Ok(None)
+    }
+
+    // Returns the key of the command to run when the ctrl-x key is another name for one, like
+    // Emacs' C-x C-s for Ctrl-S
+    fn ctrl_x_command(&mut self, seq: InputSeq, count: Option<usize>) -> io::Result<Option<InputSeq>> {
+        let byte = match seq {
+            InputSeq::Key(b, true) => Some(b & 0x1f),
+            InputSeq::Key(b, false) => Some(b),
+            _ => None,
+        };
+        if let Some(command) = byte.and_then(|b| self.ctrl_x_keys.get(&b)) {
+            return Ok(Some(command.clone()));
+        }
+        match seq {
+            InputSeq::Key(b'b', false) => self.switch_to_named_buffer()?,
+            InputSeq::Key(b'(', false) => {
+                if self.macro_buffer.is_some() {
+                    self.message = StatusMessage::new("Already defining a macro");
+                } else {
+                    self.macro_buffer = Some(vec![]);
+                    self.message = StatusMessage::new("Defining macro...");
+                }
+            }
+            InputSeq::Key(b')', false) => match self.macro_buffer.take() {
+                Some(mut keys) => {
+                    // Drop the C-x ) which just ended the recording
+                    keys.truncate(keys.len().saturating_sub(2));
+                    self.last_macro = keys;
+                    self.message = StatusMessage::new("Macro defined");
+                }
+                None => self.message = StatusMessage::new("Not defining a macro"),
+            },
+            InputSeq::Key(b'e', false) | InputSeq::Key(b'E', false) => {
+                if self.last_macro.is_empty() {
+                    self.message = StatusMessage::new("No macro defined");
+                } else {
+                    // The keys are read back by the run loop and any prompts they open
+                    for _ in 0..count.unwrap_or(1) {
+                        self.replay.extend(self.last_macro.iter().cloned());
+                    }
+                }
+            }
+            // Like Emacs' C-x RET prefix for coding systems
+            InputSeq::Key(b'\r', false) | InputSeq::Key(b'm', true) => {
+                if self.buffer().settings.read_only {
+                    self.message = StatusMessage::new("File is read-only");
+                } else {
+                    self.buffer_mut().toggle_line_ending();
+                    let ending = if self.buffer().settings.crlf { "CRLF" } else { "LF" };
+                    self.message = StatusMessage::new(format!("Line endings will be {} when saved", ending));
+                }
+            }
+            // Like Emacs' C-x C-z, since C-z is undo
+            InputSeq::Key(b'z', true) => self.suspend()?,
+            // Emacs' C-x 2 and C-x 1, though the editor shows one buffer at a time
+            InputSeq::Key(b'1' | b'2', false) => self.message = StatusMessage::new("Split windows are not supported"),
+            InputSeq::Key(0x1b, false) => {}
+            _ => self.message = StatusMessage::new("Unknown command"),
+        }
+        Ok(None)
+    }
+
+    // Asks for the next key without handling it as a command
+    fn read_key(&mut self) -> io::Result<Option<InputSeq>> {
+        while let Some(seq) = self.next_seq() {
+            match seq? {
+                InputSeq::Unidentified => continue,
+                seq => return Ok(Some(seq)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn check_disk_change(&mut self) -> io::Result<()> {
+        if !self.buffer().changed_on_disk() {
+            return Ok(());
+        }
+        self.buffer_mut().ignore_disk_change();
+
+        self.message = StatusMessage::new("File changed on disk. Ctrl-R to reload, any key to ignore.");
+        self.refresh_screen()?;
+        let mut reload = self.read_key()? == Some(InputSeq::Key(b'r', true));
+        if reload && self.buffer().is_dirty() {
+            self.message = StatusMessage::new("Unsaved changes will be lost! Ctrl-R again to reload.");
+            self.refresh_screen()?;
+            reload = self.read_key()? == Some(InputSeq::Key(b'r', true));
+        }
+
+        if reload {
+            self.reload_file();
+        } else {
+            self.message = StatusMessage::new("");
+        }
+        self.setup_scroll();
+        self.refresh_screen()
+    }
+
+    // Starts language servers for open files, sends them changed text and takes in their
+    // diagnostics. True when there is something new to draw
+    fn sync_lsp(&mut self) -> bool {
+        let mut changed = false;
+        for i in 0..self.buffers.len() {
+            let buffer = &self.buffers[i];
+            let path = match buffer.file {
+                Some(ref file) if buffer.settings.lsp => file.path.clone(),
+                _ => continue,
+            };
+            let (command, language) = match lsp::server_for(&path) {
+                Some(server) if !self.lsp_unavailable.contains(&server.0) => server,
+                _ => continue,
+            };
+            let client = match self.lsp.iter().position(|client| client.command == command) {
+                Some(client) => client,
+                None => match LspClient::start(command) {
+                    Ok(client) => {
+                        self.lsp.push(client);
+                        self.lsp.len() - 1
+                    }
+                    Err(err) => {
+                        self.lsp_unavailable.push(command);
+                        self.message = StatusMessage::new(format!("Can't start {}: {}", command, err));
+                        changed = true;
+                        continue;
+                    }
+                },
+            };
+            // The server has to see the whole file
+            let _ = self.buffers[i].load_all();
+            let buffer = &self.buffers[i];
+            if let Err(err) = self.lsp[client].sync(&path, language, buffer.changes, || buffer.text()) {
+                self.lsp.remove(client);
+                self.lsp_unavailable.push(command);
+                self.message = StatusMessage::new(format!("{} stopped: {}", command, err));
+                changed = true;
+            }
+        }
+        for client in &mut self.lsp {
+            changed |= client.poll();
+        }
+        changed
+    }
+
+    // Shows the diagnostic of the cursor's line when the cursor moves onto it
+    fn report_diagnostic(&mut self) {
+        let line = (self.current, self.buffer().cy);
+        if self.diagnostic_line == Some(line) {
+            return;
+        }
+        self.diagnostic_line = Some(line);
+        if let Some(message) = self.cursor_diagnostic().map(|d| d.message.clone()) {
+            self.message = StatusMessage::new(message);
+        }
+    }
+
+    fn reload_file(&mut self) {
+        let msg = match self.buffer_mut().reload() {
+            Ok(()) => format!("Reloaded {}", self.buffer().name()),
+            Err(err) => format!("Can't reload {}: {}", self.buffer().name(), err),
+        };
+        self.message = StatusMessage::new(msg);
+    }
+
+    // Saves the changed files once no text was typed for the auto-save interval. True when
+    // there is something new to draw
+    fn auto_save(&mut self) -> bool {
+        let interval = match self.settings.auto_save_interval {
+            Some(interval) if self.auto_save_pending => interval,
+            _ => return false,
+        };
+        if SystemTime::now().duration_since(self.last_edit_time).map_or(true, |idle| idle < interval) {
+            return false;
+        }
+        self.auto_save_pending = false;
+        let mut saved = 0;
+        for buffer in &mut self.buffers {
+            let path = match buffer.file {
+                Some(ref file) if buffer.is_dirty() && !buffer.settings.read_only => file.path.clone(),
+                _ => continue,
+            };
+            let written = match self.auto_save_path {
+                // The buffer stays dirty, since its own file wasn't saved
+                Some(ref dir) => std::fs::create_dir_all(dir)
+                    .and_then(|_| buffer.write_to(&dir.join(path.file_name().unwrap_or_default()))),
+                None => {
+                    // Trimming would take away spaces which were just typed
+                    let trim = mem::replace(&mut buffer.settings.trim_trailing_whitespace, false);
+                    let written = buffer.write_file();
+                    buffer.settings.trim_trailing_whitespace = trim;
+                    written
+                }
+            };
+            match written {
+                Ok(_) => saved += 1,
+                Err(err) => {
+                    self.message = StatusMessage::new(format!("Can't auto-save {}: {}", buffer.name(), err));
+                    return true;
+                }
+            }
+        }
+        if saved > 0 {
+            self.message = StatusMessage::new("Auto-saved");
+        }
+        saved > 0
+    }
 
     fn run(&mut self) -> io::Result<()> {
+        signal_hook::flag::register(signal_hook::consts::SIGWINCH, Arc::clone(&self.resized))?;
+        signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&self.terminated))?;
         self.ensure_screen_size()?;
 
         self.setup_scroll();
         self.refresh_screen()?;
 
-        while let Some(seq) = self.input.next() {
+        while let Some(seq) = self.next_seq() {
+            self.handle_resize()?;
+            self.check_disk_change()?;
+            if self.sync_lsp() {
+                self.report_diagnostic();
+                self.refresh_screen()?;
+            }
             let seq = seq?;
+            // Reading timed out, so nothing is being typed
             if seq == InputSeq::Unidentified {
+                if self.auto_save() {
+                    self.refresh_screen()?;
+                }
                 continue;
             }
+            let edit = modifies_buffer(&seq);
             if self.process_keypress(seq)? == AfterKeyPress::Quit {
                 break;
             }
+            if edit {
+                self.last_edit_time = SystemTime::now();
+                self.auto_save_pending = true;
+            }
             self.setup_scroll();
+            self.report_diagnostic();
             self.refresh_screen()?;
         }
         self.clear_screen()
     }
 }
 
+// The editor's own cleanup only runs while unwinding, after the panic message was printed
+// to a terminal in raw mode. So the terminal at `fd` is put back first, and `out` is the
+// screen it draws
+fn install_panic_hook<W: Write + Send + 'static>(fd: RawFd, orig: termios::Termios, out: W) {
+    let out = std::sync::Mutex::new(out);
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Ok(mut out) = out.lock() {
+            let _ = out.write_all(b"\x1b[?2004l\x1b[?1006l\x1b[?1000l\x1b[?25h\x1b[2J\x1b[H");
+            let _ = out.flush();
+        }
+        let _ = termios::tcsetattr(fd, termios::TCSAFLUSH, &orig);
+        default_hook(info);
+    }));
+}
+
+// ~/.rustitorrc, then .rustitor in the current directory on top of it, then the TOML config.
+// `config_path` replaces the usual place of the TOML config
+fn load_config(config_path: Option<&Path>) -> (Config, Vec<String>) {
+    let mut paths = vec![];
+    if let Some(home) = std::env::var_os("HOME") {
+        paths.push(Path::new(&home).join(".rustitorrc"));
+    }
+    paths.push(Path::new(".rustitor").to_path_buf());
+
+    let mut settings = Settings::default();
+    let mut warnings = vec![];
+    for path in paths {
+        match Settings::load_from_file(&path) {
+            Ok(mut loaded) => {
+                settings.merge(loaded.settings, &loaded.keys);
+                warnings.append(&mut loaded.warnings);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => warnings.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+
+    let config_dir = match (std::env::var_os("XDG_CONFIG_HOME"), std::env::var_os("HOME")) {
+        (Some(dir), _) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        (_, Some(home)) => Some(Path::new(&home).join(".config")),
+        _ => None,
+    };
+    let default_path = config_dir.map(|dir| dir.join("rustitor").join("config.toml"));
+    let mut config = Config { settings, keys: vec![], keybindings: vec![], formatters: vec![], auto_save_path: None };
+    if let Some(path) = config_path.map(Path::to_path_buf).or(default_path) {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => match Config::from_toml_str(&text) {
+                Ok(loaded) => {
+                    config.settings.merge(loaded.settings, &loaded.keys);
+                    config.keybindings = loaded.keybindings;
+                    config.formatters = loaded.formatters;
+                    config.auto_save_path = loaded.auto_save_path;
+                }
+                Err(errors) => {
+                    let lines: Vec<String> = errors.iter().map(|e| e.line.to_string()).collect();
+                    warnings.push(format!(
+                        "{}: errors on lines {} ({}), config ignored",
+                        path.display(),
+                        lines.join(", "),
+                        errors[0].message,
+                    ));
+                }
+            },
+            // A config asked for on the command line has to be there
+            Err(err) if err.kind() == io::ErrorKind::NotFound && config_path.is_none() => {}
+            Err(err) => warnings.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    (config, warnings)
+}
+
+// Edits the file with the commands of the script, leaving the terminal in cooked mode
+fn run_headless(args: &Args, settings: Settings) -> Result<(), String> {
+    let (file, position) = &args.files[0];
+    let script = args.script.as_deref().unwrap_or(Path::new(""));
+    let text = std::fs::read_to_string(script).map_err(|err| format!("{}: {}", script.display(), err))?;
+    let commands = ScriptCommand::parse_script(&text).map_err(|msg| format!("{}: {}", script.display(), msg))?;
+    let mut editor = Editor::new(Some((80, 24)), std::iter::empty(), settings);
+    editor.command_line = args.clone();
+    editor.open_file(file).map_err(|err| format!("{}: {}", file, err))?;
+    if let Some((line, col)) = *position {
+        editor.jump_to(line, col);
+    }
+    editor.run_script(commands).map_err(|err| format!("{}: {}", file, err))
+}
+
+// Prints what the highlighter finds wrong in each file. Returns whether every file was fine
+fn check_syntax(args: &Args, settings: Settings) -> bool {
+    let mut ok = true;
+    for (file, _) in &args.files {
+        let buffer = match EditorBuffer::open(file, settings).and_then(|mut buffer| buffer.load_all().map(|_| buffer)) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                eprintln!("rustitor: {}: {}", file, err);
+                ok = false;
+                continue;
+            }
+        };
+        for err in highlight::for_path(file).check(&buffer.rows) {
+            eprintln!("{}:{}: {}", file, err.line + 1, err.message);
+            ok = false;
+        }
+    }
+    ok
+}
+
+// Edits stdin with the commands of the script and writes the result to stdout, never touching
+// the terminal
+fn run_pipe(args: &Args, settings: Settings) -> Result<(), String> {
+    let script = args.script.as_deref().unwrap_or(Path::new(""));
+    let text = std::fs::read_to_string(script).map_err(|err| format!("{}: {}", script.display(), err))?;
+    let (input, encoding) = read_stdin().map_err(|err| format!("stdin: {}", err))?;
+    let mut buffer = EditorBuffer::from_text(&input, settings);
+    buffer.encoding = encoding;
+    // Ends the same way the input did, whatever the setting says
+    buffer.settings.insert_final_newline = input.ends_with('\n');
+    let mut editor = Editor::new(Some((80, 24)), std::iter::empty(), settings);
+    editor.add_buffer(buffer);
+    editor.run_pipeline(&text).map_err(|err| format!("{}: {}", script.display(), err))?;
+    editor.write_to(io::stdout().lock()).map_err(|err| format!("stdout: {}", err))
+}
+
+fn read_stdin() -> io::Result<(String, Encoding)> {
+    let mut bytes = vec![];
+    io::stdin().read_to_end(&mut bytes)?;
+    let encoding = Encoding::detect(&bytes);
+    let text = encoding.decode(bytes).unwrap_or_else(|bytes| bytes.iter().map(|&b| b as char).collect());
+    Ok((text, encoding))
+}
+
+// Text piped in, as an unnamed buffer which asks for a file name when saved
+fn read_stdin_buffer(settings: Settings) -> io::Result<EditorBuffer> {
+    let (text, encoding) = read_stdin()?;
+    let mut buffer = EditorBuffer::from_text(&text, settings);
+    buffer.label = Some(STDIN_BUFFER.to_string());
+    buffer.encoding = encoding;
+    // Quitting would lose it otherwise
+    buffer.mark_modified();
+    Ok(buffer)
+}
 
 fn main() -> io::Result<()> {
-    let input = StdinRawMode::new()?.input_keys();
-    let mut editor = Editor::new(term_size::dimensions_stdout(), input);
-    if let Some(arg) = std::env::args().skip(1).next() {
-        editor.open_file(arg)?;
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("rustitor: {}", msg);
+            std::process::exit(1);
+        }
+    };
+    if args.help {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+    if args.version {
+        println!("rustitor {}", VERSION);
+        return Ok(());
+    }
+
+    let (config, mut warnings) = load_config(args.config.as_deref());
+    let mut settings = config.settings;
+    if let Ok(name) = std::env::var("RUSTITOR_CLIPBOARD") {
+        match ClipboardProvider::from_name(&name) {
+            Some(provider) => settings.clipboard_provider = provider,
+            None => warnings.push(format!("Unknown clipboard provider: {}", name)),
+        }
+    }
+    args.apply(&mut settings);
+
+    if args.benchmark {
+        let file = &args.files[0].0;
+        match benchmark::run(file, &args, settings) {
+            Ok(results) => results.print(args.benchmark_csv),
+            Err(err) => {
+                eprintln!("rustitor: {}: {}", file, err);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if args.check_syntax {
+        std::process::exit(if check_syntax(&args, settings) { 0 } else { 1 });
+    }
+    if args.pipe {
+        if let Err(msg) = run_pipe(&args, settings) {
+            eprintln!("rustitor: {}", msg);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if args.headless {
+        if let Err(msg) = run_headless(&args, settings) {
+            eprintln!("rustitor: {}", msg);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let piped = if args.files.is_empty() && !io::stdin().is_terminal() {
+        Some(read_stdin_buffer(settings)?)
+    } else {
+        None
+    };
+    let raw_mode = match piped {
+        Some(_) => StdinRawMode::from_tty(settings.mouse_support)?,
+        None => StdinRawMode::new(settings.mouse_support)?,
+    };
+    install_panic_hook(io::stdin().as_raw_fd(), raw_mode.orig, io::stdout());
+    let input = raw_mode.input_keys();
+    if args.diff {
+        let mut view = DiffView::new(term_size::dimensions_stdout(), input, &args, settings);
+        view.open(&args.files[0].0, &args.files[1].0)?;
+        return view.run();
+    }
+    let mut editor = Editor::new(term_size::dimensions_stdout(), input, settings);
+    editor.command_line = args.clone();
+    editor.debug = std::env::var_os("RUSTITOR_DEBUG").is_some_and(|v| !v.is_empty());
+    if std::env::var_os("TERM").is_some_and(|term| term == "dumb") {
+        editor.renderer = Box::new(DumbRenderer);
+    }
+    for (file_type, command) in config.formatters {
+        editor.formatters.retain(|(name, _)| *name != file_type);
+        editor.formatters.push((file_type, command));
+    }
+    editor.bindings = KeyBindingTable::new(&config.keybindings);
+    editor.history_path = history::default_path();
+    editor.lock_files = true;
+    editor.auto_save_path = config.auto_save_path;
+    editor.keymap = config.keybindings.into_iter().map(|(from, to)| (key_seq(from), key_seq(to))).collect();
+    if let Some(ref path) = args.session {
+        if let Err(err) = Session::restore(&mut editor, path) {
+            warnings.push(format!("Can't restore the session from {}: {}", path.display(), err));
+        }
+    }
+    if let Some(buffer) = piped {
+        editor.add_buffer(buffer);
+    }
+    for (file, position) in args.files {
+        editor.open_file(file)?;
+        if let Some((line, col)) = position {
+            editor.jump_to(line, col);
+        }
+    }
+    match warnings.len() {
+        0 => {}
+        1 => editor.message = StatusMessage::new(warnings.remove(0)),
+        n => editor.message = StatusMessage::new(format!("{} (and {} more warnings)", warnings[0], n - 1)),
+    }
+    let result = editor.run();
+    editor.remember_positions(None);
+    let saved = args.save_session.map(|path| (Session::save(&editor, &path), path));
+    // Reported once the terminal is out of raw mode
+    drop(editor);
+    if let Some((Err(err), path)) = saved {
+        eprintln!("rustitor: can't save the session to {}: {}", path.display(), err);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestEditor = Editor<std::vec::IntoIter<io::Result<InputSeq>>>;
+
+    fn press(editor: &mut TestEditor, keys: &[InputSeq]) {
+        for key in keys {
+            assert!(editor.process_keypress(key.clone()).unwrap() == AfterKeyPress::Continue);
+        }
+    }
+
+    fn rows(editor: &TestEditor) -> Vec<&str> {
+        editor.buffer().rows.iter().map(|row| row.buf.as_str()).collect()
+    }
+
+    fn cursor(editor: &TestEditor) -> (usize, usize) {
+        (editor.buffer().cx, editor.buffer().cy)
+    }
+
+    // Decodes the bytes as the terminal would send them and runs the keys, with prompts reading
+    // the keys after theirs. Read timeouts are skipped, as the run loop does
+    fn type_bytes(editor: &mut TestEditor, bytes: &[u8]) {
+        editor.input = InputSequences::new(bytes).take(bytes.len() + 1).collect::<Vec<_>>().into_iter();
+        while let Some(seq) = editor.next_seq() {
+            match seq.unwrap() {
+                InputSeq::Unidentified => {}
+                seq => press(editor, &[seq]),
+            }
+        }
+    }
+
+    #[test]
+    fn unbound_function_keys_are_ignored() {
+        let mut e = Editor::new_from_string("abc\n", (80, 24));
+        type_bytes(&mut e, b"\x1b[1;3R\x1b[1;6R\x1b[1;4Rx\x1b[1;3Sy");
+        assert_eq!(rows(&e), ["xyabc"]);
+    }
+
+    #[test]
+    fn typed_utf8_goes_into_rows_and_prompts() {
+        let mut e = Editor::new_from_string("ab\nx\u{4f60}y\n", (80, 24));
+        type_bytes(&mut e, "é\u{4f60}\u{1f600}".as_bytes());
+        assert_eq!(rows(&e), ["é\u{4f60}\u{1f600}ab", "x\u{4f60}y"]);
+        assert_eq!(cursor(&e), (3, 0));
+        // Ctrl-F, the query and Enter
+        type_bytes(&mut e, "\x06\u{4f60}y\r".as_bytes());
+        assert_eq!(cursor(&e), (1, 1));
+    }
+
+    #[test]
+    fn typed_combining_mark_joins_the_grapheme_before() {
+        let mut e = Editor::new_from_string("ab\n", (80, 24));
+        press(&mut e, &[InputSeq::RightKey]);
+        type_bytes(&mut e, "e\u{301}".as_bytes());
+        assert_eq!(rows(&e), ["ae\u{301}b"]);
+        assert_eq!(cursor(&e), (2, 0));
+        // Undo takes away only the mark, not the whole grapheme
+        press(&mut e, &[InputSeq::Key(b'z', true)]);
+        assert_eq!(rows(&e), ["aeb"]);
+        assert_eq!(cursor(&e), (2, 0));
+        press(&mut e, &[InputSeq::Key(b'y', true)]);
+        assert_eq!(rows(&e), ["ae\u{301}b"]);
+        assert_eq!(cursor(&e), (2, 0));
+    }
+
+    #[test]
+    fn insert_char_at_cursor() {
+        let mut e = Editor::new_from_string("line one\nline two\n", (80, 24));
+        press(&mut e, &[InputSeq::Key(b'X', false)]);
+        assert_eq!(rows(&e), ["Xline one", "line two"]);
+        press(&mut e, &[InputSeq::DownKey, InputSeq::Key(b'Y', false)]);
+        assert_eq!(rows(&e), ["Xline one", "lYine two"]);
+        assert_eq!(cursor(&e), (2, 1));
+        assert!(e.buffer().is_dirty());
+    }
+
+    #[test]
+    fn backspace_at_line_start_merges_with_previous_line() {
+        let mut e = Editor::new_from_string("abc\ndef\n", (80, 24));
+        press(&mut e, &[InputSeq::DownKey, InputSeq::Key(0x7f, false)]);
+        assert_eq!(rows(&e), ["abcdef"]);
+        assert_eq!(cursor(&e), (3, 0));
+        assert!(e.buffer().is_dirty());
+    }
+
+    #[test]
+    fn backspace_at_start_of_file_does_nothing() {
+        let mut e = Editor::new_from_string("abc\ndef\n", (80, 24));
+        press(&mut e, &[InputSeq::Key(0x7f, false)]);
+        assert_eq!(rows(&e), ["abc", "def"]);
+        assert_eq!(cursor(&e), (0, 0));
+        assert!(!e.buffer().is_dirty());
+    }
+
+    #[test]
+    fn enter_at_line_end_adds_empty_row() {
+        let mut e = Editor::new_from_string("abc\ndef\n", (80, 24));
+        press(&mut e, &[InputSeq::EndKey, InputSeq::Key(b'\r', false)]);
+        assert_eq!(rows(&e), ["abc", "", "def"]);
+        assert_eq!(cursor(&e), (0, 1));
+        assert!(e.buffer().is_dirty());
+    }
+
+    #[test]
+    fn right_at_line_end_moves_to_next_line() {
+        let mut e = Editor::new_from_string("abc\ndef\n", (80, 24));
+        press(&mut e, &[InputSeq::EndKey, InputSeq::RightKey]);
+        assert_eq!(cursor(&e), (0, 1));
+        assert!(!e.buffer().is_dirty());
+    }
+
+    #[test]
+    fn left_at_line_start_moves_to_end_of_previous_line() {
+        let mut e = Editor::new_from_string("abc\ndef\n", (80, 24));
+        press(&mut e, &[InputSeq::DownKey, InputSeq::LeftKey]);
+        assert_eq!(cursor(&e), (3, 0));
+    }
+
+    #[test]
+    fn resize_is_picked_up_before_the_next_key() {
+        let text: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+        let mut e = Editor::new_from_string(&text, (80, 24));
+        // SIGWINCH came, and the terminal answers the size query with its bottom right corner
+        e.resized.store(true, Ordering::Relaxed);
+        e.input = vec![Ok(InputSeq::Cursor(12, 100))].into_iter();
+        e.handle_resize().unwrap();
+        assert_eq!((e.screen_cols, e.screen_rows), (100, 10));
+        assert!(!e.resized.load(Ordering::Relaxed));
+        // A page is now 10 rows
+        press(&mut e, &[InputSeq::PageDownKey]);
+        e.setup_scroll();
+        assert_eq!(cursor(&e), (0, 19));
+        assert!(e.buffer().rowoff > 9 && e.buffer().rowoff <= 19);
+
+        // Without the flag the size is not asked again
+        e.input = vec![Ok(InputSeq::Cursor(40, 100))].into_iter();
+        e.handle_resize().unwrap();
+        assert_eq!(e.screen_rows, 10);
+    }
+
+    #[test]
+    fn page_down_keeps_scroll_off_rows_around_the_cursor() {
+        let text: String = (1..=100).map(|i| format!("line {}\n", i)).collect();
+        let mut e = Editor::new_from_string(&text, (80, 24));
+        let (rows, scroll_off) = (e.screen_rows, e.buffer().settings.scroll_off);
+        assert_eq!(scroll_off, 5);
+        // Two pages stay clear of the end of the file, where there is nothing more to show below
+        for _ in 0..2 {
+            press(&mut e, &[InputSeq::PageDownKey]);
+            e.setup_scroll();
+            let (cy, rowoff) = (e.buffer().cy, e.buffer().rowoff);
+            assert!(cy - rowoff >= scroll_off && rowoff + rows - cy > scroll_off, "cy {} rowoff {}", cy, rowoff);
+        }
+        // Going back up a screen scrolls while keeping the rows above the cursor in view
+        for _ in 0..rows {
+            press(&mut e, &[InputSeq::UpKey]);
+            e.setup_scroll();
+        }
+        let (cy, rowoff) = (e.buffer().cy, e.buffer().rowoff);
+        assert!(rowoff > 0 && cy - rowoff == scroll_off, "cy {} rowoff {}", cy, rowoff);
+        // Except at the top of the file, where there is nothing above
+        press(&mut e, &[InputSeq::Key(b'g', true), InputSeq::Key(b'g', true)]);
+        e.setup_scroll();
+        assert_eq!((e.buffer().cy, e.buffer().rowoff), (0, 0));
+    }
+
+    #[test]
+    fn page_down_stays_within_short_file() {
+        let mut e = Editor::new_from_string("one\ntwo\nthree\n", (80, 24));
+        press(&mut e, &[InputSeq::PageDownKey, InputSeq::PageDownKey]);
+        // The row past the last line is as far as the cursor goes
+        assert_eq!(cursor(&e), (0, 3));
+        assert_eq!(e.buffer().rowoff, 0);
+        assert_eq!(rows(&e), ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn save_without_file_name_leaves_buffer_alone() {
+        let mut e = Editor::new_from_string("abc\n", (80, 24));
+        press(&mut e, &[InputSeq::Key(b'x', false)]);
+        // The Save as prompt gets no input
+        press(&mut e, &[InputSeq::Key(b's', true)]);
+        assert!(e.buffer().file.is_none());
+        assert_eq!(rows(&e), ["xabc"]);
+        assert!(e.buffer().is_dirty());
+    }
+
+    #[test]
+    fn multi_byte_char_moves_cursor_by_one_grapheme() {
+        let mut e = Editor::new_from_string("ab\n", (80, 24));
+        // Right arrow, then é as the terminal sends it
+        type_bytes(&mut e, b"\x1b[C\xc3\xa9");
+        assert_eq!(rows(&e), ["aéb"]);
+        assert_eq!(cursor(&e), (2, 0));
+        type_bytes(&mut e, "e\u{301}!\u{1f600}".as_bytes());
+        assert_eq!(rows(&e), ["aée\u{301}!\u{1f600}b"]);
+        assert_eq!(cursor(&e), (5, 0));
+        // Left arrows step over whole graphemes
+        type_bytes(&mut e, b"\x1b[D\x1b[D\x1b[D");
+        assert_eq!(cursor(&e), (2, 0));
+        type_bytes(&mut e, "\u{4f60}".as_bytes());
+        assert_eq!(rows(&e), ["aé\u{4f60}e\u{301}!\u{1f600}b"]);
+        assert_eq!(cursor(&e), (3, 0));
+        // Backspace, then Delete on the grapheme with the combining mark
+        type_bytes(&mut e, b"\x7f\x1b[3~");
+        assert_eq!(rows(&e), ["aé!\u{1f600}b"]);
+        assert_eq!(cursor(&e), (2, 0));
+    }
+
+    #[test]
+    fn bracket_match_spans_lines() {
+        let mut e = Editor::new_from_string("fn f() {\n    g(h(0));\n}\n", (80, 24));
+        press(&mut e, &[InputSeq::EndKey, InputSeq::LeftKey]);
+        e.setup_scroll();
+        assert_eq!(e.bracket_match, Some((2, 0)));
+        press(&mut e, &[InputSeq::DownKey, InputSeq::DownKey, InputSeq::HomeKey]);
+        e.setup_scroll();
+        assert_eq!(e.bracket_match, Some((0, 7)));
+        // Nested brackets of the same kind are counted on the way
+        press(&mut e, &[InputSeq::UpKey, InputSeq::EndKey, InputSeq::LeftKey, InputSeq::LeftKey]);
+        e.setup_scroll();
+        assert_eq!(e.bracket_match, Some((1, 5)));
+    }
+
+    #[test]
+    fn unbalanced_bracket_has_no_match() {
+        let mut e = Editor::new_from_string("(a(b)\n", (80, 24));
+        e.setup_scroll();
+        assert_eq!(e.bracket_match, None);
+        press(&mut e, &[InputSeq::RightKey, InputSeq::RightKey]);
+        e.setup_scroll();
+        assert_eq!(e.bracket_match, Some((0, 4)));
+    }
+
+    #[test]
+    fn opening_comment_highlights_rows_below() {
+        let mut e = Editor::new_from_string("a\nb\nc */ d\n", (80, 24));
+        e.buffer_mut().set_file("x.rs");
+        let comment = |e: &TestEditor, y: usize| e.buffer().rows[y].highlight[0] == highlight::Highlight::Comment;
+        assert!(!comment(&e, 1));
+        press(&mut e, &[InputSeq::Key(b'/', false), InputSeq::Key(b'*', false)]);
+        assert!(comment(&e, 1) && comment(&e, 2));
+        assert_eq!(e.buffer().rows[2].highlight[5], highlight::Highlight::Normal);
+        press(&mut e, &[InputSeq::Key(b'h', true)]);
+        assert!(!comment(&e, 1) && !comment(&e, 2));
+    }
+
+    #[test]
+    fn command_line_wins_over_editorconfig() {
+        let dir = std::env::temp_dir().join(format!("rustitor-editorconfig-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(".editorconfig"), "root = true\n[*]\nindent_style = space\nindent_size = 2\n").unwrap();
+        std::fs::write(dir.join("a.txt"), "a\n").unwrap();
+        let mut e = Editor::new_from_string("", (80, 24));
+        e.command_line = Args::parse_from(["--tab-stop", "4"].into_iter().map(String::from)).unwrap();
+        e.command_line.apply(&mut e.settings);
+        let opened = e.open_file(dir.join("a.txt"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        opened.unwrap();
+        assert_eq!(e.buffer().settings.tab_stop, 4);
+        // What the command line leaves alone still comes from the .editorconfig
+        assert!(e.buffer().settings.expand_tabs);
+    }
+
+    #[test]
+    fn formatter_file_is_new_and_removed() {
+        let log = std::env::temp_dir().join(format!("rustitor-format-log-{}", std::process::id()));
+        let mut e = Editor::new_from_string("a\nb\n", (80, 24));
+        // The formatter notes the path it was given and rewrites the file
+        e.format_selection_with(&format!("f() {{ echo \"$1\" > '{}'; printf 'c\\n' > \"$1\"; }}; f", log.display()));
+        assert_eq!(rows(&e), ["c"]);
+        let path = std::fs::read_to_string(&log).unwrap();
+        assert!(path.trim_end().ends_with(".txt") && !Path::new(path.trim_end()).exists());
+
+        e.format_selection_with(&format!("f() {{ echo \"$1\" > '{}'; echo bad >&2; exit 1; }}; f", log.display()));
+        assert_eq!(e.message.text, "Formatter failed: bad");
+        let failed_path = std::fs::read_to_string(&log).unwrap();
+        std::fs::remove_file(&log).unwrap();
+        assert!(!Path::new(failed_path.trim_end()).exists());
+    }
+
+    #[test]
+    fn undo_back_to_saved_text_clears_modified() {
+        let mut e = Editor::new_from_string("abc\n", (80, 24));
+        press(&mut e, &[InputSeq::Key(b'x', false), InputSeq::Key(b'y', false)]);
+        assert!(e.buffer().is_dirty());
+        press(&mut e, &[InputSeq::Key(b'z', true), InputSeq::Key(b'z', true)]);
+        assert_eq!(rows(&e), ["abc"]);
+        assert!(!e.buffer().is_dirty());
+        press(&mut e, &[InputSeq::Key(b'y', true)]);
+        assert!(e.buffer().is_dirty());
+        // A change which isn't in the undo stack can't be undone to get back to the saved text
+        press(&mut e, &[InputSeq::Key(b'z', true)]);
+        e.buffer_mut().toggle_line_ending();
+        assert!(e.buffer().is_dirty());
+    }
+
+    #[test]
+    fn pipeline_edits_and_writes_rows() {
+        let mut e = Editor::new_from_string("one\ntwo\n", (80, 24));
+        e.run_pipeline("goto 2\ninsert \"2 \"\nquit\ndelete\n").unwrap();
+        e.buffer_mut().settings.insert_final_newline = false;
+        let mut out = vec![];
+        e.write_to(&mut out).unwrap();
+        assert_eq!(out, b"one\n2 two");
+        assert!(e.run_pipeline("goto").is_err());
+    }
+
+    #[test]
+    fn keys_typed_while_waiting_for_the_clipboard_are_kept() {
+        let mut e = Editor::new_from_string("\n", (80, 24));
+        e.settings.clipboard_provider = ClipboardProvider::Osc52;
+        let reply = InputSeq::Clipboard(b"xy".to_vec());
+        e.input = vec![Ok(InputSeq::Key(b'a', false)), Ok(reply), Ok(InputSeq::Key(b'b', false))].into_iter();
+        press(&mut e, &[InputSeq::Key(b'v', true)]);
+        while let Some(Ok(seq)) = e.next_seq() {
+            press(&mut e, &[seq]);
+        }
+        assert_eq!(rows(&e), ["xyab"]);
+
+        // Without a reply the keys are kept until the input times out, and the editor's own clipboard is pasted
+        let mut e = Editor::new_from_string("\n", (80, 24));
+        e.settings.clipboard_provider = ClipboardProvider::Osc52;
+        e.clipboard = vec!["c".to_string()];
+        e.input = vec![Ok(InputSeq::Key(b'a', false)), Ok(InputSeq::Unidentified), Ok(InputSeq::Key(b'b', false))]
+            .into_iter();
+        press(&mut e, &[InputSeq::Key(b'v', true)]);
+        while let Some(Ok(seq)) = e.next_seq() {
+            if seq != InputSeq::Unidentified {
+                press(&mut e, &[seq]);
+            }
+        }
+        assert_eq!(rows(&e), ["cab"]);
+    }
+
+    #[test]
+    fn search_all_finds_every_match_in_the_rendered_rows() {
+        let mut e = Editor::new_from_string("abcabc\n\tabc\n\u{4f60}abcab\n", (80, 24));
+        // The tab is drawn as 8 spaces
+        assert_eq!(e.search_all("abc"), [(0, 0), (0, 3), (1, 8), (2, 1)]);
+        assert_eq!(e.search_all("bcabc"), [(0, 1)]);
+        assert_eq!(e.search_all("aa"), []);
+        assert_eq!(e.search_all(""), []);
+    }
+
+    #[test]
+    fn cutting_a_selection_deletes_its_range_and_undo_restores_it() {
+        let mut e = Editor::new_from_string("a\u{4f60}bc\nde\u{301}f\n", (80, 24));
+        e.settings.clipboard_provider = ClipboardProvider::Internal;
+        // Shift-Right twice from after the a, then C-x
+        type_bytes(&mut e, b"\x1b[C\x1b[1;2C\x1b[1;2C\x18");
+        assert_eq!(rows(&e), ["ac", "de\u{301}f"]);
+        // Into the next row, ending after its combining mark
+        type_bytes(&mut e, b"\x1b[1;2C\x1b[1;2C\x1b[1;2C\x1b[1;2C\x18");
+        assert_eq!(rows(&e), ["af"]);
+        type_bytes(&mut e, b"\x1a\x1a");
+        assert_eq!(rows(&e), ["a\u{4f60}bc", "de\u{301}f"]);
+    }
+
+    #[test]
+    fn prompt_backspace_removes_the_last_character() {
+        let mut e = Editor::new_from_string("", (80, 24));
+        let keys = [
+            InputSeq::Key(b'a', false),
+            InputSeq::Char('\u{4f60}'),
+            InputSeq::Key(b'b', false),
+            InputSeq::Key(0x7f, false),
+            InputSeq::Key(b'h', true),
+            InputSeq::Key(b'c', false),
+            InputSeq::Key(b'\r', false),
+        ];
+        e.input = keys.into_iter().map(Ok).collect::<Vec<_>>().into_iter();
+        let mut seen = vec![];
+        let input = e.prompt_with("Name: ", |editor, input, _| seen.push((input.clone(), editor.prompt_cursor)));
+        assert_eq!(input.unwrap(), Some("ac".to_string()));
+        let typed: Vec<&str> = seen.iter().map(|(input, _)| input.as_str()).collect();
+        assert_eq!(typed, ["a", "a\u{4f60}", "a\u{4f60}b", "a\u{4f60}", "a", "ac", "ac"]);
+        // The cursor is after the input, where the wide character takes two columns
+        assert_eq!(seen[2].1, Some(6 + 4));
+        assert_eq!(e.prompt_cursor, None);
+        // Backspace with nothing typed leaves nothing
+        e.input = vec![Ok(InputSeq::Key(0x7f, false)), Ok(InputSeq::Key(b'\r', false))].into_iter();
+        assert_eq!(e.prompt("Name: ").unwrap(), None);
+    }
+
+    #[test]
+    fn prompt_escape_cancels() {
+        let mut e = Editor::new_from_string("", (80, 24));
+        e.input = vec![Ok(InputSeq::Key(b'a', false)), Ok(InputSeq::Key(0x1b, false)), Ok(InputSeq::Key(b'b', false))]
+            .into_iter();
+        assert_eq!(e.prompt("Name: ").unwrap(), None);
+        assert_eq!(e.message.text, "Canceled.");
+        assert_eq!(e.prompt_cursor, None);
+        // Keys after the Escape are left for the editor
+        assert_eq!(e.next_seq().unwrap().unwrap(), InputSeq::Key(b'b', false));
+    }
+
+    #[test]
+    fn ctrl_g_twice_goes_to_top_and_ctrl_g_ctrl_e_to_end() {
+        let mut e = Editor::new_from_string("one\ntwo\nthree\n", (80, 24));
+        press(&mut e, &[InputSeq::Key(b'g', true)]);
+        assert_eq!(e.message.text, "C-g-");
+        press(&mut e, &[InputSeq::Key(b'e', true)]);
+        assert_eq!(cursor(&e), (5, 2));
+        press(&mut e, &[InputSeq::Key(b'g', true), InputSeq::Key(b'g', true)]);
+        assert_eq!(cursor(&e), (0, 0));
+        // A digit is the start of a line number
+        e.input = vec![Ok(InputSeq::Key(b'\r', false))].into_iter();
+        press(&mut e, &[InputSeq::Key(b'g', true), InputSeq::Key(b'2', false)]);
+        assert_eq!(cursor(&e), (0, 1));
+    }
+
+    #[test]
+    fn unbound_key_after_a_prefix_is_reported_and_dropped() {
+        let mut e = Editor::new_from_string("one\ntwo\n", (80, 24));
+        for prefix in [InputSeq::Key(b'g', true), InputSeq::Key(b'x', true)] {
+            press(&mut e, &[prefix, InputSeq::Key(b'q', false)]);
+            assert_eq!(e.message.text, "Unknown command");
+            assert_eq!(rows(&e), ["one", "two"]);
+            // The prefix is over, so the next key is typed again
+            press(&mut e, &[InputSeq::Key(b'q', false)]);
+            assert_eq!(rows(&e), ["qone", "two"]);
+            press(&mut e, &[InputSeq::Key(b'z', true)]);
+        }
+    }
+
+    #[test]
+    fn count_before_ctrl_x_applies_to_the_key_after_it() {
+        let mut e = Editor::new_from_string("\n", (80, 24));
+        // Recorded as C-x ( a C-x ), then run three times with M-3 C-x e
+        type_bytes(&mut e, b"\x18(a\x18)\x1b3\x18e");
+        assert_eq!(rows(&e), ["aaaa"]);
+    }
+
+    #[test]
+    fn ctrl_u_takes_the_digits_after_it_or_means_4() {
+        let mut e = Editor::new_from_string("\n", (80, 24));
+        type_bytes(&mut e, b"\x1512");
+        assert_eq!(e.message.text, "Count: 12");
+        type_bytes(&mut e, b"a");
+        assert_eq!(rows(&e), ["a".repeat(12)]);
+        // Digits without Ctrl-U are typed
+        type_bytes(&mut e, b"\x15b3");
+        assert_eq!(rows(&e), ["a".repeat(12) + "bbbb3"]);
+        type_bytes(&mut e, b"\x15\x15\x1b[D");
+        assert_eq!(cursor(&e), (13, 0));
+    }
+
+    #[test]
+    fn closing_a_modified_buffer_takes_a_second_ctrl_w() {
+        let mut e = Editor::new_from_string("first\n", (80, 24));
+        e.add_buffer(EditorBuffer::from_text("second\n", e.settings));
+        press(&mut e, &[InputSeq::Key(b'x', false), InputSeq::Key(b'w', true)]);
+        assert_eq!(e.buffers.len(), 2);
+        assert!(e.message.text.starts_with("Unsaved changes!"));
+        press(&mut e, &[InputSeq::Key(b'w', true)]);
+        assert_eq!(e.buffers.len(), 1);
+        assert_eq!(rows(&e), ["first"]);
+        // An unmodified buffer closes at once, and the last one is replaced by an empty buffer
+        press(&mut e, &[InputSeq::Key(b'w', true)]);
+        assert_eq!(e.buffers.len(), 1);
+        assert!(rows(&e).is_empty());
+    }
+
+    #[test]
+    fn switching_buffers_wraps_around() {
+        let mut e = Editor::new_from_string("one\n", (80, 24));
+        e.add_buffer(EditorBuffer::from_text("two\n", e.settings));
+        e.add_buffer(EditorBuffer::from_text("three\n", e.settings));
+        assert_eq!(e.current, 2);
+        press(&mut e, &[InputSeq::CtrlTab]);
+        assert_eq!(rows(&e), ["one"]);
+        press(&mut e, &[InputSeq::CtrlShiftTab, InputSeq::CtrlShiftTab]);
+        assert_eq!(rows(&e), ["two"]);
+        press(&mut e, &[InputSeq::CtrlTab, InputSeq::CtrlTab]);
+        assert_eq!(rows(&e), ["one"]);
+    }
+
+    #[test]
+    fn opened_file_goes_into_a_new_buffer() {
+        let dir = std::env::temp_dir().join(format!("rustitor-open-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("opened.txt");
+        std::fs::write(&path, "from disk\n").unwrap();
+        let mut e = Editor::new_from_string("typed\n", (80, 24));
+        e.lock_files = false;
+        let mut keys: Vec<_> = path.to_str().unwrap().bytes().map(|b| Ok(InputSeq::Key(b, false))).collect();
+        keys.push(Ok(InputSeq::Key(b'\r', false)));
+        e.input = keys.into_iter();
+        press(&mut e, &[InputSeq::Key(b'o', true)]);
+        assert_eq!((e.buffers.len(), e.current), (2, 1));
+        assert_eq!(rows(&e), ["from disk"]);
+        press(&mut e, &[InputSeq::CtrlTab]);
+        assert_eq!(rows(&e), ["typed"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ctrl_x_keys_switch_and_close_buffers() {
+        let mut e = Editor::new_from_string("first\n", (80, 24));
+        e.buffer_mut().label = Some("first".to_string());
+        e.add_buffer(EditorBuffer::from_text("second\n", e.settings));
+        e.buffer_mut().label = Some("second".to_string());
+        e.input = vec![Ok(InputSeq::Key(b'f', false)), Ok(InputSeq::Key(b'\r', false))].into_iter();
+        press(&mut e, &[InputSeq::Key(b'x', true), InputSeq::Key(b'b', false)]);
+        assert_eq!(rows(&e), ["first"]);
+        press(&mut e, &[InputSeq::Key(b'x', true), InputSeq::Key(b'k', false)]);
+        assert_eq!(e.buffers.len(), 1);
+        assert_eq!(rows(&e), ["second"]);
+    }
+
+    #[test]
+    fn ctrl_x_keys_come_from_a_table() {
+        let mut e = Editor::new_from_string("abc\n", (80, 24));
+        e.ctrl_x_keys.insert(b'd', InputSeq::Key(b'd', true));
+        press(&mut e, &[InputSeq::Key(b'x', true), InputSeq::Key(b'd', false)]);
+        assert_eq!(rows(&e), ["abc", "abc"]);
+        press(&mut e, &[InputSeq::Key(b'x', true), InputSeq::Key(b'2', false)]);
+        assert_eq!(e.message.text, "Split windows are not supported");
+        assert_eq!(e.buffers.len(), 1);
+    }
+
+    #[test]
+    fn panic_hook_puts_the_terminal_back() {
+        use std::os::unix::io::FromRawFd;
+        use termios::*;
+
+        let (mut master, mut slave) = (0, 0);
+        let opened = unsafe {
+            libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), std::ptr::null())
+        };
+        assert_eq!(opened, 0, "{}", io::Error::last_os_error());
+        let mut master = unsafe { std::fs::File::from_raw_fd(master) };
+        let slave = unsafe { std::fs::File::from_raw_fd(slave) };
+        let orig = Termios::from_fd(slave.as_raw_fd()).unwrap();
+        let mut raw = orig;
+        cfmakeraw(&mut raw);
+        tcsetattr(slave.as_raw_fd(), TCSANOW, &raw).unwrap();
+        assert!(Termios::from_fd(slave.as_raw_fd()).unwrap().c_lflag & ICANON == 0);
+
+        let before = std::panic::take_hook();
+        install_panic_hook(slave.as_raw_fd(), orig, slave.try_clone().unwrap());
+        let result = std::panic::catch_unwind(|| panic!("bug in the editor"));
+        std::panic::set_hook(before);
+        assert!(result.is_err());
+
+        assert!(Termios::from_fd(slave.as_raw_fd()).unwrap() == orig);
+        let reset = b"\x1b[?2004l\x1b[?1006l\x1b[?1000l\x1b[?25h\x1b[2J\x1b[H";
+        let mut shown = vec![0; reset.len()];
+        master.read_exact(&mut shown).unwrap();
+        assert_eq!(shown, reset);
+    }
+
+    #[test]
+    fn end_on_a_long_line_scrolls_to_its_last_character() {
+        let line = format!("\tab\u{4f60}\u{597d}{}", "x".repeat(40));
+        let mut e = Editor::new_from_string(&format!("{}\nshort\n", line), (20, 10));
+        press(&mut e, &[InputSeq::EndKey]);
+        e.setup_scroll();
+        // A tab and two wide characters make the width differ from the number of characters
+        let (chars, width) = (line.chars().count(), 8 + 2 + 4 + 40);
+        assert_eq!(cursor(&e), (chars, 0));
+        assert_eq!(e.buffer().rx, width);
+        let coloff = e.buffer().coloff;
+        assert!(coloff > 0 && coloff <= width && width < coloff + e.text_cols(), "coloff {}", coloff);
+
+        // Home goes to the indent, and the view scrolls back to show it
+        press(&mut e, &[InputSeq::HomeKey]);
+        e.setup_scroll();
+        assert_eq!((cursor(&e), e.buffer().rx), ((1, 0), 8));
+        assert!(e.buffer().coloff < 8);
     }
-    editor.run()
 }
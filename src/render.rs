@@ -0,0 +1,619 @@
+use crate::buffer::match_ranges;
+use crate::highlight::Highlight;
+use crate::lsp::Severity;
+use crate::{Editor, InputSeq, ScreenBuffer};
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::SystemTime;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+// Draws a whole frame of the editor: the text area, the status bar, the message bar and the
+// cursor
+pub trait Renderer<I: Iterator<Item = io::Result<InputSeq>>> {
+    fn render(&self, editor: &Editor<I>, buf: &mut dyn Write) -> io::Result<()>;
+
+    // The next frame is drawn in full, after something else wrote to the screen
+    fn invalidate(&self) {}
+}
+
+// Drawn from a third of the way down an empty screen, each line centered. The key bindings are
+// padded to the same width so that they line up
+const WELCOME: &[&str] = &[
+    concat!("Rustitor editor -- version ", env!("CARGO_PKG_VERSION")),
+    "A small text editor for the terminal",
+    "",
+    "Ctrl-O  open a file     ",
+    "Ctrl-S  save            ",
+    "Ctrl-F  find            ",
+    "F1      all key bindings",
+    "Ctrl-Q  quit            ",
+];
+
+// Escape sequences for the terminal, in the colors of the theme. Only the rows which changed
+// since the last frame are written
+#[derive(Default)]
+pub struct AnsiRenderer {
+    screen: RefCell<ScreenBuffer>,
+    // Shown in the status bar with RUSTITOR_DEBUG set
+    rows_written: Cell<usize>,
+}
+
+// The text of the screen without any escape sequences, a line for each screen row, for
+// terminals which can't move the cursor. Long lines are cut at the edge of the screen rather
+// than wrapped
+pub struct DumbRenderer;
+
+// The part of `line` within the visible columns, never splitting a character
+fn trim_line<'a, I, S>(editor: &Editor<I>, line: &'a S) -> &'a str
+where
+    I: Iterator<Item = io::Result<InputSeq>>,
+    S: AsRef<str>,
+{
+    let line = line.as_ref();
+    let (start, end) = (editor.buffer().coloff, editor.buffer().coloff + editor.text_cols());
+    let mut col = 0;
+    let (mut from, mut to) = (line.len(), line.len());
+    for (i, c) in line.char_indices() {
+        if col >= start && from == line.len() {
+            from = i;
+        }
+        col += c.width().unwrap_or(0);
+        if col > end {
+            to = i;
+            break;
+        }
+    }
+    &line[cmp::min(from, to)..to]
+}
+
+fn tab_strip<I>(editor: &Editor<I>) -> String
+where
+    I: Iterator<Item = io::Result<InputSeq>>,
+{
+    // Leave at least half of the status bar for the rest of the information
+    let width = cmp::max(3, editor.screen_cols / 2 / editor.buffers.len());
+    let mut strip = String::new();
+    for (i, buffer) in editor.buffers.iter().enumerate() {
+        let mut name: String = buffer.name().chars().take(width).collect();
+        if buffer.is_dirty() {
+            name.push('*');
+        }
+        if i == editor.current {
+            strip.push_str(&format!("[{}]", name));
+        } else {
+            strip.push_str(&format!(" {} ", name));
+        }
+    }
+    strip
+}
+
+// The theme's text colors, or nothing for the terminal's own
+fn default_colors<I>(editor: &Editor<I>) -> String
+where
+    I: Iterator<Item = io::Result<InputSeq>>,
+{
+    match editor.settings.color_scheme.palette() {
+        Some(palette) => palette.bg_default.bg(editor.color_depth) + &palette.fg_default.fg(editor.color_depth),
+        None => String::new(),
+    }
+}
+
+// The left and the right end of the status bar
+fn status_text<I>(editor: &Editor<I>) -> (String, String)
+where
+    I: Iterator<Item = io::Result<InputSeq>>,
+{
+    let buffer = editor.buffer();
+    let modified = if buffer.settings.read_only {
+        "(read-only) "
+    } else if buffer.is_dirty() {
+        "(modified) "
+    } else {
+        ""
+    };
+    let more = if buffer.fully_loaded() { "" } else { "+" };
+    let left = if editor.buffers.len() > 1 {
+        format!("{} - {}{} lines {}", tab_strip(editor), buffer.rows.len(), more, modified)
+    } else {
+        format!("{:<20?} - {}{} lines {}", buffer.name(), buffer.rows.len(), more, modified)
+    };
+
+    let not_found = match editor.search_query {
+        Some(ref q) => !q.is_empty() && editor.search_match.is_none(),
+        None => false,
+    };
+    let ending = if buffer.settings.crlf { "CRLF" } else { "LF" };
+    let line_ending = format!("[{}] [{}]", buffer.encoding.name(), ending);
+    let right = match editor.cached_stats {
+        _ if not_found => "Not found".to_string(),
+        Some((_, stats)) if editor.show_stats => {
+            format!("{} L:{} W:{} C:{}", line_ending, stats.lines, stats.words, stats.chars)
+        }
+        _ if editor.show_line_numbers => {
+            // The gutter already shows the line
+            let line_width = buffer.rows.get(buffer.cy).map_or(0, |row| row.render.width());
+            format!("{} Col {} of {}", line_ending, buffer.rx + 1, line_width)
+        }
+        _ => format!("{} Ln {}, Col {}", line_ending, buffer.cy + 1, buffer.rx + 1),
+    };
+    (left, right)
+}
+
+// The message, or without a fresh one what is wrong with the cursor's line, cut to the screen
+fn message_text<I>(editor: &Editor<I>) -> String
+where
+    I: Iterator<Item = io::Result<InputSeq>>,
+{
+    let fresh = SystemTime::now()
+        .duration_since(editor.message.timestamp)
+        .is_ok_and(|d| d.as_secs() < 5);
+    let text = if fresh {
+        Some(editor.message.text.as_str())
+    } else {
+        editor.cursor_diagnostic().map(|d| d.message.as_str())
+    };
+    let mut width = 0;
+    text.unwrap_or("")
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take_while(|c| {
+            width += c.width().unwrap_or(0);
+            width <= editor.screen_cols
+        })
+        .collect()
+}
+
+impl<I: Iterator<Item = io::Result<InputSeq>>> Renderer<I> for AnsiRenderer {
+    fn render(&self, editor: &Editor<I>, buf: &mut dyn Write) -> io::Result<()> {
+        buf.write_all(b"\x1b[?25l")?;
+        let mut rows = self.draw_rows(editor)?;
+        self.draw_scrollbar(editor, &mut rows);
+        self.draw_completion(editor, &mut rows);
+        let mut status_bar = vec![];
+        self.draw_status_bar(editor, &mut status_bar)?;
+        rows.push(status_bar);
+        let mut message_bar = vec![];
+        self.draw_message_bar(editor, &mut message_bar)?;
+        rows.push(message_bar);
+
+        let (cursor_row, cursor_col) = editor.cursor_position();
+        let written = self.screen.borrow_mut().update(rows, cursor_row, &mut *buf)?;
+        self.rows_written.set(written);
+        write!(buf, "\x1b[{};{}H", cursor_row + 1, cursor_col + 1)?;
+        buf.write_all(b"\x1b[?25h")
+    }
+
+    fn invalidate(&self) {
+        self.screen.borrow_mut().invalidate();
+    }
+}
+
+impl<I: Iterator<Item = io::Result<InputSeq>>> Renderer<I> for DumbRenderer {
+    fn render(&self, editor: &Editor<I>, buf: &mut dyn Write) -> io::Result<()> {
+        let buffer = editor.buffer();
+        for y in 0..editor.screen_rows {
+            match buffer.rows.get(buffer.rowoff + y) {
+                Some(row) => write!(buf, "{}\r\n", trim_line(editor, &row.render).trim_end())?,
+                None => write!(buf, "~\r\n")?,
+            }
+        }
+        let (left, right) = status_text(editor);
+        write!(buf, "{} {}\r\n", left.trim_end(), right)?;
+        write!(buf, "{}\r\n", message_text(editor))
+    }
+}
+
+impl AnsiRenderer {
+    // Ends each row with the minimap and the scrollbar, at the right edge of the screen
+    fn draw_scrollbar<I>(&self, editor: &Editor<I>, rows: &mut [Vec<u8>])
+    where
+        I: Iterator<Item = io::Result<InputSeq>>,
+    {
+        let width = editor.scrollbar_width();
+        if width == 0 {
+            return;
+        }
+        let shades = if editor.has_minimap() { editor.cached_minimap.as_ref().map(|(_, shades)| shades) } else { None };
+        let (top, height) = editor.scrollbar_thumb();
+        for (y, row) in rows.iter_mut().enumerate() {
+            let _ = write!(row, "\x1b[{};{}H", y + 1, editor.screen_cols.saturating_sub(width) + 1);
+            if let Some(shade) = shades.and_then(|shades| shades.get(y)) {
+                let _ = write!(row, "\x1b[48;5;{}m  \x1b[m", shade);
+            }
+            if editor.settings.show_scrollbar {
+                let cell: &[u8] = if top <= y && y < top + height { "█".as_bytes() } else { b" " };
+                row.extend_from_slice(b"\x1b[38;5;244;48;5;235m");
+                row.extend_from_slice(cell);
+                row.extend_from_slice(b"\x1b[m");
+            }
+        }
+    }
+
+    pub fn draw_status_bar<I, W>(&self, editor: &Editor<I>, mut buf: W) -> io::Result<()>
+    where
+        I: Iterator<Item = io::Result<InputSeq>>,
+        W: Write,
+    {
+        match editor.settings.color_scheme.palette() {
+            Some(palette) => {
+                let depth = editor.color_depth;
+                write!(buf, "{}{}", palette.bg_status_bar.bg(depth), palette.fg_status_bar.fg(depth))?
+            }
+            None => buf.write_all(b"\x1b[7m")?,
+        }
+
+        let (left, right) = status_text(editor);
+        let left: String = left.chars().take(editor.screen_cols).collect();
+        let left_len = left.chars().count();
+        buf.write_all(left.as_bytes())?;
+
+        let rest_len = editor.screen_cols - left_len;
+        let right = if editor.debug {
+            format!("frame {}, {} rows written {}", editor.frames, self.rows_written.get(), right)
+        } else {
+            right
+        };
+        let right_len = right.width();
+        if right_len > rest_len {
+            for _ in 0..rest_len {
+                buf.write_all(b" ")?;
+            }
+        } else {
+            for _ in 0..rest_len - right_len {
+                buf.write_all(b" ")?;
+            }
+            buf.write_all(right.as_bytes())?;
+        }
+
+        buf.write_all(b"\x1b[m")?;
+        Ok(())
+    }
+
+    fn draw_message_bar<I, W>(&self, editor: &Editor<I>, mut buf: W) -> io::Result<()>
+    where
+        I: Iterator<Item = io::Result<InputSeq>>,
+        W: Write,
+    {
+        buf.write_all(default_colors(editor).as_bytes())?;
+        buf.write_all(message_text(editor).as_bytes())?;
+        buf.write_all(b"\x1b[K\x1b[m")?;
+        Ok(())
+    }
+
+    // One line of output for each row of the text area
+    pub fn draw_rows<I>(&self, editor: &Editor<I>) -> io::Result<Vec<Vec<u8>>>
+    where
+        I: Iterator<Item = io::Result<InputSeq>>,
+    {
+        let mut rows = Vec::with_capacity(editor.screen_rows);
+        let buffer = editor.buffer();
+        let gutter = editor.line_number_width();
+        let width = editor.text_cols();
+        let (mut file_row, mut seg) = (buffer.rowoff, buffer.wrapoff);
+        let mut starts = buffer.wrap_starts(file_row, width);
+        let sign_column = editor.has_sign_column();
+        let mut severities: HashMap<usize, Severity> = HashMap::new();
+        for d in editor.diagnostics().unwrap_or(&[]) {
+            let severity = severities.entry(d.line).or_insert(d.severity);
+            if d.severity > *severity {
+                *severity = d.severity;
+            }
+        }
+        let sidebar = editor.sidebar_width();
+        for y in 0..editor.screen_rows {
+            let mut buf = vec![];
+            if sidebar > 0 {
+                editor.browser.draw_row(&mut buf, y, sidebar, editor.sidebar_focused)?;
+            }
+            let default_colors = default_colors(editor);
+            if let Some(palette) = editor.settings.color_scheme.palette() {
+                write!(buf, "{}{}", default_colors, palette.fg_gutter.fg(editor.color_depth))?;
+            }
+            if sign_column {
+                let mark = buffer.diff.as_ref().and_then(|diff| diff.get(&file_row));
+                let sign = match (severities.get(&file_row), mark) {
+                    _ if seg > 0 => " ",
+                    (Some(severity), _) => severity.symbol(),
+                    (None, Some(mark)) => mark.symbol(),
+                    (None, None) => " ",
+                };
+                buf.write_all(sign.as_bytes())?;
+            }
+            if gutter > 0 {
+                if seg > 0 {
+                    // A wrapped line only has its number on the first screen row
+                    write!(buf, "{:>1$}│", "", gutter - 1)?;
+                } else if buffer.bookmarks.contains(&file_row) {
+                    // The number is always a column short of the gutter, which leaves room for the mark
+                    write!(buf, "●{:>1$}│", file_row + 1, gutter - 2)?;
+                } else if file_row < buffer.rows.len() {
+                    write!(buf, "{:>1$}│", file_row + 1, gutter - 1)?;
+                } else {
+                    write!(buf, "{:>1$}│", "~", gutter - 1)?;
+                }
+            }
+            buf.write_all(default_colors.as_bytes())?;
+            if file_row >= buffer.rows.len() {
+                let welcome_line = y.checked_sub(editor.screen_rows / 3).and_then(|i| WELCOME.get(i));
+                if let Some(line) = welcome_line.filter(|_| editor.shows_welcome()) {
+                    let welcome = trim_line(editor, line);
+                    let padding = (editor.text_cols() - welcome.width()) / 2;
+                    if padding > 0 {
+                        if gutter == 0 {
+                            buf.write_all(b"~")?;
+                        } else {
+                            buf.write_all(b" ")?;
+                        }
+                        for _ in 0..padding - 1 {
+                            buf.write_all(b" ")?;
+                        }
+                    }
+                    buf.write_all(welcome.as_bytes())?;
+                } else if gutter == 0 {
+                    buf.write_all(b"~")?;
+                }
+                buf.write_all(b"\x1b[K\x1b[m")?;
+            } else if buffer.settings.word_wrap {
+                let start = starts[seg];
+                self.draw_row(editor, &mut buf, file_row, start, starts.get(seg + 1).copied().unwrap_or(start + width))?;
+            } else {
+                self.draw_row(editor, &mut buf, file_row, buffer.coloff, buffer.coloff + width)?;
+            }
+            rows.push(buf);
+
+            seg += 1;
+            if seg == starts.len() {
+                file_row += 1;
+                seg = 0;
+                starts = buffer.wrap_starts(file_row, width);
+            }
+        }
+        Ok(rows)
+    }
+
+    // Draws the render columns of row `y` from `start` up to `end`
+    fn draw_row<I, W>(&self, editor: &Editor<I>, mut buf: W, y: usize, start: usize, end: usize) -> io::Result<()>
+    where
+        I: Iterator<Item = io::Result<InputSeq>>,
+        W: Write,
+    {
+        let row = &editor.buffer().rows[y];
+        let mut highlight = row.highlight.clone();
+        if editor.settings.show_trailing_whitespace {
+            for h in highlight.iter_mut().skip(row.trailing_whitespace) {
+                *h = Highlight::TrailingWhitespace;
+            }
+        }
+        if let Some(((start_row, start_col), (end_row, end_col))) = editor.buffer().selection_range() {
+            if start_row <= y && y <= end_row {
+                let start = if y == start_row { row.render_index_of_grapheme(start_col, editor.settings.tab_stop) } else { 0 };
+                let end = if y == end_row { row.render_index_of_grapheme(end_col, editor.settings.tab_stop) } else { highlight.len() };
+                for h in highlight.iter_mut().take(end).skip(start) {
+                    *h = Highlight::Selection;
+                }
+            }
+        }
+        if let Some((top, bottom, left, right)) = editor.buffer().column_selection.map(|sel| sel.rect()) {
+            if top <= y && y <= bottom {
+                let start = row.render_index_of_grapheme(left, editor.settings.tab_stop);
+                let end = row.render_index_of_grapheme(right, editor.settings.tab_stop);
+                for h in highlight.iter_mut().take(end).skip(start) {
+                    *h = Highlight::Selection;
+                }
+            }
+        }
+        if let Some(ref query) = editor.search_query {
+            let found: Vec<(usize, usize)> = if editor.search_ignore_case {
+                match_ranges(&row.render, query, true)
+                    .into_iter()
+                    .map(|(idx, end)| (row.render[..idx].chars().count(), row.render[idx..end].chars().count()))
+                    .collect()
+            } else {
+                let len = query.chars().count();
+                row.matches(query).into_iter().map(|start| (start, len)).collect()
+            };
+            for (start, len) in found {
+                for h in highlight.iter_mut().skip(start).take(len) {
+                    *h = Highlight::Match;
+                }
+            }
+        }
+
+        // Written after every reset so that it lasts to the end of the line
+        let palette = editor.settings.color_scheme.palette();
+        let line_color = if editor.settings.highlight_current_line && y == editor.buffer().cy {
+            match palette {
+                Some(palette) => palette.bg_current_line.bg(editor.color_depth),
+                None => format!("\x1b[48;5;{}m", editor.settings.current_line_color),
+            }
+        } else if let Some(color) = editor.line_backgrounds.get(&y) {
+            format!("\x1b[48;5;{}m", color)
+        } else if let Some(palette) = palette {
+            palette.bg_default.bg(editor.color_depth)
+        } else {
+            String::new()
+        };
+        let line_color = line_color.as_bytes();
+        buf.write_all(line_color)?;
+
+        let bracket = editor
+            .bracket_match
+            .filter(|&(bracket_row, _)| bracket_row == y)
+            .map(|(_, x)| row.render_index_of_grapheme(x, editor.settings.tab_stop));
+        let ruler = editor.settings.ruler_column.unwrap_or(usize::MAX);
+        let mut current = (Highlight::Normal, false, false);
+        let (mut col, mut idx) = (0, 0);
+        for g in row.render.graphemes(true) {
+            let width = g.width();
+            let hl = highlight.get(idx).copied().unwrap_or(Highlight::Normal);
+            let is_bracket = bracket == Some(idx);
+            idx += g.chars().count();
+            let next = col + width;
+            if next <= start {
+                col = next;
+                continue;
+            }
+            if next > end {
+                break;
+            }
+            let past_ruler = col >= ruler;
+            if (hl, past_ruler, is_bracket) != current {
+                buf.write_all(b"\x1b[m")?;
+                buf.write_all(line_color)?;
+                if past_ruler {
+                    buf.write_all(b"\x1b[48;5;52m")?;
+                }
+                buf.write_all(&editor.settings.color_scheme.color(hl, editor.color_depth))?;
+                if is_bracket {
+                    buf.write_all(b"\x1b[1;4m")?;
+                }
+                current = (hl, past_ruler, is_bracket);
+            }
+            if col < start {
+                // Only the right half of a wide character is on screen
+                write!(buf, "{:1$}", "", next - start)?;
+            } else {
+                buf.write_all(g.as_bytes())?;
+            }
+            col = next;
+        }
+        buf.write_all(b"\x1b[m")?;
+        if line_color.is_empty() {
+            buf.write_all(b"\x1b[K")?;
+        } else {
+            // Padded with spaces, since not every terminal clears the line with the background color
+            buf.write_all(line_color)?;
+            write!(buf, "{:1$}", "", editor.text_cols().saturating_sub(col.saturating_sub(start)))?;
+        }
+        buf.write_all(b"\x1b[m")
+    }
+
+    // Drawn over the rows below the cursor, or above it when there is no room below
+    fn draw_completion<I>(&self, editor: &Editor<I>, rows: &mut [Vec<u8>])
+    where
+        I: Iterator<Item = io::Result<InputSeq>>,
+    {
+        let completion = match editor.completion {
+            Some(ref completion) => completion,
+            None => return,
+        };
+        let (row, col) = editor.buffer().screen_cursor(editor.text_cols());
+        let n = completion.items.len();
+        let top = if row + 1 + n <= rows.len() || row < n { row + 1 } else { row - n };
+
+        let longest = completion.items.iter().map(|item| item.len()).max().unwrap_or(0);
+        let width = cmp::min(longest + 2, editor.screen_cols);
+        let left = (editor.text_left() + col).saturating_sub(completion.prefix_len + 1);
+        let left = cmp::min(left, editor.screen_cols.saturating_sub(width));
+
+        for (i, item) in completion.items.iter().enumerate() {
+            let row = match rows.get_mut(top + i) {
+                Some(row) => row,
+                None => break,
+            };
+            let color = if i == completion.selected { "\x1b[7m" } else { "\x1b[48;5;238m" };
+            let item: String = item.chars().take(width.saturating_sub(2)).collect();
+            let _ = write!(row, "\x1b[{};{}H{} {:<w$} \x1b[m", top + i + 1, left + 1, color, item, w = width.saturating_sub(2));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trim_line_cuts_multibyte_text_at_characters() {
+        let line = "h\u{e9}llo \u{4f60}\u{597d} w\u{f6}rld \u{1f600}!";
+        // Ten columns of text, and one for the scrollbar
+        let mut editor = Editor::new_from_string(line, (11, 6));
+        editor.show_line_numbers = false;
+        let width = editor.text_cols();
+        let columns: usize = line.chars().map(|c| c.width().unwrap_or(0)).sum();
+        for coloff in 0..columns + 2 {
+            editor.buffer_mut().coloff = coloff;
+            let shown = trim_line(&editor, &line);
+            assert!(shown.chars().map(|c| c.width().unwrap_or(0)).sum::<usize>() <= width);
+            // Starts at the first character at or after the column, or is empty past the end
+            let mut col = 0;
+            let expected = line.char_indices().find_map(|(i, c)| {
+                let start = col;
+                col += c.width().unwrap_or(0);
+                (start >= coloff).then_some(i)
+            });
+            match expected {
+                Some(i) => assert!(line[i..].starts_with(shown) && !shown.is_empty(), "coloff {}", coloff),
+                None => assert_eq!(shown, "", "coloff {}", coloff),
+            }
+        }
+        editor.buffer_mut().coloff = 6;
+        assert_eq!(trim_line(&editor, &line), "\u{4f60}\u{597d} w\u{f6}rld");
+    }
+
+    #[test]
+    fn dumb_renderer_writes_plain_text() {
+        let editor = Editor::new_from_string("fn main() {\n\tbody\n}\n", (40, 6));
+        let mut buf = vec![];
+        DumbRenderer.render(&editor, &mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = text.split_terminator("\r\n").collect();
+        assert_eq!(lines[..4], ["fn main() {", "        body", "}", "~"]);
+        assert!(lines[4].starts_with("\"[No Name]\""));
+        assert!(!text.contains('\x1b'));
+    }
+
+    #[test]
+    fn ansi_renderer_writes_changed_rows_only() {
+        let mut editor = Editor::new_from_string("one\ntwo\n", (40, 6));
+        editor.show_line_numbers = false;
+        editor.settings.highlight_current_line = false;
+        let renderer = AnsiRenderer::default();
+        let mut first = vec![];
+        renderer.render(&editor, &mut first).unwrap();
+        let first = String::from_utf8(first).unwrap();
+        assert!(first.contains("\x1b[1;1H") && first.contains("one") && first.contains("two"));
+        assert!(first.ends_with("\x1b[1;1H\x1b[?25h"));
+
+        editor.buffer_mut().cy = 1;
+        let mut second = vec![];
+        renderer.render(&editor, &mut second).unwrap();
+        let second = String::from_utf8(second).unwrap();
+        assert!(!second.contains("one") && second.contains("two"));
+        assert!(second.ends_with("\x1b[2;1H\x1b[?25h"));
+    }
+
+    #[test]
+    fn welcome_shown_only_on_empty_screen() {
+        let mut editor = Editor::new_from_string("", (80, 24));
+        let rows = AnsiRenderer::default().draw_rows(&editor).unwrap();
+        let text = String::from_utf8(rows.concat()).unwrap();
+        assert!(WELCOME.iter().all(|line| text.contains(line.trim_end())));
+
+        editor.settings.show_welcome = false;
+        let rows = AnsiRenderer::default().draw_rows(&editor).unwrap();
+        assert!(!String::from_utf8(rows.concat()).unwrap().contains("Ctrl-O"));
+
+        editor.settings.show_welcome = true;
+        editor.buffer_mut().insert_char('x');
+        let rows = AnsiRenderer::default().draw_rows(&editor).unwrap();
+        assert!(!String::from_utf8(rows.concat()).unwrap().contains("Ctrl-O"));
+    }
+
+    #[test]
+    fn current_line_highlight_keeps_search_matches_visible() {
+        let mut editor = Editor::new_from_string("one two three\nfour\n", (40, 6));
+        editor.show_line_numbers = false;
+        editor.search_query = Some("two".to_string());
+        let line = format!("\x1b[48;5;{}m", editor.settings.current_line_color);
+        let rows = AnsiRenderer::default().draw_rows(&editor).unwrap();
+        let first = String::from_utf8(rows[0].clone()).unwrap();
+        // The match is reversed on top of the line's background, which comes back after it
+        assert!(first.starts_with(&line));
+        assert!(first.contains(&format!("\x1b[m{}\x1b[7mtwo\x1b[m{} three", line, line)), "{:?}", first);
+        let second = String::from_utf8(rows[1].clone()).unwrap();
+        assert!(!second.contains(&line));
+    }
+}
@@ -0,0 +1,264 @@
+use crate::row::Row;
+use std::iter::FromIterator;
+use std::ops::{Index, IndexMut};
+
+// Rows are kept in chunks of at most this many, so inserting or removing a row only shifts the
+// rows of a single chunk
+const MAX_CHUNK: usize = 1024;
+
+#[derive(Default)]
+pub struct Rope {
+    chunks: Vec<Vec<Row>>,
+    // Index of the first row of each chunk, for finding a row's chunk with a binary search
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl Rope {
+    pub fn new() -> Rope {
+        Rope::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // The chunk holding row `index` and the row's position in it
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.len {
+            return None;
+        }
+        let c = self.starts.partition_point(|&start| start <= index) - 1;
+        Some((c, index - self.starts[c]))
+    }
+
+    // Works out the starts of the chunks from chunk `from` on, after rows were added or taken out
+    fn recount(&mut self, from: usize) {
+        self.starts.truncate(from);
+        let mut start = match from {
+            0 => 0,
+            _ => self.starts[from - 1] + self.chunks[from - 1].len(),
+        };
+        for chunk in &self.chunks[from..] {
+            self.starts.push(start);
+            start += chunk.len();
+        }
+        self.len = start;
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Row> {
+        let (c, i) = self.locate(index)?;
+        Some(&self.chunks[c][i])
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Row> {
+        let (c, i) = self.locate(index)?;
+        Some(&mut self.chunks[c][i])
+    }
+
+    pub fn insert(&mut self, at: usize, row: Row) {
+        assert!(at <= self.len, "insertion index {} is out of bounds (len {})", at, self.len);
+        let (c, i) = match self.locate(at) {
+            Some(pos) => pos,
+            None if self.chunks.is_empty() => {
+                self.chunks.push(vec![]);
+                self.starts.push(0);
+                (0, 0)
+            }
+            None => (self.chunks.len() - 1, self.chunks[self.chunks.len() - 1].len()),
+        };
+        self.chunks[c].insert(i, row);
+        if self.chunks[c].len() > MAX_CHUNK {
+            let tail = self.chunks[c].split_off(MAX_CHUNK / 2);
+            self.chunks.insert(c + 1, tail);
+            self.recount(c + 1);
+        } else {
+            for start in &mut self.starts[c + 1..] {
+                *start += 1;
+            }
+            self.len += 1;
+        }
+    }
+
+    pub fn push(&mut self, row: Row) {
+        self.insert(self.len, row);
+    }
+
+    pub fn remove(&mut self, at: usize) -> Row {
+        let (c, i) = match self.locate(at) {
+            Some(pos) => pos,
+            None => panic!("removal index {} is out of bounds (len {})", at, self.len),
+        };
+        let row = self.chunks[c].remove(i);
+        let next = if self.chunks[c].is_empty() {
+            self.chunks.remove(c);
+            self.starts.remove(c);
+            c
+        } else {
+            c + 1
+        };
+        for start in &mut self.starts[next..] {
+            *start -= 1;
+        }
+        self.len -= 1;
+        row
+    }
+
+    // Removes the rows from `start` up to but not including `end`. The chunks in between are
+    // dropped whole, and only the chunks at either end have rows shifted
+    pub fn remove_range(&mut self, start: usize, end: usize) {
+        assert!(
+            start <= end && end <= self.len,
+            "removal range {}..{} is out of bounds (len {})",
+            start,
+            end,
+            self.len
+        );
+        if start == end {
+            return;
+        }
+        let (first, from) = self.locate(start).unwrap();
+        let (last, to) = match self.locate(end) {
+            Some(pos) => pos,
+            None => (self.chunks.len() - 1, self.chunks[self.chunks.len() - 1].len()),
+        };
+        if first == last {
+            self.chunks[first].drain(from..to);
+        } else {
+            self.chunks[last].drain(..to);
+            self.chunks[first].truncate(from);
+            self.chunks.drain(first + 1..last);
+        }
+        self.chunks.retain(|chunk| !chunk.is_empty());
+        self.recount(first.min(self.chunks.len()));
+    }
+
+    pub fn swap(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+        let row_b = std::mem::replace(&mut self[b], Row::empty());
+        let row_a = std::mem::replace(&mut self[a], row_b);
+        self[b] = row_a;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Row> {
+        self.chunks.iter().flatten()
+    }
+}
+
+impl FromIterator<Row> for Rope {
+    fn from_iter<T: IntoIterator<Item = Row>>(iter: T) -> Rope {
+        let mut rope = Rope::new();
+        let mut chunk = Vec::with_capacity(MAX_CHUNK / 2);
+        for row in iter {
+            chunk.push(row);
+            if chunk.len() == MAX_CHUNK / 2 {
+                rope.starts.push(rope.len);
+                rope.len += chunk.len();
+                rope.chunks.push(std::mem::replace(&mut chunk, Vec::with_capacity(MAX_CHUNK / 2)));
+            }
+        }
+        if !chunk.is_empty() {
+            rope.starts.push(rope.len);
+            rope.len += chunk.len();
+            rope.chunks.push(chunk);
+        }
+        rope
+    }
+}
+
+impl Index<usize> for Rope {
+    type Output = Row;
+
+    fn index(&self, index: usize) -> &Row {
+        match self.get(index) {
+            Some(row) => row,
+            None => panic!("row index {} is out of bounds (len {})", index, self.len),
+        }
+    }
+}
+
+impl IndexMut<usize> for Rope {
+    fn index_mut(&mut self, index: usize) -> &mut Row {
+        let len = self.len;
+        match self.get_mut(index) {
+            Some(row) => row,
+            None => panic!("row index {} is out of bounds (len {})", index, len),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(rope: &Rope, model: &[String]) {
+        assert_eq!(rope.len(), model.len());
+        assert!(rope.iter().map(|row| &row.buf).eq(model.iter()));
+        for (i, line) in model.iter().enumerate() {
+            assert_eq!(&rope[i].buf, line);
+        }
+        assert!(rope.get(model.len()).is_none());
+    }
+
+    #[test]
+    fn edits_match_a_vec_across_chunks() {
+        let mut model: Vec<String> = (0..3000).map(|i| i.to_string()).collect();
+        let mut rope: Rope = model.iter().map(Row::new).collect();
+        check(&rope, &model);
+        // Enough rows in one place to split its chunk twice
+        for i in 0..1500 {
+            model.insert(1, format!("front {}", i));
+            rope.insert(1, Row::new(format!("front {}", i)));
+        }
+        check(&rope, &model);
+
+        // Positions from a fixed linear congruential sequence, so that failures can be replayed
+        let mut seed = 12345usize;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        for step in 0..4000 {
+            match next(3) {
+                0 => {
+                    let at = next(model.len() + 1);
+                    model.insert(at, format!("new {}", step));
+                    rope.insert(at, Row::new(format!("new {}", step)));
+                }
+                1 if !model.is_empty() => {
+                    let at = next(model.len());
+                    assert_eq!(rope.remove(at).buf, model.remove(at));
+                }
+                _ => {
+                    let start = next(model.len() + 1);
+                    let end = start + next(model.len() - start + 1).min(700);
+                    model.drain(start..end);
+                    rope.remove_range(start, end);
+                }
+            }
+            if step % 100 == 0 {
+                check(&rope, &model);
+            }
+        }
+        check(&rope, &model);
+    }
+
+    #[test]
+    fn remove_range_drops_whole_chunks() {
+        let mut rope: Rope = (0..5000).map(|i| Row::new(i.to_string())).collect();
+        rope.remove_range(100, 4900);
+        assert_eq!(rope.chunks.len(), 2);
+        let model: Vec<String> = (0..100).chain(4900..5000).map(|i| i.to_string()).collect();
+        check(&rope, &model);
+        rope.remove_range(0, rope.len());
+        assert!(rope.is_empty() && rope.chunks.is_empty());
+        rope.push(Row::new("again"));
+        check(&rope, &["again".to_string()]);
+    }
+}
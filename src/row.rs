@@ -0,0 +1,525 @@
+use crate::highlight::{Highlight, LineState};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Columns taken by grapheme `g` drawn at column `col`
+pub fn advance(g: &str, col: usize, tab_stop: usize) -> usize {
+    if g == "\t" {
+        tab_stop - (col % tab_stop)
+    } else {
+        g.width()
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum TokenClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+fn token_class(g: &str) -> TokenClass {
+    if g.chars().all(char::is_whitespace) {
+        TokenClass::Space
+    } else if g.chars().any(|c| c.is_alphanumeric() || c == '_') {
+        TokenClass::Word
+    } else {
+        TokenClass::Punctuation
+    }
+}
+
+// Byte offset of the first match of `needle` in `haystack` by Boyer-Moore-Horspool, which moves
+// the window on by how far from the end of `needle` the window's last byte is last found in it
+fn horspool(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let last = needle.len() - 1;
+    let mut skip = [needle.len(); 256];
+    for (i, &b) in needle[..last].iter().enumerate() {
+        skip[b as usize] = last - i;
+    }
+    let mut at = 0;
+    while at + needle.len() <= haystack.len() {
+        if haystack[at..at + needle.len()] == *needle {
+            return Some(at);
+        }
+        at += skip[haystack[at + last] as usize];
+    }
+    None
+}
+
+// Byte offset of the first match of `query` in `text` at or after byte `from`. Long queries are
+// searched by Horspool, whose skips only pay off once they are longer than a few bytes
+pub fn find_from(text: &str, query: &str, from: usize) -> Option<usize> {
+    let rest = &text[from..];
+    let found = if query.len() > 4 { horspool(rest.as_bytes(), query.as_bytes()) } else { rest.find(query) };
+    found.map(|idx| from + idx)
+}
+
+pub struct Row {
+    pub buf: String,
+    pub render: String,
+    pub highlight: Vec<Highlight>,
+    // Index in the characters of `render` where the whitespace at the end of the line starts
+    pub trailing_whitespace: usize,
+    // The strings or comments left open by the rows above, which `highlight` was worked out from,
+    // and the ones left open after this row
+    pub start_state: LineState,
+    pub end_state: LineState,
+}
+
+impl Row {
+    // `render` and `highlight` stay empty until the owning buffer updates the row
+    pub fn new<S: Into<String>>(line: S) -> Row {
+        Row {
+            buf: line.into(),
+            render: "".to_string(),
+            highlight: vec![],
+            trailing_whitespace: 0,
+            start_state: LineState::Code,
+            end_state: LineState::Code,
+        }
+    }
+
+    pub fn empty() -> Row {
+        Row {
+            buf: "".to_string(),
+            render: "".to_string(),
+            highlight: vec![],
+            trailing_whitespace: 0,
+            start_state: LineState::Code,
+            end_state: LineState::Code,
+        }
+    }
+
+    // Wide characters take two columns of the terminal but a single character of `render`, so
+    // columns are tracked separately from the length of `render`
+    pub fn update_render(&mut self, tab_stop: usize) {
+        self.render = String::with_capacity(self.buf.len());
+        let mut col = 0;
+        for g in self.buf.graphemes(true) {
+            let width = advance(g, col, tab_stop);
+            if g == "\t" {
+                self.render.extend(std::iter::repeat_n(' ', width));
+            } else {
+                self.render.push_str(g);
+            }
+            col += width;
+        }
+        self.trailing_whitespace = self.render.trim_end().chars().count();
+    }
+
+    pub fn grapheme_count(&self) -> usize {
+        self.buf.graphemes(true).count()
+    }
+
+    pub fn byte_offset_of_grapheme(&self, n: usize) -> usize {
+        self.buf
+            .grapheme_indices(true)
+            .nth(n)
+            .map(|(idx, _)| idx)
+            .unwrap_or(self.buf.len())
+    }
+
+    pub fn grapheme_index_of_byte(&self, byte: usize) -> usize {
+        self.buf
+            .grapheme_indices(true)
+            .take_while(|(idx, _)| *idx < byte)
+            .count()
+    }
+
+    // Index in the characters of `render`, not a column
+    pub fn render_index_of_grapheme(&self, n: usize, tab_stop: usize) -> usize {
+        let mut col = 0;
+        self.buf.graphemes(true).take(n).fold(0, |idx, g| {
+            let width = advance(g, col, tab_stop);
+            col += width;
+            if g == "\t" {
+                idx + width
+            } else {
+                idx + g.chars().count()
+            }
+        })
+    }
+
+    // Grapheme index of the first character which is not a space or tab, 0 for blank lines
+    pub fn first_non_whitespace(&self) -> usize {
+        self.buf
+            .graphemes(true)
+            .position(|g| g != " " && g != "\t")
+            .unwrap_or(0)
+    }
+
+    // Skips the whitespace from grapheme `from`, then a run of word characters or of
+    // punctuation. Stops at the end of the line
+    pub fn next_token_boundary(&self, from: usize) -> usize {
+        let mut graphemes = self.buf.graphemes(true).skip(from).map(token_class).peekable();
+        let mut at = from;
+        while graphemes.next_if(|&class| class == TokenClass::Space).is_some() {
+            at += 1;
+        }
+        if let Some(class) = graphemes.next() {
+            at += 1;
+            while graphemes.next_if(|&c| c == class).is_some() {
+                at += 1;
+            }
+        }
+        at
+    }
+
+    // The same going back from before grapheme `from`, stopping at the start of the line
+    pub fn prev_token_boundary(&self, from: usize) -> usize {
+        let classes: Vec<TokenClass> = self.buf.graphemes(true).take(from).map(token_class).collect();
+        let mut at = classes.len();
+        while at > 0 && classes[at - 1] == TokenClass::Space {
+            at -= 1;
+        }
+        if at > 0 {
+            let class = classes[at - 1];
+            while at > 0 && classes[at - 1] == class {
+                at -= 1;
+            }
+        }
+        at
+    }
+
+    pub fn grapheme_at(&self, n: usize) -> Option<&str> {
+        self.buf.graphemes(true).nth(n)
+    }
+
+    pub fn display_width_up_to(&self, n: usize, tab_stop: usize) -> usize {
+        self.buf.graphemes(true).take(n).fold(0, |rx, g| rx + advance(g, rx, tab_stop))
+    }
+
+    pub fn rx_from_cx(&self, cx: usize, tab_stop: usize) -> usize {
+        self.display_width_up_to(cx, tab_stop)
+    }
+
+    // Index of the grapheme drawn at render column `rx`
+    pub fn cx_from_rx(&self, rx: usize, tab_stop: usize) -> usize {
+        let mut cur_rx = 0;
+        for (cx, g) in self.buf.graphemes(true).enumerate() {
+            cur_rx += advance(g, cur_rx, tab_stop);
+            if cur_rx > rx {
+                return cx;
+            }
+        }
+        self.grapheme_count()
+    }
+
+    // Index in the characters of `render` of the first match of `query` at or after character
+    // `from`. UTF-8 never matches in the middle of a char, so the bytes can be searched
+    #[cfg(test)]
+    pub fn search(&self, query: &str, from: usize) -> Option<usize> {
+        let start = match self.render.char_indices().nth(from) {
+            Some((idx, _)) => idx,
+            None if from == self.render.chars().count() => self.render.len(),
+            None => return None,
+        };
+        let idx = find_from(&self.render, query, start)?;
+        Some(from + self.render[start..idx].chars().count())
+    }
+
+    // Indexes in the characters of `render` of every non-overlapping match of `query`, counted
+    // as the search goes along so that the row is walked once
+    pub fn matches(&self, query: &str) -> Vec<usize> {
+        let mut found = vec![];
+        if query.is_empty() {
+            return found;
+        }
+        let (step, mut byte, mut col) = (query.chars().count(), 0, 0);
+        while let Some(idx) = find_from(&self.render, query, byte) {
+            col += self.render[byte..idx].chars().count();
+            found.push(col);
+            byte = idx + query.len();
+            col += step;
+        }
+        found
+    }
+
+    // Render columns at which each screen row starts when the row is wrapped at `width`. The
+    // cell after the last character counts too, so that the cursor always has room
+    pub fn wrap_starts(&self, width: usize) -> Vec<usize> {
+        let mut starts = vec![0];
+        if width == 0 {
+            return starts;
+        }
+        let mut col = 0;
+        for w in self.render.graphemes(true).map(|g| g.width()).chain(std::iter::once(1)) {
+            let start = starts[starts.len() - 1];
+            if col > start && col + w > start + width {
+                starts.push(col);
+            }
+            col += w;
+        }
+        starts
+    }
+
+    pub fn insert_char(&mut self, at: usize, c: char) {
+        let idx = self.byte_offset_of_grapheme(at);
+        self.buf.insert(idx, c);
+    }
+
+    pub fn insert_str<S: AsRef<str>>(&mut self, at: usize, s: S) {
+        let idx = self.byte_offset_of_grapheme(at);
+        self.buf.insert_str(idx, s.as_ref());
+    }
+
+    // Replaces graphemes `from..to` in one step, rather than a grapheme at a time
+    pub fn replace_range(&mut self, from: usize, to: usize, replacement: &str) {
+        let (start, end) = (self.byte_offset_of_grapheme(from), self.byte_offset_of_grapheme(to));
+        self.buf.replace_range(start..end, replacement);
+    }
+
+    pub fn delete_range(&mut self, from: usize, to: usize) {
+        self.replace_range(from, to, "");
+    }
+
+    // Search matches can end inside a grapheme, so find and replace goes by the bytes of `old`
+    pub fn replace_str(&mut self, at: usize, old: &str, new: &str) {
+        let idx = self.byte_offset_of_grapheme(at);
+        self.buf.replace_range(idx..idx + old.len(), new);
+    }
+
+    // Removes the char at byte `idx`, which may be only a part of a grapheme
+    pub fn remove_char(&mut self, idx: usize) {
+        self.buf.remove(idx);
+    }
+
+    pub fn delete_char(&mut self, at: usize) {
+        let start = self.byte_offset_of_grapheme(at);
+        if start < self.buf.len() {
+            let end = self.byte_offset_of_grapheme(at + 1);
+            self.buf.replace_range(start..end, "");
+        }
+    }
+
+    // Moves the grapheme before `at` after the one at `at`
+    pub fn transpose_at(&mut self, at: usize) {
+        let (start, mid, end) = (
+            self.byte_offset_of_grapheme(at - 1),
+            self.byte_offset_of_grapheme(at),
+            self.byte_offset_of_grapheme(at + 1),
+        );
+        let swapped = format!("{}{}", &self.buf[mid..end], &self.buf[start..mid]);
+        self.buf.replace_range(start..end, &swapped);
+    }
+
+    pub fn append<S: AsRef<str>>(&mut self, s: S) {
+        self.buf.push_str(s.as_ref());
+    }
+
+    // The text before and from the n-th grapheme
+    pub fn split_at_grapheme(&self, n: usize) -> (String, String) {
+        let (head, tail) = self.buf.split_at(self.byte_offset_of_grapheme(n));
+        (head.to_string(), tail.to_string())
+    }
+
+    pub fn truncate(&mut self, at: usize) {
+        let idx = self.byte_offset_of_grapheme(at);
+        if idx < self.buf.len() {
+            self.buf.truncate(idx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pseudo-random numbers from a fixed linear congruential sequence, so that failures can be replayed
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % bound
+        }
+
+        fn string(&mut self, alphabet: &[char], max_len: usize) -> String {
+            let len = self.next(max_len + 1);
+            (0..len).map(|_| alphabet[self.next(alphabet.len())]).collect()
+        }
+    }
+
+    fn rendered(line: &str) -> Row {
+        let mut row = Row::new(line);
+        row.update_render(4);
+        row
+    }
+
+    #[test]
+    fn search_finds_the_first_match_from_a_character() {
+        let alphabet = ['a', 'b', '\u{e9}', '\u{4f60}', ' ', '\t'];
+        let mut rng = Lcg(1);
+        for _ in 0..3000 {
+            let row = rendered(&rng.string(&alphabet, 24));
+            let render: Vec<char> = row.render.chars().collect();
+            let from = rng.next(render.len() + 1);
+            let query: String = if rng.next(2) == 0 && from < render.len() {
+                // A part of the row, so that long queries are found too
+                let start = from + rng.next(render.len() - from);
+                render[start..(start + rng.next(8)).min(render.len())].iter().collect()
+            } else {
+                rng.string(&alphabet, 7)
+            };
+            let query_chars: Vec<char> = query.chars().collect();
+            let expected = (from..=render.len().saturating_sub(query_chars.len()))
+                .find(|&x| render[x..].starts_with(&query_chars));
+            let found = row.search(&query, from);
+            assert_eq!(found, expected, "{:?} in {:?} from {}", query, row.render, from);
+            if let Some(x) = found {
+                assert!(x <= render.len() - query_chars.len());
+            }
+            if query.is_empty() {
+                assert_eq!(found, Some(from));
+            }
+        }
+    }
+
+    #[test]
+    fn search_past_the_end_finds_nothing() {
+        let row = rendered("abc");
+        assert_eq!(row.search("", 3), Some(3));
+        assert_eq!(row.search("", 4), None);
+        assert_eq!(row.search("c", 4), None);
+    }
+
+    #[test]
+    fn horspool_skips_by_the_last_byte_of_the_window() {
+        assert_eq!(horspool(b"abcabdabcabcabe", b"abcabe"), Some(9));
+        assert_eq!(horspool(b"aaaaa", b"aaaaaa"), None);
+        assert_eq!(horspool(b"xxhellox", b"hello"), Some(2));
+    }
+
+    #[test]
+    fn range_edits_go_by_graphemes() {
+        let mut row = Row::new("a\u{4f60}e\u{301}\u{1f600}b");
+        row.delete_range(1, 3);
+        assert_eq!(row.buf, "a\u{1f600}b");
+        row.replace_range(1, 2, "xyz");
+        assert_eq!(row.buf, "axyzb");
+        row.replace_range(4, 4, "!");
+        assert_eq!(row.buf, "axyz!b");
+        // Past the end stops at the end
+        row.delete_range(3, 10);
+        assert_eq!(row.buf, "axy");
+    }
+
+    #[test]
+    fn cx_from_rx_inverts_rx_from_cx() {
+        let alphabet = ['a', ' ', '\t', '\u{e9}', '\u{301}', '\u{4f60}', '\u{1f600}'];
+        let mut rng = Lcg(51);
+        for _ in 0..2000 {
+            let row = Row::new(rng.string(&alphabet, 16));
+            let tab_stop = 1 + rng.next(8);
+            let count = row.grapheme_count();
+            let width = row.rx_from_cx(count, tab_stop);
+            // Each grapheme starts at the column it is found at
+            for cx in 0..count {
+                let rx = row.rx_from_cx(cx, tab_stop);
+                if row.rx_from_cx(cx + 1, tab_stop) > rx {
+                    assert_eq!(row.cx_from_rx(rx, tab_stop), cx, "{:?} at {}", row.buf, rx);
+                }
+            }
+            // Any other column is inside the grapheme drawn over it, and columns past the end are
+            // after the last grapheme
+            for rx in 0..width + 3 {
+                let cx = row.cx_from_rx(rx, tab_stop);
+                if rx < width {
+                    assert!(row.rx_from_cx(cx, tab_stop) <= rx && rx < row.rx_from_cx(cx + 1, tab_stop));
+                } else {
+                    assert_eq!(cx, count);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        // Text, then the column each grapheme starts at and the width of the row
+        let cases: [(&str, &[usize], usize); 6] = [
+            ("\u{4f60}\u{597d}a", &[0, 2, 4], 5),
+            ("\u{3053}\u{3093}\u{306b}\u{3061}\u{306f}", &[0, 2, 4, 6, 8], 10),
+            // Half-width katakana take one column
+            ("\u{ff76}\u{ff80}\u{30ab}", &[0, 1, 2], 4),
+            ("\u{d55c}\u{ad6d}\u{c5b4}", &[0, 2, 4], 6),
+            // A Hangul syllable written as its three jamo is one grapheme
+            ("\u{1112}\u{1161}\u{11ab}x", &[0, 2], 3),
+            ("a\u{1f600}b\u{1f44d}\u{1f3fd}", &[0, 1, 3, 4], 6),
+        ];
+        for (text, starts, width) in cases {
+            let row = rendered(text);
+            assert_eq!(row.grapheme_count(), starts.len(), "{:?}", text);
+            for (cx, &rx) in starts.iter().enumerate() {
+                assert_eq!(row.rx_from_cx(cx, 4), rx, "{:?} at {}", text, cx);
+                assert_eq!(row.cx_from_rx(rx, 4), cx, "{:?} at {}", text, rx);
+            }
+            assert_eq!(row.rx_from_cx(starts.len(), 4), width, "{:?}", text);
+            // Nothing is added to the rendered text for the second column
+            assert_eq!(row.render, text);
+            assert_eq!(row.render.width(), width);
+        }
+    }
+
+    #[test]
+    fn wide_characters_wrap_whole() {
+        let row = rendered("ab\u{4f60}\u{597d}c");
+        // The second wide character would take the columns 4 and 5 of a 5 column screen
+        assert_eq!(row.wrap_starts(5), [0, 4]);
+        // And on a 3 column screen neither fits after the one before it. The cursor after the c
+        // takes a row of its own
+        assert_eq!(row.wrap_starts(3), [0, 2, 4, 7]);
+    }
+
+    #[test]
+    fn split_at_grapheme_keeps_clusters_whole() {
+        // A family emoji joined from four, e with a combining acute, and two CJK characters
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}";
+        let line = format!("a{}e\u{301}\u{4f60}\u{597d}z", family);
+        let row = Row::new(&line);
+        let cases = [
+            (0, ""),
+            (1, "a"),
+            (2, "a\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}"),
+            (3, "a\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}e\u{301}"),
+            (4, "a\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}e\u{301}\u{4f60}"),
+            (5, line.trim_end_matches('z')),
+            (6, line.as_str()),
+            (10, line.as_str()),
+        ];
+        for (n, head) in cases {
+            let (before, after) = row.split_at_grapheme(n);
+            assert_eq!((before.as_str(), after.as_str()), (head, &line[head.len()..]), "split at {}", n);
+            let mut truncated = Row::new(&line);
+            truncated.truncate(n);
+            assert_eq!(truncated.buf, head, "truncated at {}", n);
+        }
+    }
+
+    #[test]
+    fn token_boundaries_stop_where_the_character_class_changes() {
+        // (line, from, next boundary, previous boundary)
+        let cases = [
+            ("foo_bar1 baz", 0, 8, 0),
+            ("foo_bar1 baz", 8, 12, 0),
+            ("foo_bar1 baz", 12, 12, 9),
+            ("a  \t  b", 1, 7, 0),
+            ("a  \t  b", 6, 7, 0),
+            ("x+=-y", 1, 4, 0),
+            ("x+=-y", 4, 5, 1),
+            ("(\u{4f60}\u{597d})", 0, 1, 0),
+            ("(\u{4f60}\u{597d})", 1, 3, 0),
+            ("(\u{4f60}\u{597d})", 3, 4, 1),
+            ("caf\u{e9}! ok", 0, 4, 0),
+            ("caf\u{e9}! ok", 5, 8, 4),
+            ("e\u{301}e\u{301}.", 0, 2, 0),
+            ("e\u{301}e\u{301}.", 3, 3, 2),
+            ("   ", 0, 3, 0),
+            ("", 0, 0, 0),
+        ];
+        for (line, from, next, prev) in cases {
+            let row = Row::new(line);
+            assert_eq!(row.next_token_boundary(from), next, "next from {} in {:?}", from, line);
+            assert_eq!(row.prev_token_boundary(from), prev, "previous from {} in {:?}", from, line);
+        }
+    }
+}
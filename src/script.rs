@@ -0,0 +1,133 @@
+use crate::{Editor, InputSeq};
+use std::io::{self, Write};
+
+// One line of a script for --headless. Blank lines and lines starting with # are skipped
+#[derive(Clone, PartialEq, Debug)]
+pub enum ScriptCommand {
+    // 1-based line and column
+    Goto(usize, usize),
+    // Text to put at the cursor, which may hold line breaks
+    Insert(String),
+    // Number of lines to delete from the cursor's
+    Delete(usize),
+    Save,
+    Quit,
+}
+
+fn parse_count(name: &str, value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid number for {}: {}", name, value)),
+    }
+}
+
+// A string in double quotes, with \n, \t, \" and \\ inside
+fn parse_string(arg: &str) -> Result<String, String> {
+    let inner = arg
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|_| arg.len() >= 2)
+        .ok_or(format!("expected a string in double quotes, found {}", arg))?;
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some('"') => text.push('"'),
+            Some('\\') => text.push('\\'),
+            Some(c) => return Err(format!("unknown escape: \\{}", c)),
+            None => return Err("string ends with \\".to_string()),
+        }
+    }
+    Ok(text)
+}
+
+impl ScriptCommand {
+    pub fn parse(line: &str) -> Result<Option<ScriptCommand>, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let (name, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arg = arg.trim();
+        let args: Vec<&str> = arg.split_whitespace().collect();
+        let command = match (name, args.as_slice()) {
+            ("goto", [line]) => ScriptCommand::Goto(parse_count(name, line)?, 1),
+            ("goto", [line, col]) => ScriptCommand::Goto(parse_count(name, line)?, parse_count(name, col)?),
+            ("goto", _) => return Err("goto takes a line and an optional column".to_string()),
+            ("insert", _) => ScriptCommand::Insert(parse_string(arg)?),
+            ("delete", []) => ScriptCommand::Delete(1),
+            ("delete", [n]) => ScriptCommand::Delete(parse_count(name, n)?),
+            ("save", []) => ScriptCommand::Save,
+            ("quit", []) => ScriptCommand::Quit,
+            ("delete" | "save" | "quit", _) => return Err(format!("too many arguments for {}", name)),
+            _ => return Err(format!("unknown command: {}", name)),
+        };
+        Ok(Some(command))
+    }
+
+    // Every command of a script, or the first error with its line number
+    pub fn parse_script(text: &str) -> Result<Vec<ScriptCommand>, String> {
+        let mut commands = vec![];
+        for (i, line) in text.lines().enumerate() {
+            match ScriptCommand::parse(line) {
+                Ok(Some(command)) => commands.push(command),
+                Ok(None) => {}
+                Err(msg) => return Err(format!("line {}: {}", i + 1, msg)),
+            }
+        }
+        Ok(commands)
+    }
+}
+
+impl<I: Iterator<Item = io::Result<InputSeq>>> Editor<I> {
+    pub fn apply_script_command(&mut self, command: ScriptCommand) -> io::Result<()> {
+        let changes_file = matches!(command, ScriptCommand::Insert(_) | ScriptCommand::Delete(_) | ScriptCommand::Save);
+        if changes_file && self.buffer().settings.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "file is read-only"));
+        }
+        match command {
+            ScriptCommand::Goto(line, col) => self.jump_to(line, col),
+            ScriptCommand::Insert(text) => {
+                let lines: Vec<String> = text.split('\n').map(String::from).collect();
+                self.buffer_mut().paste(&lines);
+            }
+            ScriptCommand::Delete(n) => self.buffer_mut().delete_lines(n),
+            ScriptCommand::Save => {
+                self.buffer_mut().write_file()?;
+            }
+            // Ends the script, which the caller checks for
+            ScriptCommand::Quit => {}
+        }
+        Ok(())
+    }
+
+    // Applies the commands to the current buffer up to the first quit
+    pub fn run_script(&mut self, commands: Vec<ScriptCommand>) -> io::Result<()> {
+        self.buffer_mut().load_all()?;
+        for command in commands {
+            if command == ScriptCommand::Quit {
+                break;
+            }
+            self.apply_script_command(command)?;
+        }
+        Ok(())
+    }
+
+    // Parses the text of a script and applies it, for --pipe
+    pub fn run_pipeline(&mut self, script: &str) -> io::Result<()> {
+        let commands = ScriptCommand::parse_script(script).map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, msg))?;
+        self.run_script(commands)
+    }
+
+    // The current buffer as it would be saved
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        self.buffer().encode_rows(&mut w)?;
+        w.flush()
+    }
+}
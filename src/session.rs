@@ -0,0 +1,126 @@
+use crate::buffer::EditorBuffer;
+use crate::{Editor, InputSeq, StatusMessage};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Buffers with a file, the buffer to show and the display toggles, written as key=value lines.
+// Each buffer's lines follow a [buffer] header. Settings from the config files aren't saved,
+// since they are read again at startup
+pub struct Session;
+
+struct SavedBuffer {
+    path: PathBuf,
+    cx: usize,
+    cy: usize,
+    bookmarks: Vec<usize>,
+}
+
+fn invalid(line: usize, message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, message))
+}
+
+fn parse_number(line: usize, key: &str, value: &str) -> io::Result<usize> {
+    value.parse().map_err(|_| invalid(line, format!("invalid value for {}: {}", key, value)))
+}
+
+fn parse_bool(line: usize, key: &str, value: &str) -> io::Result<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(invalid(line, format!("invalid value for {}: {}", key, value))),
+    }
+}
+
+// A path with a line break couldn't be read back
+fn saved_path(buffer: &EditorBuffer) -> Option<&Path> {
+    let file = buffer.file.as_ref()?;
+    if file.display.contains('\n') {
+        None
+    } else {
+        Some(&file.path)
+    }
+}
+
+impl Session {
+    pub fn save<I: Iterator<Item = io::Result<InputSeq>>>(editor: &Editor<I>, path: &Path) -> io::Result<()> {
+        // Buffers without a file are left out, so the index counts only those before it
+        let current = editor.buffers[..editor.current].iter().filter_map(saved_path).count();
+        let mut out = String::new();
+        let _ = writeln!(out, "current={}", current);
+        let _ = writeln!(out, "show_line_numbers={}", editor.show_line_numbers);
+        let _ = writeln!(out, "show_stats={}", editor.show_stats);
+        for buffer in &editor.buffers {
+            if let Some(path) = saved_path(buffer) {
+                let _ = writeln!(out, "\n[buffer]");
+                let _ = writeln!(out, "path={}", path.display());
+                let _ = writeln!(out, "cx={}", buffer.cx);
+                let _ = writeln!(out, "cy={}", buffer.cy);
+                if !buffer.bookmarks.is_empty() {
+                    let rows: Vec<String> = buffer.bookmarks.iter().map(|y| y.to_string()).collect();
+                    let _ = writeln!(out, "bookmarks={}", rows.join(","));
+                }
+            }
+        }
+        fs::write(path, out)
+    }
+
+    pub fn restore<I: Iterator<Item = io::Result<InputSeq>>>(editor: &mut Editor<I>, path: &Path) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        let mut current = 0;
+        let mut buffers: Vec<SavedBuffer> = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if line.trim() == "[buffer]" {
+                buffers.push(SavedBuffer { path: PathBuf::new(), cx: 0, cy: 0, bookmarks: vec![] });
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| invalid(line_no, "expected key=value".to_string()))?;
+            match (buffers.last_mut(), key.trim()) {
+                (None, "current") => current = parse_number(line_no, key, value)?,
+                (None, "show_line_numbers") => editor.show_line_numbers = parse_bool(line_no, key, value)?,
+                (None, "show_stats") => editor.show_stats = parse_bool(line_no, key, value)?,
+                // The path is taken as written, spaces included
+                (Some(buffer), "path") => buffer.path = PathBuf::from(value),
+                (Some(buffer), "cx") => buffer.cx = parse_number(line_no, key, value)?,
+                (Some(buffer), "cy") => buffer.cy = parse_number(line_no, key, value)?,
+                (Some(buffer), "bookmarks") => {
+                    for row in value.split(',') {
+                        buffer.bookmarks.push(parse_number(line_no, key, row)?);
+                    }
+                }
+                (_, key) => return Err(invalid(line_no, format!("unknown key: {}", key))),
+            }
+        }
+
+        let mut missing = vec![];
+        // Where each buffer ended up, since the first may take the place of an untouched one
+        let mut restored = vec![];
+        for saved in buffers.iter().filter(|b| !b.path.as_os_str().is_empty()) {
+            if saved.path.exists() {
+                editor.open_file(&saved.path)?;
+                editor.jump_to(saved.cy + 1, saved.cx + 1);
+                editor.buffer_mut().bookmarks.extend(&saved.bookmarks);
+            } else {
+                let mut buffer = EditorBuffer::new(editor.settings);
+                buffer.set_file(&saved.path);
+                editor.add_buffer(buffer);
+                missing.push(saved.path.display().to_string());
+            }
+            restored.push(editor.current);
+        }
+        if let Some(&index) = restored.get(current).or(restored.last()) {
+            editor.current = index;
+        }
+        editor.message = if missing.is_empty() {
+            StatusMessage::new(format!("Restored session from {}", path.display()))
+        } else {
+            StatusMessage::new(format!("[File not found] {}", missing.join(", ")))
+        };
+        Ok(())
+    }
+}
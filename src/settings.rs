@@ -0,0 +1,253 @@
+use crate::clipboard::ClipboardProvider;
+use crate::highlight::ColorScheme;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Settings {
+    pub tab_stop: usize,
+    // Insert spaces instead of a tab character
+    pub expand_tabs: bool,
+    // Rows kept visible above and below the cursor
+    pub scroll_off: usize,
+    // Columns kept visible to the left and right of the cursor
+    pub side_scroll_off: usize,
+    pub show_line_numbers: bool,
+    pub highlight_current_line: bool,
+    // Count lines, words and characters in the status bar instead of showing the cursor position
+    pub show_stats: bool,
+    // Scrollbar in the rightmost column
+    pub show_scrollbar: bool,
+    // Shading left of the scrollbar for how full each part of a file over 10,000 lines is
+    pub show_minimap: bool,
+    // Background of the current line, from the 256-color palette
+    pub current_line_color: u8,
+    // Characters past this column are drawn on a dark red background
+    pub ruler_column: Option<usize>,
+    // Draw spaces and tabs at the end of a line on a red background
+    pub show_trailing_whitespace: bool,
+    // Continue long lines on the next screen row instead of scrolling sideways
+    pub word_wrap: bool,
+    // Show the file name in the terminal title
+    pub set_title: bool,
+    // Key bindings and the version in the middle of an empty screen when no file was given
+    pub show_welcome: bool,
+    // Ask the terminal to report mouse clicks
+    pub mouse_support: bool,
+    // Lines moved per notch of the mouse wheel
+    pub mouse_scroll_lines: usize,
+    pub color_scheme: ColorScheme,
+    pub clipboard_provider: ClipboardProvider,
+    // Start a language server for the file and show its diagnostics
+    pub lsp: bool,
+    // Write lines ending in CR LF
+    pub crlf: bool,
+    // Removed from every line when saving
+    pub trim_trailing_whitespace: bool,
+    pub insert_final_newline: bool,
+    // Files are saved once no text was typed for this long
+    pub auto_save_interval: Option<Duration>,
+    // Only set from the command line
+    pub read_only: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            tab_stop: 8,
+            expand_tabs: false,
+            scroll_off: 5,
+            side_scroll_off: 3,
+            show_line_numbers: true,
+            highlight_current_line: true,
+            show_stats: false,
+            show_scrollbar: true,
+            show_minimap: false,
+            current_line_color: 236,
+            ruler_column: None,
+            show_trailing_whitespace: false,
+            word_wrap: false,
+            set_title: true,
+            show_welcome: true,
+            mouse_support: true,
+            mouse_scroll_lines: 3,
+            color_scheme: ColorScheme::Default,
+            clipboard_provider: ClipboardProvider::Auto,
+            lsp: false,
+            crlf: false,
+            trim_trailing_whitespace: false,
+            insert_final_newline: true,
+            auto_save_interval: None,
+            read_only: false,
+        }
+    }
+}
+
+fn parse_value<T, F: FnOnce(&str) -> Option<T>>(key: &str, value: &str, parse: F) -> Result<T, String> {
+    parse(value).ok_or_else(|| format!("invalid value for {}: {}", key, value))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_tab_stop(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|&n| n > 0)
+}
+
+// Seconds, with 0 turning auto-save off
+fn parse_auto_save(value: &str) -> Option<Option<Duration>> {
+    value.parse().ok().map(|n| Some(Duration::from_secs(n)).filter(|_| n > 0))
+}
+
+// 0 turns the ruler off
+fn parse_ruler(value: &str) -> Option<Option<usize>> {
+    value.parse().ok().map(|n| Some(n).filter(|&n| n > 0))
+}
+
+// What a file of `key = value` lines sets
+#[derive(Default, Debug)]
+pub struct SettingsFile {
+    pub settings: Settings,
+    // Keys the file gave a value, which are all that merge takes from it
+    pub keys: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl Settings {
+    // Reads `key = value` lines, with `#` starting a comment. Unknown keys and bad values are
+    // skipped and returned as warnings
+    pub fn load_from_file(path: &Path) -> io::Result<SettingsFile> {
+        let file = fs::File::open(path)?;
+        let mut loaded = SettingsFile::default();
+        for (i, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => {
+                    loaded.warnings.push(format!("{}:{}: expected key = value", path.display(), i + 1));
+                    continue;
+                }
+            };
+            match loaded.settings.set(key, value) {
+                Ok(()) => loaded.keys.push(key.to_string()),
+                Err(msg) => loaded.warnings.push(format!("{}:{}: {}", path.display(), i + 1, msg)),
+            }
+        }
+        Ok(loaded)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "tab_stop" => self.tab_stop = parse_value(key, value, parse_tab_stop)?,
+            "expand_tabs" => self.expand_tabs = parse_value(key, value, parse_bool)?,
+            "scroll_off" => self.scroll_off = parse_value(key, value, |v| v.parse().ok())?,
+            "side_scroll_off" => self.side_scroll_off = parse_value(key, value, |v| v.parse().ok())?,
+            "show_line_numbers" => self.show_line_numbers = parse_value(key, value, parse_bool)?,
+            "highlight_current_line" => self.highlight_current_line = parse_value(key, value, parse_bool)?,
+            "show_stats" => self.show_stats = parse_value(key, value, parse_bool)?,
+            "show_scrollbar" => self.show_scrollbar = parse_value(key, value, parse_bool)?,
+            "show_minimap" => self.show_minimap = parse_value(key, value, parse_bool)?,
+            "current_line_color" => self.current_line_color = parse_value(key, value, |v| v.parse().ok())?,
+            "ruler" => self.ruler_column = parse_value(key, value, parse_ruler)?,
+            "show_trailing_whitespace" => self.show_trailing_whitespace = parse_value(key, value, parse_bool)?,
+            "word_wrap" => self.word_wrap = parse_value(key, value, parse_bool)?,
+            "set_title" => self.set_title = parse_value(key, value, parse_bool)?,
+            "show_welcome" => self.show_welcome = parse_value(key, value, parse_bool)?,
+            "mouse_support" => self.mouse_support = parse_value(key, value, parse_bool)?,
+            "mouse_scroll_lines" => self.mouse_scroll_lines = parse_value(key, value, |v| v.parse().ok())?,
+            "color_scheme" => self.color_scheme = parse_value(key, value, ColorScheme::from_name)?,
+            "crlf" => self.crlf = parse_value(key, value, parse_bool)?,
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = parse_value(key, value, parse_bool)?,
+            "insert_final_newline" => self.insert_final_newline = parse_value(key, value, parse_bool)?,
+            "clipboard" => self.clipboard_provider = parse_value(key, value, ClipboardProvider::from_name)?,
+            "lsp" => self.lsp = parse_value(key, value, parse_bool)?,
+            "auto_save" => self.auto_save_interval = parse_value(key, value, parse_auto_save)?,
+            _ => return Err(format!("unknown setting: {}", key)),
+        }
+        Ok(())
+    }
+
+    // Takes the values of `other` for `keys`, the keys its file set
+    pub fn merge(&mut self, other: Settings, keys: &[String]) {
+        macro_rules! merge_fields {
+            ($($field:ident: $key:literal),*) => {
+                $(if keys.iter().any(|key| key == $key) {
+                    self.$field = other.$field;
+                })*
+            };
+        }
+        merge_fields!(
+            tab_stop: "tab_stop",
+            expand_tabs: "expand_tabs",
+            scroll_off: "scroll_off",
+            side_scroll_off: "side_scroll_off",
+            show_line_numbers: "show_line_numbers",
+            highlight_current_line: "highlight_current_line",
+            show_stats: "show_stats",
+            show_scrollbar: "show_scrollbar",
+            show_minimap: "show_minimap",
+            current_line_color: "current_line_color",
+            ruler_column: "ruler",
+            show_trailing_whitespace: "show_trailing_whitespace",
+            word_wrap: "word_wrap",
+            set_title: "set_title",
+            show_welcome: "show_welcome",
+            mouse_support: "mouse_support",
+            mouse_scroll_lines: "mouse_scroll_lines",
+            color_scheme: "color_scheme",
+            clipboard_provider: "clipboard",
+            lsp: "lsp",
+            crlf: "crlf",
+            trim_trailing_whitespace: "trim_trailing_whitespace",
+            insert_final_newline: "insert_final_newline",
+            auto_save_interval: "auto_save"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, text: &str) -> SettingsFile {
+        let path = std::env::temp_dir().join(format!("rustitor-{}-{}", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        let loaded = Settings::load_from_file(&path);
+        fs::remove_file(&path).unwrap();
+        loaded.unwrap()
+    }
+
+    #[test]
+    fn local_file_can_set_defaults_back() {
+        let home = load("home", "tab_stop = 4\nshow_line_numbers = false\nruler = 80\n");
+        let local = load("local", "tab_stop = 8\nshow_line_numbers = true # as before\nruler = 0\n");
+        assert_eq!(local.keys, ["tab_stop", "show_line_numbers", "ruler"]);
+        let mut settings = Settings::default();
+        settings.merge(home.settings, &home.keys);
+        assert_eq!((settings.tab_stop, settings.show_line_numbers, settings.ruler_column), (4, false, Some(80)));
+        settings.merge(local.settings, &local.keys);
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn keys_left_out_are_not_merged() {
+        let home = load("home-scroll", "scroll_off = 2\n");
+        let local = load("local-scroll", "expand_tabs = true\nbogus = 1\n");
+        assert_eq!(local.warnings.len(), 1);
+        let mut settings = Settings::default();
+        settings.merge(home.settings, &home.keys);
+        settings.merge(local.settings, &local.keys);
+        assert_eq!((settings.scroll_off, settings.expand_tabs), (2, true));
+    }
+}